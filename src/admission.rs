@@ -0,0 +1,286 @@
+/*
+ * Copyright (c) Kia Shakiba
+ *
+ * This source code is licensed under the GNU AGPLv3 license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::{
+	fmt,
+	str::FromStr,
+	hash::{DefaultHasher, Hash, Hasher},
+	sync::atomic::{AtomicU8, AtomicU64, Ordering},
+};
+
+use crate::error::ServerError;
+
+const SKETCH_DEPTH: usize = 4;
+const SKETCH_WIDTH: usize = 1 << 20;
+
+const DOORKEEPER_BITS: usize = 1 << 23;
+
+const MAX_COUNT: u8 = 15;
+
+// the number of recorded accesses after which every count is halved and
+// the doorkeeper is cleared, so that the filter favours recent frequency
+const RESET_SIZE: u64 = 10 * SKETCH_WIDTH as u64;
+
+// an object is admitted once its key has been accessed at least this many
+// times, so keys which are only ever accessed once are never admitted
+const ADMISSION_FREQUENCY: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AdmissionPolicy {
+	None,
+	TinyLfu,
+}
+
+/// Decides whether a new object is admitted into the cache, based on how
+/// often its key has recently been accessed. Accesses are counted by a
+/// count-min sketch which sits behind a doorkeeper Bloom filter, so a
+/// key's first access only sets its doorkeeper bits and one-hit wonders
+/// never reach the sketch.
+pub struct Admission {
+	policy: AdmissionPolicy,
+
+	sketch: Box<[AtomicU8]>,
+	doorkeeper: Box<[AtomicU64]>,
+	num_records: AtomicU64,
+
+	admitted: AtomicU64,
+	rejected: AtomicU64,
+}
+
+impl Admission {
+	pub fn new(policy: AdmissionPolicy) -> Self {
+		// the filter is only allocated if it is used
+		let (sketch_size, doorkeeper_size) = match policy {
+			AdmissionPolicy::None => (0, 0),
+			AdmissionPolicy::TinyLfu => (SKETCH_DEPTH * SKETCH_WIDTH, DOORKEEPER_BITS / 64),
+		};
+
+		Admission {
+			policy,
+
+			sketch: (0..sketch_size).map(|_| AtomicU8::default()).collect(),
+			doorkeeper: (0..doorkeeper_size).map(|_| AtomicU64::default()).collect(),
+			num_records: AtomicU64::default(),
+
+			admitted: AtomicU64::default(),
+			rejected: AtomicU64::default(),
+		}
+	}
+
+	pub fn is_enabled(&self) -> bool {
+		self.policy != AdmissionPolicy::None
+	}
+
+	pub fn admitted(&self) -> u64 {
+		self.admitted.load(Ordering::Relaxed)
+	}
+
+	pub fn rejected(&self) -> u64 {
+		self.rejected.load(Ordering::Relaxed)
+	}
+
+	/// Records an access to the key.
+	pub fn record(&self, key: &[u8]) {
+		if !self.is_enabled() {
+			return;
+		}
+
+		let key_hash = hash_key(key);
+
+		if self.doorkeeper_insert(key_hash) {
+			for index in self.sketch_indexes(key_hash) {
+				let _ = self.sketch[index].fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+					(count < MAX_COUNT).then_some(count + 1)
+				});
+			}
+		}
+
+		if self.num_records.fetch_add(1, Ordering::Relaxed) + 1 >= RESET_SIZE {
+			self.reset();
+		}
+	}
+
+	/// Returns whether a new object with the key should be admitted into
+	/// the cache, and records the write as an access to the key. The
+	/// decision is made before the write is recorded, so an object is not
+	/// admitted by the write alone.
+	pub fn admit(&self, key: &[u8]) -> bool {
+		if !self.is_enabled() {
+			return true;
+		}
+
+		let is_admitted = self.frequency(hash_key(key)) >= ADMISSION_FREQUENCY;
+
+		match is_admitted {
+			true => self.admitted.fetch_add(1, Ordering::Relaxed),
+			false => self.rejected.fetch_add(1, Ordering::Relaxed),
+		};
+
+		self.record(key);
+
+		is_admitted
+	}
+
+	fn frequency(&self, key_hash: u64) -> u32 {
+		if !self.doorkeeper_contains(key_hash) {
+			return 0;
+		}
+
+		let count = self.sketch_indexes(key_hash)
+			.map(|index| self.sketch[index].load(Ordering::Relaxed))
+			.min()
+			.unwrap_or(0);
+
+		count as u32 + 1
+	}
+
+	/// Sets the key's doorkeeper bits and returns whether they were all
+	/// already set.
+	fn doorkeeper_insert(&self, key_hash: u64) -> bool {
+		let mut is_contained = true;
+
+		for bit in doorkeeper_bits(key_hash) {
+			let mask = 1 << (bit % 64);
+			let previous = self.doorkeeper[bit / 64].fetch_or(mask, Ordering::Relaxed);
+
+			is_contained &= previous & mask != 0;
+		}
+
+		is_contained
+	}
+
+	fn doorkeeper_contains(&self, key_hash: u64) -> bool {
+		doorkeeper_bits(key_hash).all(|bit| {
+			self.doorkeeper[bit / 64].load(Ordering::Relaxed) & (1 << (bit % 64)) != 0
+		})
+	}
+
+	fn sketch_indexes(&self, key_hash: u64) -> impl Iterator<Item = usize> {
+		let (h1, h2) = split_hash(key_hash);
+
+		(0..SKETCH_DEPTH).map(move |row| {
+			let column = h1.wrapping_add((row as u64).wrapping_mul(h2)) as usize % SKETCH_WIDTH;
+			row * SKETCH_WIDTH + column
+		})
+	}
+
+	/// Halves every count and clears the doorkeeper. Accesses recorded
+	/// concurrently with a reset may be partially lost, which only affects
+	/// the accuracy of the estimates.
+	fn reset(&self) {
+		self.num_records.store(0, Ordering::Relaxed);
+
+		for count in &self.sketch {
+			let _ = count.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+				Some(count / 2)
+			});
+		}
+
+		for word in &self.doorkeeper {
+			word.store(0, Ordering::Relaxed);
+		}
+	}
+}
+
+impl FromStr for AdmissionPolicy {
+	type Err = ServerError;
+
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		match value {
+			"none" => Ok(AdmissionPolicy::None),
+			"tinylfu" => Ok(AdmissionPolicy::TinyLfu),
+
+			_ => Err(ServerError::InvalidConfigParam("admission")),
+		}
+	}
+}
+
+impl fmt::Display for AdmissionPolicy {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			AdmissionPolicy::None => write!(f, "none"),
+			AdmissionPolicy::TinyLfu => write!(f, "tinylfu"),
+		}
+	}
+}
+
+fn doorkeeper_bits(key_hash: u64) -> impl Iterator<Item = usize> {
+	let (h1, h2) = split_hash(key_hash.rotate_left(17));
+
+	(0..3u64).map(move |index| {
+		h1.wrapping_add(index.wrapping_mul(h2)) as usize % DOORKEEPER_BITS
+	})
+}
+
+fn split_hash(key_hash: u64) -> (u64, u64) {
+	(key_hash & 0xffff_ffff, (key_hash >> 32) | 1)
+}
+
+fn hash_key(key: &[u8]) -> u64 {
+	let mut s = DefaultHasher::new();
+	key.hash(&mut s);
+
+	s.finish()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn it_admits_everything_without_a_policy() {
+		let admission = Admission::new(AdmissionPolicy::None);
+
+		assert!(!admission.is_enabled());
+		assert!(admission.admit(b"a"));
+		assert_eq!(admission.admitted(), 0);
+	}
+
+	#[test]
+	fn it_rejects_one_hit_wonders() {
+		let admission = Admission::new(AdmissionPolicy::TinyLfu);
+
+		assert!(!admission.admit(b"a"));
+		assert!(!admission.admit(b"b"));
+
+		assert_eq!(admission.rejected(), 2);
+		assert_eq!(admission.admitted(), 0);
+	}
+
+	#[test]
+	fn it_admits_keys_which_were_accessed_before() {
+		let admission = Admission::new(AdmissionPolicy::TinyLfu);
+
+		admission.record(b"a");
+		assert!(!admission.admit(b"a"));
+		assert!(admission.admit(b"a"));
+
+		assert_eq!(admission.rejected(), 1);
+		assert_eq!(admission.admitted(), 1);
+	}
+
+	#[test]
+	fn it_forgets_accesses_after_a_reset() {
+		let admission = Admission::new(AdmissionPolicy::TinyLfu);
+
+		admission.record(b"a");
+		admission.record(b"a");
+		admission.reset();
+
+		assert_eq!(admission.frequency(hash_key(b"a")), 0);
+		assert!(!admission.admit(b"a"));
+	}
+
+	#[test]
+	fn it_parses_policies() {
+		for policy in [AdmissionPolicy::None, AdmissionPolicy::TinyLfu] {
+			assert_eq!(policy.to_string().parse::<AdmissionPolicy>(), Ok(policy));
+		}
+
+		assert!("lfu".parse::<AdmissionPolicy>().is_err());
+	}
+}
@@ -0,0 +1,709 @@
+/*
+ * Copyright (c) Kia Shakiba
+ *
+ * This source code is licensed under the GNU AGPLv3 license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::{
+	thread,
+	process,
+	str::FromStr,
+	time::{Duration, Instant},
+	sync::atomic::{AtomicBool, Ordering},
+};
+
+use clap::{Parser, ValueEnum};
+use parse_size::parse_size;
+use paper_utils::stream::{Buffer, StreamError};
+
+use paper_server::{
+	command::Command,
+	expiry::Expiry,
+	node_client::{NodeClient, Field},
+};
+
+// the error code and cache error code of a response to a missing key
+const ERROR_CODE_CACHE: u8 = 0;
+const CACHE_ERROR_CODE_KEY_NOT_FOUND: u8 = 1;
+
+const PERCENTILES: &[f64] = &[50.0, 90.0, 99.0, 99.9];
+
+// the number of SETs which are pipelined while preloading the keys
+const PRELOAD_BATCH_SIZE: u64 = 100;
+
+/// Drives a paper-server with a configurable mix of GET, SET and DEL
+/// commands over several connections, and reports the throughput and
+/// latency percentiles. Runs with the same arguments and seed send the same
+/// commands, so that policies and server builds can be compared.
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+	/// Host of the server
+	#[arg(short = 'H', long, default_value = "127.0.0.1")]
+	host: String,
+
+	/// Port of the server
+	#[arg(short, long, default_value_t = 3145)]
+	port: u32,
+
+	/// Token with which the connections are authorized
+	#[arg(short, long)]
+	auth: Option<String>,
+
+	/// Number of connections, each driven by its own thread
+	#[arg(short, long, default_value_t = 16)]
+	connections: usize,
+
+	/// Duration of the run in seconds
+	#[arg(short, long, default_value_t = 10)]
+	duration: u64,
+
+	/// Ratio of GETs to SETs to DELs
+	#[arg(short, long, default_value = "90:10:0")]
+	mix: Mix,
+
+	/// Number of distinct keys
+	#[arg(short, long, default_value_t = 100_000)]
+	keys: u64,
+
+	/// Distribution of the accessed keys
+	#[arg(long, value_enum, default_value_t = Distribution::Zipf)]
+	distribution: Distribution,
+
+	/// Exponent of the Zipfian distribution
+	#[arg(long, default_value_t = 0.99)]
+	zipf_exponent: f64,
+
+	/// Size of the set values (e.g. 1KiB), or a range of sizes from which
+	/// each value's size is drawn uniformly (e.g. 64B-4KiB)
+	#[arg(short, long, default_value = "1KiB")]
+	value_size: ValueSize,
+
+	/// Number of commands sent on a connection before their responses are
+	/// read
+	#[arg(long, default_value_t = 1)]
+	pipeline: usize,
+
+	/// Target number of commands per second across all connections, where
+	/// 0 sends commands as fast as the server responds
+	#[arg(short, long, default_value_t = 0)]
+	rate: u64,
+
+	/// Set every key once before the run, so that the run starts warm
+	#[arg(long)]
+	preload: bool,
+
+	/// Interval in seconds at which the server's miss ratio is sampled with
+	/// STATUS during the run
+	#[arg(long)]
+	status_interval: Option<u64>,
+
+	/// Seed of the generated commands
+	#[arg(long, default_value_t = 0)]
+	seed: u64,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Distribution {
+	Uniform,
+	Zipf,
+}
+
+#[derive(Clone, Copy)]
+struct Mix {
+	get: u64,
+	set: u64,
+	del: u64,
+}
+
+#[derive(Clone, Copy)]
+struct ValueSize {
+	min: u64,
+	max: u64,
+}
+
+#[derive(Clone, Copy)]
+enum Op {
+	Get,
+	Set,
+	Del,
+}
+
+/// Draws the index of the next accessed key.
+enum KeySampler {
+	Uniform(u64),
+
+	/// The cumulative probability of every key, where the key with the
+	/// lowest index is the most popular.
+	Zipf(Vec<f64>),
+}
+
+/// A small deterministic generator (SplitMix64), so that a run can be
+/// reproduced from its seed.
+struct Rng {
+	state: u64,
+}
+
+#[derive(Default)]
+struct BenchResult {
+	gets: u64,
+	sets: u64,
+	dels: u64,
+
+	hits: u64,
+	misses: u64,
+	errors: u64,
+
+	latencies_micros: Vec<u64>,
+}
+
+fn main() {
+	let args = Args::parse();
+
+	if let Err(err) = validate_args(&args) {
+		eprintln!("{err}");
+		process::exit(1);
+	}
+
+	let address = format!("{}:{}", args.host, args.port);
+	let sampler = KeySampler::new(args.distribution, args.keys, args.zipf_exponent);
+
+	if args.preload && let Err(err) = preload(&args, &address) {
+		eprintln!("Could not preload keys ({err})");
+		process::exit(1);
+	}
+
+	let is_running = AtomicBool::new(true);
+	let start = Instant::now();
+
+	let (args, address, sampler, is_running) = (&args, &address, &sampler, &is_running);
+
+	let results = thread::scope(|s| {
+		let handles = (0..args.connections)
+			.map(|index| s.spawn(move || run_connection(args, address, sampler, index)))
+			.collect::<Vec<_>>();
+
+		if let Some(interval) = args.status_interval {
+			s.spawn(move || sample_status(args, address, Duration::from_secs(interval), is_running));
+		}
+
+		let results = handles
+			.into_iter()
+			.map(|handle| handle.join().expect("Connection thread panicked"))
+			.collect::<Vec<_>>();
+
+		is_running.store(false, Ordering::Relaxed);
+		results
+	});
+
+	let elapsed = start.elapsed();
+	let mut total = BenchResult::default();
+
+	for result in results {
+		match result {
+			Ok(result) => total.merge(result),
+			Err(err) => eprintln!("Connection failed ({err})"),
+		}
+	}
+
+	print_report(&total, elapsed);
+}
+
+fn validate_args(args: &Args) -> Result<(), String> {
+	if args.connections == 0 {
+		return Err("connections must be greater than zero".into());
+	}
+
+	if args.pipeline == 0 {
+		return Err("pipeline must be greater than zero".into());
+	}
+
+	if args.keys == 0 {
+		return Err("keys must be greater than zero".into());
+	}
+
+	if args.value_size.min == 0 {
+		return Err("value size must be greater than zero".into());
+	}
+
+	Ok(())
+}
+
+/// Sends the commands of a single connection until the run's duration has
+/// elapsed.
+fn run_connection(
+	args: &Args,
+	address: &str,
+	sampler: &KeySampler,
+	index: usize,
+) -> Result<BenchResult, StreamError> {
+	let mut client = NodeClient::connect(address, args.auth.as_deref())?;
+	let mut rng = Rng::new(args.seed.wrapping_add(index as u64));
+	let mut result = BenchResult::default();
+
+	// the interval between the pipelined batches of this connection which
+	// keeps all connections at the target rate
+	let batch_interval = match args.rate {
+		0 => None,
+		rate => Some(Duration::from_secs_f64(
+			(args.pipeline * args.connections) as f64 / rate as f64
+		)),
+	};
+
+	let start = Instant::now();
+	let deadline = start + Duration::from_secs(args.duration);
+
+	let mut num_batches = 0u32;
+
+	loop {
+		// when a rate is targeted, latency is measured from when the batch
+		// was scheduled rather than when it was sent, so that a slow server
+		// is not hidden by the client falling behind its schedule
+		let sent_at = match batch_interval {
+			Some(batch_interval) => start + batch_interval * num_batches,
+			None => Instant::now(),
+		};
+
+		if sent_at >= deadline {
+			break;
+		}
+
+		if let Some(wait) = sent_at.checked_duration_since(Instant::now()) {
+			thread::sleep(wait);
+		}
+
+		let batch = (0..args.pipeline)
+			.map(|_| next_command(args, sampler, &mut rng))
+			.collect::<Vec<_>>();
+
+		for (_, command) in &batch {
+			client.write(command)?;
+		}
+
+		for (op, command) in &batch {
+			let fields = client.read(command)?;
+			result.record(*op, &fields, sent_at.elapsed());
+		}
+
+		num_batches += 1;
+	}
+
+	Ok(result)
+}
+
+fn next_command(args: &Args, sampler: &KeySampler, rng: &mut Rng) -> (Op, Command) {
+	let key = key_name(sampler.sample(rng));
+	let total = args.mix.get + args.mix.set + args.mix.del;
+
+	match rng.below(total) {
+		value if value < args.mix.get => (Op::Get, Command::Get(key)),
+
+		value if value < args.mix.get + args.mix.set => {
+			let value_size = args.value_size.sample(rng);
+			(Op::Set, Command::Set(key, make_value(value_size), Expiry::Never, Vec::new()))
+		},
+
+		_ => (Op::Del, Command::Del(key)),
+	}
+}
+
+fn preload(args: &Args, address: &str) -> Result<(), StreamError> {
+	let mut client = NodeClient::connect(address, args.auth.as_deref())?;
+	let mut rng = Rng::new(args.seed);
+
+	for batch_start in (0..args.keys).step_by(PRELOAD_BATCH_SIZE as usize) {
+		let batch = (batch_start..(batch_start + PRELOAD_BATCH_SIZE).min(args.keys))
+			.map(|index| {
+				let value_size = args.value_size.sample(&mut rng);
+				Command::Set(key_name(index), make_value(value_size), Expiry::Never, Vec::new())
+			})
+			.collect::<Vec<_>>();
+
+		for command in &batch {
+			client.write(command)?;
+		}
+
+		for command in &batch {
+			client.read(command)?;
+		}
+	}
+
+	Ok(())
+}
+
+/// Prints the server's miss ratio at every interval until the run is done.
+fn sample_status(args: &Args, address: &str, interval: Duration, is_running: &AtomicBool) {
+	let mut client = match NodeClient::connect(address, args.auth.as_deref()) {
+		Ok(client) => client,
+
+		Err(err) => {
+			eprintln!("Could not sample STATUS ({err})");
+			return;
+		},
+	};
+
+	let start = Instant::now();
+
+	loop {
+		thread::sleep(interval);
+
+		if !is_running.load(Ordering::Relaxed) {
+			return;
+		}
+
+		let miss_ratio = client
+			.send(&Command::Status)
+			.ok()
+			.and_then(|fields| fields.into_iter().find_map(|field| match field {
+				// the miss ratio is the first float of the response
+				Field::F64(miss_ratio) => Some(miss_ratio),
+				_ => None,
+			}));
+
+		match miss_ratio {
+			Some(miss_ratio) => println!("[{:>4}s] miss ratio: {miss_ratio:.4}", start.elapsed().as_secs()),
+			None => eprintln!("Could not sample STATUS"),
+		}
+	}
+}
+
+fn print_report(result: &BenchResult, elapsed: Duration) {
+	let num_ops = result.gets + result.sets + result.dels;
+	let throughput = num_ops as f64 / elapsed.as_secs_f64();
+
+	let hit_ratio = match result.hits + result.misses {
+		0 => 0.0,
+		num_gets => result.hits as f64 / num_gets as f64,
+	};
+
+	println!("duration:   {:.2}s", elapsed.as_secs_f64());
+	println!("commands:   {num_ops} (gets {}, sets {}, dels {})", result.gets, result.sets, result.dels);
+	println!("throughput: {throughput:.0} commands/s");
+	println!("hit ratio:  {hit_ratio:.4}");
+	println!("errors:     {}", result.errors);
+
+	let mut latencies = result.latencies_micros.clone();
+	latencies.sort_unstable();
+
+	if latencies.is_empty() {
+		return;
+	}
+
+	println!("latency:");
+
+	for percentile in PERCENTILES {
+		let index = ((percentile / 100.0) * latencies.len() as f64).ceil() as usize;
+		let latency = latencies[index.saturating_sub(1).min(latencies.len() - 1)];
+
+		println!("  p{percentile:<5} {latency}us");
+	}
+
+	println!("  max    {}us", latencies[latencies.len() - 1]);
+}
+
+fn key_name(index: u64) -> Buffer {
+	Buffer::from(format!("key:{index}").as_bytes())
+}
+
+fn make_value(size: u64) -> Buffer {
+	Buffer::from(vec![b'x'; size as usize])
+}
+
+impl BenchResult {
+	fn record(&mut self, op: Op, fields: &[Field], latency: Duration) {
+		match op {
+			Op::Get => self.gets += 1,
+			Op::Set => self.sets += 1,
+			Op::Del => self.dels += 1,
+		}
+
+		self.latencies_micros.push(latency.as_micros() as u64);
+
+		let is_key_not_found = matches!(
+			fields,
+			[Field::Bool(false), Field::U8(ERROR_CODE_CACHE), Field::U8(CACHE_ERROR_CODE_KEY_NOT_FOUND)]
+		);
+
+		match (op, fields.first(), is_key_not_found) {
+			(Op::Get, Some(Field::Bool(true)), _) => self.hits += 1,
+			(Op::Get, _, true) => self.misses += 1,
+
+			// deleting a missing key is not an error
+			(Op::Del, _, true) => {},
+
+			(_, Some(Field::Bool(true)), _) => {},
+			_ => self.errors += 1,
+		}
+	}
+
+	fn merge(&mut self, other: BenchResult) {
+		self.gets += other.gets;
+		self.sets += other.sets;
+		self.dels += other.dels;
+
+		self.hits += other.hits;
+		self.misses += other.misses;
+		self.errors += other.errors;
+
+		self.latencies_micros.extend(other.latencies_micros);
+	}
+}
+
+impl KeySampler {
+	fn new(distribution: Distribution, num_keys: u64, exponent: f64) -> Self {
+		match distribution {
+			Distribution::Uniform => KeySampler::Uniform(num_keys),
+
+			Distribution::Zipf => {
+				let mut total = 0.0;
+
+				let mut cdf = (1..=num_keys)
+					.map(|rank| {
+						total += 1.0 / (rank as f64).powf(exponent);
+						total
+					})
+					.collect::<Vec<_>>();
+
+				for probability in &mut cdf {
+					*probability /= total;
+				}
+
+				KeySampler::Zipf(cdf)
+			},
+		}
+	}
+
+	fn sample(&self, rng: &mut Rng) -> u64 {
+		match self {
+			KeySampler::Uniform(num_keys) => rng.below(*num_keys),
+
+			KeySampler::Zipf(cdf) => {
+				let value = rng.next_f64();
+				cdf.partition_point(|probability| *probability < value).min(cdf.len() - 1) as u64
+			},
+		}
+	}
+}
+
+impl ValueSize {
+	fn sample(&self, rng: &mut Rng) -> u64 {
+		self.min + rng.below(self.max - self.min + 1)
+	}
+}
+
+impl Rng {
+	fn new(seed: u64) -> Self {
+		Rng {
+			state: seed,
+		}
+	}
+
+	fn next_u64(&mut self) -> u64 {
+		self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+		let mut z = self.state;
+		z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+
+		z ^ (z >> 31)
+	}
+
+	/// Returns a float in [0, 1).
+	fn next_f64(&mut self) -> f64 {
+		(self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+	}
+
+	/// Returns an integer in [0, n).
+	fn below(&mut self, n: u64) -> u64 {
+		self.next_u64() % n
+	}
+}
+
+impl FromStr for Mix {
+	type Err = String;
+
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		let invalid = || format!("invalid mix <{value}> (expected <get>:<set>:<del>)");
+
+		let ratios = value
+			.split(':')
+			.map(|ratio| ratio.trim().parse::<u64>())
+			.collect::<Result<Vec<_>, _>>()
+			.map_err(|_| invalid())?;
+
+		match ratios.as_slice() {
+			[get, set, del] if get + set + del > 0 => Ok(Mix {
+				get: *get,
+				set: *set,
+				del: *del,
+			}),
+
+			_ => Err(invalid()),
+		}
+	}
+}
+
+impl FromStr for ValueSize {
+	type Err = String;
+
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		let invalid = || format!("invalid value size <{value}>");
+
+		let (min, max) = match value.split_once('-') {
+			Some((min, max)) => (min, max),
+			None => (value, value),
+		};
+
+		let min = parse_size(min.trim()).map_err(|_| invalid())?;
+		let max = parse_size(max.trim()).map_err(|_| invalid())?;
+
+		if min > max {
+			return Err(invalid());
+		}
+
+		Ok(ValueSize {
+			min,
+			max,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const NUM_SAMPLES: usize = 10_000;
+
+	fn args(extra_args: &[&str]) -> Args {
+		Args::parse_from(["paper-bench"].iter().chain(extra_args))
+	}
+
+	fn key_counts(sampler: &KeySampler, num_keys: usize) -> Vec<usize> {
+		let mut rng = Rng::new(0);
+		let mut counts = vec![0; num_keys];
+
+		for _ in 0..NUM_SAMPLES {
+			counts[sampler.sample(&mut rng) as usize] += 1;
+		}
+
+		counts
+	}
+
+	#[test]
+	fn it_reproduces_the_commands_of_a_seed() {
+		let args = args(&["--keys", "100", "--mix", "1:1:1", "--value-size", "1B-1KiB"]);
+		let sampler = KeySampler::new(args.distribution, args.keys, args.zipf_exponent);
+
+		let commands = |seed| {
+			let mut rng = Rng::new(seed);
+
+			(0..100)
+				.map(|_| next_command(&args, &sampler, &mut rng).1.to_sheet().serialize().to_vec())
+				.collect::<Vec<_>>()
+		};
+
+		assert_eq!(commands(1), commands(1));
+		assert_ne!(commands(1), commands(2));
+	}
+
+	#[test]
+	fn it_follows_the_mix() {
+		let args = args(&["--keys", "100", "--mix", "3:1:0"]);
+		let sampler = KeySampler::new(args.distribution, args.keys, args.zipf_exponent);
+		let mut rng = Rng::new(0);
+
+		let ops = (0..NUM_SAMPLES)
+			.map(|_| next_command(&args, &sampler, &mut rng).0)
+			.collect::<Vec<_>>();
+
+		let num_gets = ops.iter().filter(|op| matches!(op, Op::Get)).count();
+		let num_sets = ops.iter().filter(|op| matches!(op, Op::Set)).count();
+
+		assert_eq!(num_gets + num_sets, NUM_SAMPLES);
+		assert!(num_gets.abs_diff(3 * NUM_SAMPLES / 4) < NUM_SAMPLES / 50);
+	}
+
+	#[test]
+	fn it_samples_uniform_keys() {
+		let counts = key_counts(&KeySampler::new(Distribution::Uniform, 10, 0.99), 10);
+
+		let expected = NUM_SAMPLES / 10;
+		assert!(counts.iter().all(|count| count.abs_diff(expected) < expected / 5));
+	}
+
+	#[test]
+	fn it_samples_zipfian_keys() {
+		let sampler = KeySampler::new(Distribution::Zipf, 100, 0.99);
+
+		let KeySampler::Zipf(cdf) = &sampler else {
+			panic!("expected a zipfian sampler");
+		};
+
+		assert!((cdf[99] - 1.0).abs() < 1e-9);
+
+		let counts = key_counts(&sampler, 100);
+
+		// more than half of the accesses go to the first tenth of the keys
+		assert!(counts[0] > counts[1] && counts[1] > counts[10]);
+		assert!(counts[..10].iter().sum::<usize>() > NUM_SAMPLES / 2);
+	}
+
+	#[test]
+	fn it_draws_value_sizes_in_the_range() {
+		let value_size = "64B-4KiB".parse::<ValueSize>().unwrap();
+		let mut rng = Rng::new(0);
+
+		let sizes = (0..NUM_SAMPLES)
+			.map(|_| value_size.sample(&mut rng))
+			.collect::<Vec<_>>();
+
+		assert!(sizes.iter().all(|size| (64..=4096).contains(size)));
+		assert!(sizes.iter().any(|size| *size < 128) && sizes.iter().any(|size| *size > 4000));
+
+		let value_size = "1KiB".parse::<ValueSize>().unwrap();
+		assert_eq!(value_size.sample(&mut rng), 1024);
+
+		assert!("4KiB-64B".parse::<ValueSize>().is_err());
+		assert!("large".parse::<ValueSize>().is_err());
+	}
+
+	#[test]
+	fn it_parses_a_mix() {
+		let mix = "90:10:0".parse::<Mix>().unwrap();
+		assert_eq!((mix.get, mix.set, mix.del), (90, 10, 0));
+
+		assert!("0:0:0".parse::<Mix>().is_err());
+		assert!("90:10".parse::<Mix>().is_err());
+		assert!("a:b:c".parse::<Mix>().is_err());
+	}
+
+	#[test]
+	fn it_validates_the_arguments() {
+		assert!(validate_args(&args(&[])).is_ok());
+		assert!(validate_args(&args(&["--connections", "0"])).is_err());
+		assert!(validate_args(&args(&["--pipeline", "0"])).is_err());
+		assert!(validate_args(&args(&["--keys", "0"])).is_err());
+		assert!(validate_args(&args(&["--value-size", "0B"])).is_err());
+	}
+
+	#[test]
+	fn it_counts_hits_misses_and_errors() {
+		let not_found = [
+			Field::Bool(false),
+			Field::U8(ERROR_CODE_CACHE),
+			Field::U8(CACHE_ERROR_CODE_KEY_NOT_FOUND),
+		];
+
+		let mut result = BenchResult::default();
+
+		result.record(Op::Get, &[Field::Bool(true)], Duration::from_micros(1));
+		result.record(Op::Get, &not_found, Duration::from_micros(1));
+		result.record(Op::Del, &not_found, Duration::from_micros(1));
+		result.record(Op::Set, &[Field::Bool(false), Field::U8(1)], Duration::from_micros(1));
+
+		assert_eq!((result.gets, result.sets, result.dels), (2, 1, 1));
+		assert_eq!((result.hits, result.misses, result.errors), (1, 1, 1));
+		assert_eq!(result.latencies_micros.len(), 4);
+	}
+}
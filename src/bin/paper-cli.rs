@@ -0,0 +1,964 @@
+/*
+ * Copyright (c) Kia Shakiba
+ *
+ * This source code is licensed under the GNU AGPLv3 license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::{
+	env,
+	process,
+	path::PathBuf,
+};
+
+use clap::Parser;
+use parse_size::parse_size;
+use paper_utils::stream::Buffer;
+
+use rustyline::{
+	Editor,
+	Helper,
+	Context,
+	completion::Completer,
+	hint::Hinter,
+	highlight::Highlighter,
+	validate::Validator,
+	history::DefaultHistory,
+	error::ReadlineError,
+};
+
+use paper_server::{
+	command::Command,
+	expiry::{self, Expiry},
+	error::{ServerError, ErrorCode},
+	node_client::{NodeClient, Field},
+};
+
+const HISTORY_FILE_NAME: &str = ".paper_cli_history";
+
+/// An interactive client for paper-server. When a command is supplied, it
+/// is run once and its response is printed, otherwise commands are read
+/// from a prompt.
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+	/// Host of the server
+	#[arg(short = 'H', long, default_value = "127.0.0.1")]
+	host: String,
+
+	/// Port of the server
+	#[arg(short, long, default_value_t = 3145)]
+	port: u32,
+
+	/// Token with which the connection is authorized
+	#[arg(short, long)]
+	auth: Option<String>,
+
+	/// Command to run once instead of starting the prompt (e.g. get foo)
+	#[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+	command: Vec<String>,
+}
+
+struct CommandSpec {
+	name: &'static str,
+	usage: &'static str,
+}
+
+const COMMANDS: &[CommandSpec] = &[
+	CommandSpec { name: "ping", usage: "ping" },
+	CommandSpec { name: "version", usage: "version" },
+	CommandSpec { name: "auth", usage: "auth <token>" },
+
+	CommandSpec { name: "get", usage: "get <key>" },
+	CommandSpec { name: "set", usage: "set <key> <value> [expiry] [tag ...]" },
+	CommandSpec { name: "del", usage: "del <key>" },
+	CommandSpec { name: "mget", usage: "mget <key> [key ...]" },
+
+	CommandSpec { name: "gets", usage: "gets <key>" },
+	CommandSpec { name: "cas", usage: "cas <key> <value> <version> [expiry]" },
+
+	CommandSpec { name: "setnx", usage: "setnx <key> <value> [expiry]" },
+	CommandSpec { name: "setxx", usage: "setxx <key> <value> [expiry]" },
+	CommandSpec { name: "getset", usage: "getset <key> <value> [expiry]" },
+
+	CommandSpec { name: "has", usage: "has <key>" },
+	CommandSpec { name: "peek", usage: "peek <key>" },
+	CommandSpec { name: "ttl", usage: "ttl <key> [expiry]" },
+	CommandSpec { name: "pttl", usage: "pttl <key>" },
+	CommandSpec { name: "size", usage: "size <key>" },
+
+	CommandSpec { name: "scan", usage: "scan [prefix] [count] [cursor]" },
+	CommandSpec { name: "delprefix", usage: "delprefix <prefix>" },
+
+	CommandSpec { name: "invalidate", usage: "invalidate <tag>" },
+
+	CommandSpec { name: "pin", usage: "pin <key>" },
+	CommandSpec { name: "unpin", usage: "unpin <key>" },
+
+	CommandSpec { name: "wipe", usage: "wipe" },
+
+	CommandSpec { name: "resize", usage: "resize <size>" },
+	CommandSpec { name: "policy", usage: "policy <policy>" },
+	CommandSpec { name: "policyhistory", usage: "policyhistory" },
+
+	CommandSpec { name: "status", usage: "status" },
+
+	CommandSpec { name: "trace", usage: "trace start <name> [sample_rate] | trace stop" },
+	CommandSpec { name: "mrc", usage: "mrc" },
+
+	CommandSpec { name: "cluster", usage: "cluster nodes | cluster migrate|import|cancelimport|setslots <start> <end> <node>" },
+	CommandSpec { name: "restore", usage: "restore <key> <value> [expiry] [tag ...]" },
+
+	CommandSpec { name: "help", usage: "help" },
+	CommandSpec { name: "quit", usage: "quit" },
+];
+
+const SUBCOMMANDS: &[(&str, &[&str])] = &[
+	("trace", &["start", "stop"]),
+	("cluster", &["nodes", "migrate", "import", "cancelimport", "setslots"]),
+];
+
+/// Completes command names, and the subcommands of commands which have
+/// them.
+struct CliHelper;
+
+/// Reads the fields of a response in order.
+struct Response {
+	fields: std::vec::IntoIter<Field>,
+}
+
+fn main() {
+	let args = Args::parse();
+	let address = format!("{}:{}", args.host, args.port);
+
+	let mut client = match NodeClient::connect(&address, args.auth.as_deref()) {
+		Ok(client) => client,
+
+		Err(err) => {
+			eprintln!("Could not connect to <{address}> ({err})");
+			process::exit(1);
+		},
+	};
+
+	if !args.command.is_empty() {
+		let is_ok = run(&mut client, &args.command);
+		process::exit(if is_ok { 0 } else { 1 });
+	}
+
+	if let Err(err) = repl(&mut client, &address) {
+		eprintln!("{err}");
+		process::exit(1);
+	}
+}
+
+fn repl(client: &mut NodeClient, address: &str) -> Result<(), ReadlineError> {
+	let mut editor = Editor::<CliHelper, DefaultHistory>::new()?;
+	editor.set_helper(Some(CliHelper));
+
+	let history_path = env::var_os("HOME")
+		.map(|home| PathBuf::from(home).join(HISTORY_FILE_NAME));
+
+	if let Some(path) = &history_path {
+		// the history file does not exist before the first session
+		let _ = editor.load_history(path);
+	}
+
+	let prompt = format!("{address}> ");
+
+	loop {
+		let line = match editor.readline(&prompt) {
+			Ok(line) => line,
+			Err(ReadlineError::Interrupted) => continue,
+			Err(ReadlineError::Eof) => break,
+			Err(err) => return Err(err),
+		};
+
+		let tokens = match tokenize(&line) {
+			Ok(tokens) => tokens,
+
+			Err(err) => {
+				println!("(error) {err}");
+				continue;
+			},
+		};
+
+		if tokens.is_empty() {
+			continue;
+		}
+
+		editor.add_history_entry(line.as_str())?;
+
+		match tokens[0].to_lowercase().as_str() {
+			"quit" | "exit" => break,
+			"help" => print_help(),
+
+			_ => {
+				run(client, &tokens);
+			},
+		}
+	}
+
+	if let Some(path) = &history_path {
+		editor.save_history(path)?;
+	}
+
+	Ok(())
+}
+
+/// Sends the command and prints its response. Returns whether the command
+/// succeeded.
+fn run(client: &mut NodeClient, tokens: &[String]) -> bool {
+	let command = match parse_command(tokens) {
+		Ok(command) => command,
+
+		Err(err) => {
+			println!("(error) {err}");
+			return false;
+		},
+	};
+
+	let fields = match client.send(&command) {
+		Ok(fields) => fields,
+
+		Err(err) => {
+			println!("(error) could not send command ({err})");
+			return false;
+		},
+	};
+
+	let mut response = Response {
+		fields: fields.into_iter(),
+	};
+
+	if !response.bool() {
+		println!("(error) {}", response.error());
+		return false;
+	}
+
+	print_response(&command, &mut response);
+	true
+}
+
+fn parse_command(tokens: &[String]) -> Result<Command, String> {
+	let name = tokens[0].to_lowercase();
+	let args = &tokens[1..];
+
+	let usage = || COMMANDS
+		.iter()
+		.find(|spec| spec.name == name)
+		.map(|spec| format!("usage: {}", spec.usage))
+		.unwrap_or_else(|| format!("unknown command <{name}>"));
+
+	let command = match (name.as_str(), args) {
+		("ping", []) => Command::Ping,
+		("version", []) => Command::Version,
+		("auth", [token]) => Command::Auth(to_buf(token)),
+
+		("get", [key]) => Command::Get(to_buf(key)),
+		("set", [key, value, rest @ ..]) => {
+			let (expiry, tags) = parse_expiry_and_tags(rest)?;
+			Command::Set(to_buf(key), to_buf(value), expiry, tags)
+		},
+		("del", [key]) => Command::Del(to_buf(key)),
+		("mget", keys) if !keys.is_empty() => Command::MGet(keys.iter().map(|key| to_buf(key)).collect()),
+
+		("gets", [key]) => Command::Gets(to_buf(key)),
+		("cas", [key, value, version, expiry @ ..]) => Command::Cas(
+			to_buf(key),
+			to_buf(value),
+			version.parse().map_err(|_| format!("invalid version <{version}>"))?,
+			parse_expiry(expiry)?,
+		),
+
+		("setnx", [key, value, expiry @ ..]) => Command::SetNx(to_buf(key), to_buf(value), parse_expiry(expiry)?),
+		("setxx", [key, value, expiry @ ..]) => Command::SetXx(to_buf(key), to_buf(value), parse_expiry(expiry)?),
+		("getset", [key, value, expiry @ ..]) => Command::GetSet(to_buf(key), to_buf(value), parse_expiry(expiry)?),
+
+		("has", [key]) => Command::Has(to_buf(key)),
+		("peek", [key]) => Command::Peek(to_buf(key)),
+		("ttl", [key, expiry @ ..]) => Command::Ttl(to_buf(key), parse_expiry(expiry)?),
+		("pttl", [key]) => Command::Pttl(to_buf(key)),
+		("size", [key]) => Command::Size(to_buf(key)),
+
+		("scan", args) if args.len() <= 3 => {
+			let prefix = args.first().map(|prefix| to_buf(prefix)).unwrap_or_default();
+
+			let count = match args.get(1) {
+				Some(count) => count.parse().map_err(|_| format!("invalid count <{count}>"))?,
+				None => 0,
+			};
+
+			let cursor = args.get(2).map(|cursor| to_buf(cursor)).unwrap_or_default();
+
+			Command::Scan(cursor, prefix, count)
+		},
+		("delprefix", [prefix]) => Command::DelPrefix(to_buf(prefix)),
+
+		("invalidate", [tag]) => Command::Invalidate(to_buf(tag)),
+
+		("pin", [key]) => Command::Pin(to_buf(key)),
+		("unpin", [key]) => Command::Unpin(to_buf(key)),
+
+		("wipe", []) => Command::Wipe,
+
+		("resize", [size]) => Command::Resize(parse_size(size).map_err(|_| format!("invalid size <{size}>"))?),
+		("policy", [policy]) => Command::Policy(policy.clone()),
+		("policyhistory", []) => Command::PolicyHistory,
+
+		("status", []) => Command::Status,
+
+		("trace", [subcommand, args @ ..]) => match (subcommand.to_lowercase().as_str(), args) {
+			("start", [name]) => Command::TraceStart(name.clone(), 1.0),
+			("start", [name, sample_rate]) => Command::TraceStart(
+				name.clone(),
+				sample_rate.parse().map_err(|_| format!("invalid sample rate <{sample_rate}>"))?,
+			),
+			("stop", []) => Command::TraceStop,
+
+			_ => return Err(usage()),
+		},
+
+		("mrc", []) => Command::Mrc,
+
+		("cluster", [subcommand, args @ ..]) => match (subcommand.to_lowercase().as_str(), args) {
+			("nodes", []) => Command::ClusterNodes,
+
+			("migrate", [start, end, node_id]) => {
+				let (start, end) = parse_slot_range(start, end)?;
+				Command::ClusterMigrate(start, end, node_id.clone())
+			},
+
+			("import", [start, end, node_id]) => {
+				let (start, end) = parse_slot_range(start, end)?;
+				Command::ClusterImport(start, end, node_id.clone())
+			},
+
+			("cancelimport", [start, end, node_id]) => {
+				let (start, end) = parse_slot_range(start, end)?;
+				Command::ClusterCancelImport(start, end, node_id.clone())
+			},
+
+			("setslots", [start, end, node_id]) => {
+				let (start, end) = parse_slot_range(start, end)?;
+				Command::ClusterSetSlots(start, end, node_id.clone())
+			},
+
+			_ => return Err(usage()),
+		},
+
+		("restore", [key, value, rest @ ..]) => {
+			let (expiry, tags) = parse_expiry_and_tags(rest)?;
+			Command::Restore(to_buf(key), to_buf(value), expiry, tags)
+		},
+
+		_ => return Err(usage()),
+	};
+
+	Ok(command)
+}
+
+fn print_response(command: &Command, response: &mut Response) {
+	match command {
+		Command::Ping
+			| Command::Version
+			| Command::Get(_)
+			| Command::Peek(_) => println!("{}", response.string()),
+
+		Command::Gets(_) => {
+			let value = response.string();
+			println!("{value} (version {})", response.u64());
+		},
+
+		Command::MGet(_) => {
+			for index in 0..response.u32() {
+				match response.bool() {
+					true => println!("{}) {}", index + 1, response.string()),
+					false => println!("{}) (nil)", index + 1),
+				}
+			}
+		},
+
+		Command::SetNx(..)
+			| Command::SetXx(..)
+			| Command::Has(_)
+			| Command::Restore(..) => println!("{}", response.bool()),
+
+		Command::GetSet(..) => match response.bool() {
+			true => println!("{}", response.string()),
+			false => println!("(nil)"),
+		},
+
+		Command::Pttl(_) => match response.bool() {
+			true => println!("{}ms", response.u64()),
+			false => println!("(no expiry)"),
+		},
+
+		Command::Size(_) => println!("{}", format_size(response.u32() as u64)),
+
+		Command::Scan(..) => {
+			let is_done = response.bool();
+			let cursor = response.string();
+
+			for index in 0..response.u32() {
+				println!("{}) {}", index + 1, response.string());
+			}
+
+			match is_done {
+				true => println!("(done)"),
+				false => println!("(cursor {cursor})"),
+			}
+		},
+
+		Command::DelPrefix(_) | Command::Invalidate(_) => println!("(deleted {})", response.u64()),
+
+		Command::PolicyHistory => print_policy_history(response),
+		Command::Status => print_status(response),
+		Command::Mrc => print_mrc(response),
+		Command::ClusterNodes => print_cluster_nodes(response),
+
+		Command::Auth(_)
+			| Command::Set(..)
+			| Command::Del(_)
+			| Command::Cas(..)
+			| Command::Ttl(..)
+			| Command::Pin(_)
+			| Command::Unpin(_)
+			| Command::Wipe
+			| Command::Resize(_)
+			| Command::Policy(_)
+			| Command::TraceStart(..)
+			| Command::TraceStop
+			| Command::ClusterMigrate(..)
+			| Command::ClusterImport(..)
+			| Command::ClusterCancelImport(..)
+			| Command::ClusterSetSlots(..) => println!("OK"),
+	}
+}
+
+fn print_status(response: &mut Response) {
+	let pid = response.u32();
+	let max_size = response.u64();
+	let used_size = response.u64();
+	let num_objects = response.u64();
+	let rss = response.u64();
+	let hwm = response.u64();
+	let total_gets = response.u64();
+	let total_sets = response.u64();
+	let total_dels = response.u64();
+	let miss_ratio = response.f64();
+
+	let policies = (0..response.u32())
+		.map(|_| response.string())
+		.collect::<Vec<_>>()
+		.join(", ");
+
+	let policy = response.string();
+	let is_auto_policy = response.bool();
+	let uptime = response.u64();
+
+	let policy = match is_auto_policy {
+		true => format!("{policy} (auto)"),
+		false => policy,
+	};
+
+	print_table(&["stat", "value"], vec![
+		vec!["pid".into(), pid.to_string()],
+		vec!["max size".into(), format_size(max_size)],
+		vec!["used size".into(), format_size(used_size)],
+		vec!["objects".into(), num_objects.to_string()],
+		vec!["rss".into(), format_size(rss)],
+		vec!["hwm".into(), format_size(hwm)],
+		vec!["gets".into(), total_gets.to_string()],
+		vec!["sets".into(), total_sets.to_string()],
+		vec!["dels".into(), total_dels.to_string()],
+		vec!["miss ratio".into(), format!("{miss_ratio:.4}")],
+		vec!["policies".into(), policies],
+		vec!["policy".into(), policy],
+		vec!["uptime".into(), format!("{uptime}ms")],
+	]);
+
+	let num_tiers = response.u32();
+
+	if num_tiers > 0 {
+		let rows = (0..num_tiers)
+			.map(|_| vec![
+				response.string(),
+				format_size(response.u64()),
+				format_size(response.u64()),
+				response.u64().to_string(),
+				response.u64().to_string(),
+				response.u64().to_string(),
+				response.u64().to_string(),
+				format!("{}ns", response.u64()),
+			])
+			.collect();
+
+		println!();
+		print_table(
+			&["tier", "used size", "max size", "objects", "hits", "promotions", "demotions", "avg access"],
+			rows,
+		);
+	}
+
+	if response.bool() {
+		let admitted = response.u64();
+		let rejected = response.u64();
+
+		println!();
+		print_table(&["admitted", "rejected"], vec![
+			vec![admitted.to_string(), rejected.to_string()],
+		]);
+	}
+
+	let pinned_used_size = response.u64();
+	let pinned_max_size = response.u64();
+
+	println!();
+	print_table(&["pinned size", "max pinned size"], vec![
+		vec![format_size(pinned_used_size), format_size(pinned_max_size)],
+	]);
+
+	let num_namespaces = response.u32();
+
+	if num_namespaces > 0 {
+		let rows = (0..num_namespaces)
+			.map(|_| vec![
+				response.string(),
+				format_size(response.u64()),
+				format_size(response.u64()),
+				response.u64().to_string(),
+				format!("{:.4}", response.f64()),
+				response.string(),
+			])
+			.collect();
+
+		println!();
+		print_table(&["namespace", "used size", "max size", "objects", "miss ratio", "policy"], rows);
+	}
+}
+
+fn print_policy_history(response: &mut Response) {
+	let rows = (0..response.u32())
+		.map(|_| {
+			let timestamp = response.u64();
+			let old_policy = response.string();
+			let new_policy = response.string();
+			let miss_ratio = response.f64();
+			let is_auto = response.bool();
+			let issuer = response.string();
+
+			let source = match is_auto {
+				true => "auto".into(),
+				false => issuer,
+			};
+
+			vec![
+				timestamp.to_string(),
+				old_policy,
+				new_policy,
+				format!("{miss_ratio:.4}"),
+				source,
+			]
+		})
+		.collect();
+
+	print_table(&["timestamp", "old policy", "new policy", "miss ratio", "source"], rows);
+}
+
+fn print_mrc(response: &mut Response) {
+	let sample_rate = response.f64();
+
+	let rows = (0..response.u32())
+		.map(|_| vec![
+			response.string(),
+			format_size(response.u64()),
+			response.u64().to_string(),
+			response.u64().to_string(),
+			format!("{:.4}", response.f64()),
+		])
+		.collect();
+
+	println!("sample rate: {sample_rate}");
+	print_table(&["policy", "size", "gets", "misses", "miss ratio"], rows);
+}
+
+fn print_cluster_nodes(response: &mut Response) {
+	let rows = (0..response.u32())
+		.map(|_| {
+			let id = response.string();
+			let host = response.string();
+			let port = response.u32();
+			let is_self = response.bool();
+
+			let slots = (0..response.u32())
+				.map(|_| match (response.u32(), response.u32()) {
+					(start, end) if start == end => start.to_string(),
+					(start, end) => format!("{start}-{end}"),
+				})
+				.collect::<Vec<_>>()
+				.join(",");
+
+			let id = match is_self {
+				true => format!("{id} (self)"),
+				false => id,
+			};
+
+			vec![id, format!("{host}:{port}"), slots]
+		})
+		.collect();
+
+	print_table(&["id", "address", "slots"], rows);
+}
+
+fn print_table(headers: &[&str], rows: Vec<Vec<String>>) {
+	let mut widths = headers
+		.iter()
+		.map(|header| header.len())
+		.collect::<Vec<_>>();
+
+	for row in &rows {
+		for (width, cell) in widths.iter_mut().zip(row) {
+			*width = (*width).max(cell.len());
+		}
+	}
+
+	let print_row = |cells: Vec<&str>| {
+		let line = cells
+			.iter()
+			.zip(&widths)
+			.map(|(cell, width)| format!("{cell:<width$}"))
+			.collect::<Vec<_>>()
+			.join("  ");
+
+		println!("{}", line.trim_end());
+	};
+
+	let separators = widths
+		.iter()
+		.map(|width| "-".repeat(*width))
+		.collect::<Vec<_>>();
+
+	print_row(headers.to_vec());
+	print_row(separators.iter().map(String::as_str).collect());
+
+	for row in &rows {
+		print_row(row.iter().map(String::as_str).collect());
+	}
+}
+
+fn print_help() {
+	for spec in COMMANDS {
+		println!("{}", spec.usage);
+	}
+
+	println!();
+	println!("expiry: <seconds> | ex <seconds> | px <milliseconds> | exat <unix seconds> | pxat <unix milliseconds>");
+}
+
+/// Splits a line into tokens on whitespace. A token may be wrapped in
+/// double quotes to include whitespace, and a backslash escapes the
+/// following character.
+fn tokenize(line: &str) -> Result<Vec<String>, String> {
+	let mut tokens = Vec::new();
+	let mut token: Option<String> = None;
+	let mut is_quoted = false;
+
+	let mut chars = line.chars();
+
+	while let Some(c) = chars.next() {
+		match c {
+			'\\' => {
+				let escaped = chars.next().ok_or("unterminated escape")?;
+				token.get_or_insert_default().push(escaped);
+			},
+
+			'"' => {
+				is_quoted = !is_quoted;
+				token.get_or_insert_default();
+			},
+
+			c if c.is_whitespace() && !is_quoted => {
+				if let Some(token) = token.take() {
+					tokens.push(token);
+				}
+			},
+
+			c => token.get_or_insert_default().push(c),
+		}
+	}
+
+	if is_quoted {
+		return Err("unterminated quote".into());
+	}
+
+	if let Some(token) = token {
+		tokens.push(token);
+	}
+
+	Ok(tokens)
+}
+
+/// Parses an expiry which must be the command's last argument.
+fn parse_expiry(args: &[String]) -> Result<Expiry, String> {
+	match expiry::parse_args(args)? {
+		(expiry, []) => Ok(expiry),
+		(_, [arg, ..]) => Err(format!("unexpected argument <{arg}>")),
+	}
+}
+
+fn parse_expiry_and_tags(args: &[String]) -> Result<(Expiry, Vec<Buffer>), String> {
+	let (expiry, tags) = expiry::parse_args(args)?;
+
+	let tags = tags
+		.iter()
+		.map(|tag| to_buf(tag))
+		.collect();
+
+	Ok((expiry, tags))
+}
+
+fn parse_slot_range(start: &str, end: &str) -> Result<(u32, u32), String> {
+	let start = start.parse().map_err(|_| format!("invalid slot <{start}>"))?;
+	let end = end.parse().map_err(|_| format!("invalid slot <{end}>"))?;
+
+	Ok((start, end))
+}
+
+fn to_buf(value: &str) -> Buffer {
+	Buffer::from(value.as_bytes())
+}
+
+fn format_size(size: u64) -> String {
+	const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+
+	let mut value = size as f64;
+	let mut unit = 0;
+
+	while value >= 1024.0 && unit < UNITS.len() - 1 {
+		value /= 1024.0;
+		unit += 1;
+	}
+
+	match unit {
+		0 => format!("{size}B"),
+		_ => format!("{value:.2}{}", UNITS[unit]),
+	}
+}
+
+impl Response {
+	fn bool(&mut self) -> bool {
+		matches!(self.fields.next(), Some(Field::Bool(true)))
+	}
+
+	fn u8(&mut self) -> u8 {
+		match self.fields.next() {
+			Some(Field::U8(value)) => value,
+			_ => 0,
+		}
+	}
+
+	fn u32(&mut self) -> u32 {
+		match self.fields.next() {
+			Some(Field::U32(value)) => value,
+			_ => 0,
+		}
+	}
+
+	fn u64(&mut self) -> u64 {
+		match self.fields.next() {
+			Some(Field::U64(value)) => value,
+			_ => 0,
+		}
+	}
+
+	fn f64(&mut self) -> f64 {
+		match self.fields.next() {
+			Some(Field::F64(value)) => value,
+			_ => 0.0,
+		}
+	}
+
+	fn string(&mut self) -> String {
+		match self.fields.next() {
+			Some(Field::Buf(value)) => String::from_utf8_lossy(&value).into_owned(),
+			_ => String::new(),
+		}
+	}
+
+	/// Reads the error which follows a failed response's status.
+	fn error(&mut self) -> String {
+		match self.u8() {
+			ErrorCode::CACHE => ServerError::from_cache_error_code(self.u8())
+				.map(|err| err.to_string())
+				.unwrap_or("cache error".into()),
+
+			ErrorCode::MAX_CONNECTIONS => ServerError::MaxConnectionsExceeded.to_string(),
+			ErrorCode::UNAUTHORIZED => ServerError::Unauthorized.to_string(),
+
+			ErrorCode::MOVED => {
+				let slot = self.u32();
+				format!("MOVED {slot} {}", self.string())
+			},
+
+			ErrorCode::BACKEND_UNAVAILABLE => "backend is unavailable".into(),
+
+			_ => "command failed".into(),
+		}
+	}
+}
+
+impl Completer for CliHelper {
+	type Candidate = String;
+
+	fn complete(
+		&self,
+		line: &str,
+		pos: usize,
+		_ctx: &Context<'_>,
+	) -> rustyline::Result<(usize, Vec<String>)> {
+		let line = &line[..pos];
+		let start = line.rfind(char::is_whitespace).map(|index| index + 1).unwrap_or(0);
+
+		let prefix = line[start..].to_lowercase();
+		let previous = line[..start].split_whitespace().collect::<Vec<_>>();
+
+		let candidates: Vec<&str> = match previous.as_slice() {
+			[] => COMMANDS.iter().map(|spec| spec.name).collect(),
+
+			[name] => SUBCOMMANDS
+				.iter()
+				.find(|(command, _)| command.eq_ignore_ascii_case(name))
+				.map(|(_, subcommands)| subcommands.to_vec())
+				.unwrap_or_default(),
+
+			_ => Vec::new(),
+		};
+
+		let candidates = candidates
+			.into_iter()
+			.filter(|candidate| candidate.starts_with(&prefix))
+			.map(String::from)
+			.collect();
+
+		Ok((start, candidates))
+	}
+}
+
+impl Hinter for CliHelper {
+	type Hint = String;
+}
+
+impl Highlighter for CliHelper {}
+impl Validator for CliHelper {}
+impl Helper for CliHelper {}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn parse(line: &str) -> Result<Command, String> {
+		parse_command(&tokenize(line).unwrap())
+	}
+
+	fn buf(value: &str) -> Buffer {
+		Buffer::from(value.as_bytes())
+	}
+
+	#[test]
+	fn it_tokenizes_a_line() {
+		assert_eq!(tokenize("  set key  value ").unwrap(), ["set", "key", "value"]);
+		assert_eq!(tokenize(r#"set "a key" "" a\"b"#).unwrap(), ["set", "a key", "", "a\"b"]);
+
+		assert!(tokenize(r#"set "key"#).is_err());
+		assert!(tokenize(r"set key\").is_err());
+	}
+
+	#[test]
+	fn it_parses_the_command_from_the_arguments() {
+		let args = Args::parse_from(["paper-cli", "-p", "31450", "set", "key", "value", "px", "500"]);
+
+		assert_eq!(args.port, 31450);
+		assert_eq!(args.command, ["set", "key", "value", "px", "500"]);
+
+		assert!(matches!(
+			parse_command(&args.command).unwrap(),
+			Command::Set(key, value, Expiry::Millis(500), tags)
+				if key == buf("key") && value == buf("value") && tags.is_empty()
+		));
+
+		let args = Args::parse_from(["paper-cli"]);
+		assert!(args.command.is_empty());
+	}
+
+	#[test]
+	fn it_parses_an_expiry_and_tags() {
+		assert!(matches!(
+			parse("SET key value").unwrap(),
+			Command::Set(_, _, Expiry::Never, tags) if tags.is_empty()
+		));
+
+		assert!(matches!(
+			parse("set key value 10 a b").unwrap(),
+			Command::Set(_, _, Expiry::Seconds(10), tags) if tags == [buf("a"), buf("b")]
+		));
+
+		assert!(matches!(parse("setnx key value exat 100").unwrap(), Command::SetNx(_, _, Expiry::UnixSeconds(100))));
+		assert!(matches!(parse("ttl key 0").unwrap(), Command::Ttl(_, Expiry::Never)));
+
+		assert!(parse("setxx key value px").is_err());
+		assert!(parse("getset key value 10 tag").is_err());
+	}
+
+	#[test]
+	fn it_parses_the_arguments_of_each_command() {
+		assert!(matches!(
+			parse("cas key value 3").unwrap(),
+			Command::Cas(_, _, 3, Expiry::Never)
+		));
+
+		assert!(matches!(
+			parse("mget a b").unwrap(),
+			Command::MGet(keys) if keys == [buf("a"), buf("b")]
+		));
+
+		assert!(matches!(
+			parse("scan user: 10 user:5").unwrap(),
+			Command::Scan(cursor, prefix, 10) if cursor == buf("user:5") && prefix == buf("user:")
+		));
+
+		assert!(matches!(
+			parse("scan").unwrap(),
+			Command::Scan(cursor, prefix, 0) if cursor.is_empty() && prefix.is_empty()
+		));
+
+		assert!(matches!(parse("resize 1KiB").unwrap(), Command::Resize(1024)));
+		assert!(matches!(parse("trace start name 0.5").unwrap(), Command::TraceStart(name, 0.5) if name == "name"));
+		assert!(matches!(parse("trace start name").unwrap(), Command::TraceStart(_, 1.0)));
+
+		assert!(matches!(
+			parse("cluster setslots 0 100 node").unwrap(),
+			Command::ClusterSetSlots(0, 100, node_id) if node_id == "node"
+		));
+	}
+
+	#[test]
+	fn it_rejects_invalid_arguments() {
+		assert_eq!(parse("get").err().unwrap(), "usage: get <key>");
+		assert_eq!(parse("get a b").err().unwrap(), "usage: get <key>");
+		assert_eq!(parse("trace restart").err().unwrap(), "usage: trace start <name> [sample_rate] | trace stop");
+		assert_eq!(parse("other").err().unwrap(), "unknown command <other>");
+
+		assert_eq!(parse("cas key value x").err().unwrap(), "invalid version <x>");
+		assert_eq!(parse("resize large").err().unwrap(), "invalid size <large>");
+		assert_eq!(parse("cluster migrate 0 x node").err().unwrap(), "invalid slot <x>");
+		assert!(parse("scan a b").is_err());
+		assert!(parse("mget").is_err());
+	}
+
+	#[test]
+	fn it_formats_a_size() {
+		assert_eq!(format_size(512), "512B");
+		assert_eq!(format_size(1536), "1.50KiB");
+		assert_eq!(format_size(1 << 30), "1.00GiB");
+	}
+}
@@ -0,0 +1,407 @@
+/*
+ * Copyright (c) Kia Shakiba
+ *
+ * This source code is licensed under the GNU AGPLv3 license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::{
+	fs,
+	thread,
+	str::FromStr,
+	path::PathBuf,
+	collections::HashMap,
+};
+
+use clap::{Parser, ValueEnum};
+use parse_size::parse_size;
+use paper_cache::{PaperCache, PaperPolicy, CacheError};
+use paper_utils::stream::Buffer;
+use paper_server::trace::{TraceReader, TraceRecord, TraceOp};
+
+#[cfg(not(target_env = "msvc"))]
+use tikv_jemallocator::Jemalloc;
+
+#[cfg(not(target_env = "msvc"))]
+#[global_allocator]
+static GLOBAL: Jemalloc = Jemalloc;
+
+type Cache = PaperCache<Buffer, Buffer>;
+
+// the cache sizes simulated by default, as fractions of the trace's
+// working set size
+const DEFAULT_SIZE_FRACTIONS: &[f64] = &[0.01, 0.02, 0.05, 0.1, 0.2, 0.3, 0.5, 0.75, 1.0];
+
+/// Replays an access trace recorded with TRACE START against every
+/// configured policy and several cache sizes, and outputs the resulting
+/// miss-ratio curves.
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+	/// Path to the recorded access trace
+	trace: PathBuf,
+
+	/// Optional path to PaperConfig (pconf) file whose policies[] are simulated
+	#[arg(short, long)]
+	config: Option<PathBuf>,
+
+	/// Policies to simulate, overriding the config's policies[]
+	#[arg(short, long, value_delimiter = ',')]
+	policies: Vec<String>,
+
+	/// Cache sizes to simulate (e.g. 64MiB,1GiB), in the scale of the
+	/// sampled trace; defaults to fractions of the trace's working set
+	#[arg(short, long, value_delimiter = ',')]
+	sizes: Vec<String>,
+
+	/// Set the recorded object on a GET miss, for traces without SETs
+	#[arg(long)]
+	fill_on_miss: bool,
+
+	/// Output format of the miss-ratio curves
+	#[arg(short, long, value_enum, default_value_t = Format::Csv)]
+	format: Format,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+	Csv,
+	Json,
+}
+
+struct SimResult {
+	policy: PaperPolicy,
+	size: u64,
+
+	gets: u64,
+	misses: u64,
+}
+
+fn main() {
+	let args = Args::parse();
+
+	let records = match TraceReader::open(&args.trace)
+		.and_then(|reader| reader.collect::<Result<Vec<_>, _>>())
+	{
+		Ok(records) => records,
+
+		Err(err) => {
+			eprintln!("Could not read trace: {err}");
+			return;
+		},
+	};
+
+	let policies = match parse_policies(&args) {
+		Ok(policies) => policies,
+
+		Err(value) => {
+			eprintln!("Invalid policy <{value}>");
+			return;
+		},
+	};
+
+	let sizes = match parse_sizes(&args, &records) {
+		Ok(sizes) => sizes,
+
+		Err(value) => {
+			eprintln!("Invalid size <{value}>");
+			return;
+		},
+	};
+
+	let results = thread::scope(|scope| {
+		let handles = policies
+			.iter()
+			.flat_map(|policy| sizes.iter().map(move |size| (*policy, *size)))
+			.map(|(policy, size)| {
+				let records = &records;
+				scope.spawn(move || simulate(records, policy, size, args.fill_on_miss))
+			})
+			.collect::<Vec<_>>();
+
+		handles
+			.into_iter()
+			.filter_map(|handle| handle.join().ok())
+			.collect::<Result<Vec<_>, _>>()
+	});
+
+	match results {
+		Ok(results) => print!("{}", format_results(&results, args.format)),
+		Err(err) => eprintln!("Could not simulate cache: {err}"),
+	}
+}
+
+fn simulate(
+	records: &[TraceRecord],
+	policy: PaperPolicy,
+	size: u64,
+	fill_on_miss: bool,
+) -> Result<SimResult, CacheError> {
+	let cache = Cache::new(size, &[policy], policy)?;
+
+	let mut gets = 0;
+	let mut misses = 0;
+
+	for record in records {
+		let key = Buffer::from(record.key_hash.to_le_bytes().to_vec());
+
+		match record.op {
+			TraceOp::Get => {
+				gets += 1;
+
+				if cache.get(&key).is_err() {
+					misses += 1;
+
+					if fill_on_miss {
+						set(&cache, key, record.value_size);
+					}
+				}
+			},
+
+			TraceOp::Set => set(&cache, key, record.value_size),
+			TraceOp::Del => { let _ = cache.del(&key); },
+
+			// neither command affects the eviction policy
+			TraceOp::Has | TraceOp::Peek => {},
+		}
+	}
+
+	Ok(SimResult {
+		policy,
+		size,
+
+		gets,
+		misses,
+	})
+}
+
+fn set(cache: &Cache, key: Buffer, value_size: u32) {
+	if value_size == 0 {
+		return;
+	}
+
+	let value = Buffer::from(vec![0; value_size as usize]);
+
+	// objects larger than the simulated cache are rejected by the cache,
+	// which the simulation treats the same as a miss
+	let _ = cache.set(key, value, None);
+}
+
+fn parse_policies(args: &Args) -> Result<Vec<PaperPolicy>, String> {
+	let policy_strs = match (&args.config, args.policies.is_empty()) {
+		(_, false) => args.policies.clone(),
+
+		(Some(path), true) => {
+			let config = fs::read_to_string(path).map_err(|_| path.display().to_string())?;
+			config_policies(&config)
+		},
+
+		(None, true) => config_policies(include_str!("../../default.pconf")),
+	};
+
+	policy_strs
+		.iter()
+		.map(|value| match PaperPolicy::from_str(value) {
+			Ok(policy) if !policy.is_auto() => Ok(policy),
+			_ => Err(value.clone()),
+		})
+		.collect()
+}
+
+/// Returns the values of the policies[] lines of a pconf file.
+fn config_policies(config: &str) -> Vec<String> {
+	config
+		.lines()
+		.map(|line| line.trim())
+		.filter_map(|line| line.strip_prefix("policies[]="))
+		.map(|value| value.trim().to_owned())
+		.collect()
+}
+
+fn parse_sizes(args: &Args, records: &[TraceRecord]) -> Result<Vec<u64>, String> {
+	if !args.sizes.is_empty() {
+		return args.sizes
+			.iter()
+			.map(|value| match parse_size(value) {
+				Ok(size) if size > 0 => Ok(size),
+				_ => Err(value.clone()),
+			})
+			.collect();
+	}
+
+	let mut object_sizes = HashMap::new();
+
+	for record in records.iter().filter(|record| record.value_size > 0) {
+		object_sizes.insert(record.key_hash, record.value_size as u64);
+	}
+
+	let working_set_size = object_sizes.values().sum::<u64>().max(1);
+
+	let mut sizes = DEFAULT_SIZE_FRACTIONS
+		.iter()
+		.map(|fraction| ((working_set_size as f64 * fraction) as u64).max(1))
+		.collect::<Vec<_>>();
+
+	sizes.dedup();
+
+	Ok(sizes)
+}
+
+fn format_results(results: &[SimResult], format: Format) -> String {
+	match format {
+		Format::Csv => {
+			let mut output = String::from("policy,size,gets,misses,miss_ratio\n");
+
+			for result in results {
+				output += &format!(
+					"{},{},{},{},{:.6}\n",
+					result.policy,
+					result.size,
+					result.gets,
+					result.misses,
+					miss_ratio(result),
+				);
+			}
+
+			output
+		},
+
+		Format::Json => {
+			let rows = results
+				.iter()
+				.map(|result| format!(
+					"{{\"policy\":\"{}\",\"size\":{},\"gets\":{},\"misses\":{},\"miss_ratio\":{:.6}}}",
+					result.policy,
+					result.size,
+					result.gets,
+					result.misses,
+					miss_ratio(result),
+				))
+				.collect::<Vec<_>>();
+
+			format!("[{}]\n", rows.join(","))
+		},
+	}
+}
+
+fn miss_ratio(result: &SimResult) -> f64 {
+	match result.gets {
+		0 => 0.0,
+		gets => result.misses as f64 / gets as f64,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const VALUE_SIZE: u32 = 100;
+
+	fn record(key_hash: u64, op: TraceOp) -> TraceRecord {
+		TraceRecord {
+			timestamp: 0,
+			key_hash,
+			value_size: VALUE_SIZE,
+			op,
+		}
+	}
+
+	/// Two objects are set, and three of the five GETs miss: one for a key
+	/// which was never set, one after a DEL and one for a PEEKed key.
+	fn trace() -> Vec<TraceRecord> {
+		vec![
+			record(1, TraceOp::Set),
+			record(2, TraceOp::Set),
+			record(1, TraceOp::Get),
+			record(2, TraceOp::Get),
+			record(3, TraceOp::Get),
+			record(1, TraceOp::Del),
+			record(1, TraceOp::Get),
+			record(4, TraceOp::Peek),
+			record(4, TraceOp::Get),
+		]
+	}
+
+	fn args(extra_args: &[&str]) -> Args {
+		Args::parse_from(["paper-sim", "trace"].iter().chain(extra_args))
+	}
+
+	#[test]
+	fn it_replays_a_trace() {
+		let result = simulate(&trace(), PaperPolicy::Lru, 1 << 20, false).unwrap();
+
+		assert_eq!(result.gets, 5);
+		assert_eq!(result.misses, 3);
+	}
+
+	#[test]
+	fn it_sets_the_object_on_a_miss() {
+		let records = [
+			record(1, TraceOp::Get),
+			record(1, TraceOp::Get),
+			record(2, TraceOp::Get),
+			record(2, TraceOp::Get),
+		];
+
+		let result = simulate(&records, PaperPolicy::Lru, 1 << 20, false).unwrap();
+		assert_eq!(result.misses, 4);
+
+		let result = simulate(&records, PaperPolicy::Lru, 1 << 20, true).unwrap();
+		assert_eq!(result.misses, 2);
+	}
+
+	#[test]
+	fn it_outputs_the_miss_ratio_curves() {
+		let results = [
+			simulate(&trace(), PaperPolicy::Lru, 1000, false).unwrap(),
+			SimResult {
+				policy: PaperPolicy::Fifo,
+				size: 2000,
+
+				gets: 0,
+				misses: 0,
+			},
+		];
+
+		assert_eq!(
+			format_results(&results, Format::Csv),
+			"policy,size,gets,misses,miss_ratio\n\
+			lru,1000,5,3,0.600000\n\
+			fifo,2000,0,0,0.000000\n",
+		);
+
+		assert_eq!(
+			format_results(&results, Format::Json),
+			"[{\"policy\":\"lru\",\"size\":1000,\"gets\":5,\"misses\":3,\"miss_ratio\":0.600000},\
+			{\"policy\":\"fifo\",\"size\":2000,\"gets\":0,\"misses\":0,\"miss_ratio\":0.000000}]\n",
+		);
+	}
+
+	#[test]
+	fn it_sizes_the_caches_by_the_working_set() {
+		let sizes = parse_sizes(&args(&[]), &trace()).unwrap();
+
+		// the working set holds the four keys of the trace
+		assert_eq!(sizes.first(), Some(&4));
+		assert_eq!(sizes.last(), Some(&(4 * VALUE_SIZE as u64)));
+
+		let sizes = parse_sizes(&args(&["--sizes", "1KiB,1MiB"]), &trace()).unwrap();
+		assert_eq!(sizes, [1024, 1 << 20]);
+
+		assert!(parse_sizes(&args(&["--sizes", "0"]), &trace()).is_err());
+	}
+
+	#[test]
+	fn it_parses_the_simulated_policies() {
+		let policies = parse_policies(&args(&["--policies", "lru,fifo"])).unwrap();
+		assert_eq!(policies, [PaperPolicy::Lru, PaperPolicy::Fifo]);
+
+		assert!(parse_policies(&args(&["--policies", "auto"])).is_err());
+		assert!(parse_policies(&args(&["--policies", "other"])).is_err());
+
+		assert_eq!(
+			config_policies("max_size=1MiB\npolicies[]=lru\n  policies[]=sieve\n"),
+			["lru", "sieve"],
+		);
+	}
+}
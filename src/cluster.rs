@@ -0,0 +1,474 @@
+/*
+ * Copyright (c) Kia Shakiba
+ *
+ * This source code is licensed under the GNU AGPLv3 license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::{
+	fmt,
+	str::FromStr,
+	ops::RangeInclusive,
+	sync::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use crate::{
+	error::ServerError,
+	numa,
+};
+
+pub const NUM_SLOTS: usize = 16384;
+
+/// A cluster node as configured with
+/// `cluster_nodes[]=<id>:<host>:<port>:<slots>`, where the slots are a
+/// list of slots and slot ranges such as `0-8191,10000`.
+#[derive(Debug, Clone)]
+pub struct ClusterNodeConfig {
+	id: String,
+	host: String,
+	port: u32,
+
+	slots: Vec<RangeInclusive<u16>>,
+}
+
+/// The keyspace of a cluster, which is split into fixed hash slots. Every
+/// slot is owned by exactly one node, and a node only serves the keys of
+/// the slots it owns. A proxy routes keys with the same slots without
+/// being a node itself.
+///
+/// While slots are migrated, the source node marks them as migrating to
+/// the target node and the target node marks them as importing, until the
+/// ownership of the slots is flipped to the target node.
+pub struct Cluster {
+	nodes: Vec<ClusterNodeConfig>,
+	node_index: Option<usize>,
+
+	slots: RwLock<Slots>,
+}
+
+struct Slots {
+	owners: Box<[usize]>,
+	migrating: Box<[Option<usize>]>,
+	importing: Box<[bool]>,
+}
+
+/// Where the command for a key is handled.
+pub enum Route {
+	Local,
+
+	/// The slot is owned by the node at the contained address.
+	Moved(u16, String),
+
+	/// The slot is migrating to the node at the contained address.
+	Migrating(u16, String),
+}
+
+impl ClusterNodeConfig {
+	pub fn id(&self) -> &str {
+		&self.id
+	}
+
+	pub fn host(&self) -> &str {
+		&self.host
+	}
+
+	pub fn port(&self) -> u32 {
+		self.port
+	}
+
+	pub fn address(&self) -> String {
+		format!("{}:{}", self.host, self.port)
+	}
+
+	pub fn slots(&self) -> &[RangeInclusive<u16>] {
+		&self.slots
+	}
+}
+
+impl FromStr for ClusterNodeConfig {
+	type Err = ServerError;
+
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		let invalid = || ServerError::InvalidConfigCluster(value.into());
+
+		let (id, address) = value.split_once(':').ok_or_else(invalid)?;
+		let (address, slots) = address.rsplit_once(':').ok_or_else(invalid)?;
+		let (host, port) = address.rsplit_once(':').ok_or_else(invalid)?;
+
+		let id = id.trim();
+		let host = host.trim();
+
+		if id.is_empty() || host.is_empty() {
+			return Err(invalid());
+		}
+
+		let port = port.trim().parse::<u32>().map_err(|_| invalid())?;
+
+		let slots = numa::parse_id_list(slots.trim(), NUM_SLOTS).ok_or_else(invalid)?;
+
+		Ok(ClusterNodeConfig {
+			id: id.to_owned(),
+			host: host.to_owned(),
+			port,
+
+			slots: into_ranges(&slots),
+		})
+	}
+}
+
+impl fmt::Display for ClusterNodeConfig {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let slots = self.slots
+			.iter()
+			.map(|range| match range.start() == range.end() {
+				true => range.start().to_string(),
+				false => format!("{}-{}", range.start(), range.end()),
+			})
+			.collect::<Vec<_>>()
+			.join(",");
+
+		write!(f, "{}:{}:{}:{}", self.id, self.host, self.port, slots)
+	}
+}
+
+impl Cluster {
+	/// Builds the cluster from the configured nodes, where `node_id` is the
+	/// ID of this server if it is a node. Every slot must be owned by
+	/// exactly one node.
+	pub fn new(
+		nodes: &[ClusterNodeConfig],
+		node_id: Option<&str>,
+	) -> Result<Self, ServerError> {
+		let node_index = match node_id {
+			Some(node_id) => Some(nodes
+				.iter()
+				.position(|node| node.id() == node_id)
+				.ok_or_else(|| ServerError::InvalidConfigCluster(node_id.into()))?),
+
+			None => None,
+		};
+
+		let mut owners = vec![None; NUM_SLOTS];
+
+		for (index, node) in nodes.iter().enumerate() {
+			for slot in node.slots().iter().cloned().flatten() {
+				let owner = &mut owners[slot as usize];
+
+				if owner.is_some() {
+					return Err(ServerError::InvalidConfigCluster(format!("slot {slot} is owned by several nodes")));
+				}
+
+				*owner = Some(index);
+			}
+		}
+
+		let owners = owners
+			.into_iter()
+			.enumerate()
+			.map(|(slot, owner)| owner.ok_or_else(|| {
+				ServerError::InvalidConfigCluster(format!("slot {slot} is not owned by any node"))
+			}))
+			.collect::<Result<_, _>>()?;
+
+		let slots = Slots {
+			owners,
+			migrating: vec![None; NUM_SLOTS].into(),
+			importing: vec![false; NUM_SLOTS].into(),
+		};
+
+		Ok(Cluster {
+			nodes: nodes.to_vec(),
+			node_index,
+
+			slots: RwLock::new(slots),
+		})
+	}
+
+	pub fn nodes(&self) -> &[ClusterNodeConfig] {
+		&self.nodes
+	}
+
+	/// Returns this server's node, if it is a node.
+	pub fn node(&self) -> Option<&ClusterNodeConfig> {
+		self.node_index.map(|index| &self.nodes[index])
+	}
+
+	pub fn node_index(&self, node_id: &str) -> Option<usize> {
+		self.nodes
+			.iter()
+			.position(|node| node.id() == node_id)
+	}
+
+	pub fn is_self(&self, node: &ClusterNodeConfig) -> bool {
+		self.node().is_some_and(|self_node| self_node.id() == node.id())
+	}
+
+	/// Returns the ranges of slots which the node currently owns.
+	pub fn node_slots(&self, node_index: usize) -> Vec<RangeInclusive<u16>> {
+		let slots = self.read_slots()
+			.owners
+			.iter()
+			.enumerate()
+			.filter(|(_, owner)| **owner == node_index)
+			.map(|(slot, _)| slot)
+			.collect::<Vec<_>>();
+
+		into_ranges(&slots)
+	}
+
+	/// Returns whether this node owns every slot in the range, and none of
+	/// the slots are already migrating.
+	pub fn can_migrate(&self, range: &RangeInclusive<u16>) -> bool {
+		let slots = self.read_slots();
+
+		range.clone().all(|slot| {
+			Some(slots.owners[slot as usize]) == self.node_index
+				&& slots.migrating[slot as usize].is_none()
+		})
+	}
+
+	/// Returns whether this node owns none of the slots in the range, so an
+	/// import of the slots can still be cancelled.
+	pub fn can_cancel_import(&self, range: &RangeInclusive<u16>) -> bool {
+		let slots = self.read_slots();
+
+		range
+			.clone()
+			.all(|slot| Some(slots.owners[slot as usize]) != self.node_index)
+	}
+
+	/// Returns the index of the node which owns the key.
+	pub fn owner(&self, key: &[u8]) -> usize {
+		self.read_slots().owners[key_slot(key) as usize]
+	}
+
+	pub fn route(&self, key: &[u8]) -> Route {
+		let slot = key_slot(key);
+		let slots = self.read_slots();
+
+		let owner = slots.owners[slot as usize];
+
+		if Some(owner) != self.node_index {
+			return match slots.importing[slot as usize] {
+				true => Route::Local,
+				false => Route::Moved(slot, self.nodes[owner].address()),
+			};
+		}
+
+		match slots.migrating[slot as usize] {
+			Some(target) => Route::Migrating(slot, self.nodes[target].address()),
+			None => Route::Local,
+		}
+	}
+
+	/// Marks the slots as migrating from this node to the target node.
+	pub fn set_migrating(&self, range: RangeInclusive<u16>, target: Option<usize>) {
+		let mut slots = self.write_slots();
+
+		for slot in range {
+			slots.migrating[slot as usize] = target;
+		}
+	}
+
+	/// Marks the slots as importing into this node, so that this node serves
+	/// them before it owns them.
+	pub fn set_importing(&self, range: RangeInclusive<u16>, is_importing: bool) {
+		let mut slots = self.write_slots();
+
+		for slot in range {
+			slots.importing[slot as usize] = is_importing;
+		}
+	}
+
+	/// Flips the ownership of the slots to the node, which ends any
+	/// migration of the slots.
+	pub fn set_owner(&self, range: RangeInclusive<u16>, owner: usize) {
+		let mut slots = self.write_slots();
+
+		for slot in range {
+			slots.owners[slot as usize] = owner;
+			slots.migrating[slot as usize] = None;
+			slots.importing[slot as usize] = false;
+		}
+	}
+
+	fn read_slots(&self) -> RwLockReadGuard<'_, Slots> {
+		self.slots
+			.read()
+			.unwrap_or_else(|err| err.into_inner())
+	}
+
+	fn write_slots(&self) -> RwLockWriteGuard<'_, Slots> {
+		self.slots
+			.write()
+			.unwrap_or_else(|err| err.into_inner())
+	}
+}
+
+/// Returns the range of slots from `start` to `end` if it is valid.
+pub fn slot_range(start: u32, end: u32) -> Option<RangeInclusive<u16>> {
+	match start <= end && (end as usize) < NUM_SLOTS {
+		true => Some(start as u16..=end as u16),
+		false => None,
+	}
+}
+
+/// Returns the slot of the key, which is the CRC16 (XMODEM) of the key
+/// modulo the number of slots, so clients can compute it themselves.
+pub fn key_slot(key: &[u8]) -> u16 {
+	(crc16(key) as usize % NUM_SLOTS) as u16
+}
+
+fn crc16(bytes: &[u8]) -> u16 {
+	let mut crc = 0u16;
+
+	for byte in bytes {
+		crc ^= (*byte as u16) << 8;
+
+		for _ in 0..8 {
+			crc = match crc & 0x8000 {
+				0 => crc << 1,
+				_ => (crc << 1) ^ 0x1021,
+			};
+		}
+	}
+
+	crc
+}
+
+/// Collapses a sorted list of slots into ranges of consecutive slots.
+fn into_ranges(slots: &[usize]) -> Vec<RangeInclusive<u16>> {
+	let mut ranges: Vec<RangeInclusive<u16>> = Vec::new();
+
+	for slot in slots.iter().map(|slot| *slot as u16) {
+		match ranges.last_mut() {
+			Some(range) if *range.end() + 1 == slot => *range = *range.start()..=slot,
+			_ => ranges.push(slot..=slot),
+		}
+	}
+
+	ranges
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn it_parses_a_node() {
+		let node = "a:127.0.0.1:3145:0-8191,10000".parse::<ClusterNodeConfig>().unwrap();
+
+		assert_eq!(node.id(), "a");
+		assert_eq!(node.address(), "127.0.0.1:3145");
+		assert_eq!(node.slots(), [0..=8191, 10000..=10000]);
+		assert_eq!(node.to_string(), "a:127.0.0.1:3145:0-8191,10000");
+	}
+
+	#[test]
+	fn it_rejects_an_invalid_node() {
+		for node in ["a:127.0.0.1:3145", ":127.0.0.1:3145:0", "a:127.0.0.1:port:0", "a:127.0.0.1:3145:16384"] {
+			assert!(node.parse::<ClusterNodeConfig>().is_err(), "{node}");
+		}
+	}
+
+	fn two_nodes(node_id: Option<&str>) -> Cluster {
+		let nodes = ["a:127.0.0.1:3145:0-8191", "b:127.0.0.1:3146:8192-16383"]
+			.map(|node| node.parse::<ClusterNodeConfig>().unwrap());
+
+		Cluster::new(&nodes, node_id).unwrap()
+	}
+
+	/// Returns a key whose slot is in the range.
+	fn key_in(range: RangeInclusive<u16>) -> Vec<u8> {
+		(0..)
+			.map(|index| format!("key{index}").into_bytes())
+			.find(|key| range.contains(&key_slot(key)))
+			.unwrap()
+	}
+
+	#[test]
+	fn it_hashes_keys_to_slots() {
+		assert_eq!(key_slot(b"123456789"), 0x31c3);
+		assert_eq!(key_slot(b""), 0);
+
+		assert_eq!(slot_range(10, 20), Some(10..=20));
+		assert_eq!(slot_range(20, 10), None);
+		assert_eq!(slot_range(0, NUM_SLOTS as u32), None);
+	}
+
+	#[test]
+	fn it_requires_every_slot_to_have_one_owner() {
+		let nodes = |configs: &[&str]| configs
+			.iter()
+			.map(|node| node.parse::<ClusterNodeConfig>().unwrap())
+			.collect::<Vec<_>>();
+
+		let missing = nodes(&["a:127.0.0.1:3145:0-8191"]);
+		let overlapping = nodes(&["a:127.0.0.1:3145:0-8192", "b:127.0.0.1:3146:8192-16383"]);
+
+		assert!(Cluster::new(&missing, Some("a")).is_err());
+		assert!(Cluster::new(&overlapping, Some("a")).is_err());
+		assert!(Cluster::new(&nodes(&["a:127.0.0.1:3145:0-16383"]), Some("b")).is_err());
+	}
+
+	#[test]
+	fn it_routes_keys_to_their_owners() {
+		let cluster = two_nodes(Some("a"));
+
+		let local_key = key_in(0..=8191);
+		let moved_key = key_in(8192..=16383);
+
+		assert!(matches!(cluster.route(&local_key), Route::Local));
+		assert!(matches!(
+			cluster.route(&moved_key),
+			Route::Moved(slot, address) if slot == key_slot(&moved_key) && address == "127.0.0.1:3146"
+		));
+
+		assert_eq!(cluster.owner(&local_key), 0);
+		assert_eq!(cluster.owner(&moved_key), 1);
+
+		// a proxy is not a node, so it serves none of the slots
+		assert!(matches!(two_nodes(None).route(&local_key), Route::Moved(..)));
+	}
+
+	#[test]
+	fn it_routes_migrating_slots_to_the_target() {
+		let cluster = two_nodes(Some("a"));
+		let key = key_in(0..=99);
+
+		assert!(cluster.can_migrate(&(0..=99)));
+		assert!(!cluster.can_migrate(&(8000..=8200)));
+
+		cluster.set_migrating(0..=99, Some(1));
+
+		assert!(!cluster.can_migrate(&(0..=99)));
+		assert!(matches!(cluster.route(&key), Route::Migrating(_, address) if address == "127.0.0.1:3146"));
+
+		cluster.set_owner(0..=99, 1);
+
+		assert!(matches!(cluster.route(&key), Route::Moved(_, address) if address == "127.0.0.1:3146"));
+		assert_eq!(cluster.node_slots(0), [100..=8191]);
+		assert_eq!(cluster.node_slots(1), [0..=99, 8192..=16383]);
+	}
+
+	#[test]
+	fn it_serves_importing_slots_before_it_owns_them() {
+		let cluster = two_nodes(Some("b"));
+		let key = key_in(0..=99);
+
+		cluster.set_importing(0..=99, true);
+
+		assert!(matches!(cluster.route(&key), Route::Local));
+		assert!(cluster.can_cancel_import(&(0..=99)));
+
+		cluster.set_owner(0..=99, 1);
+
+		assert!(matches!(cluster.route(&key), Route::Local));
+		assert!(!cluster.can_cancel_import(&(0..=99)));
+
+		cluster.set_importing(100..=199, true);
+		cluster.set_importing(100..=199, false);
+
+		assert!(matches!(cluster.route(&key_in(100..=199)), Route::Moved(..)));
+	}
+}
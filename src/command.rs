@@ -9,9 +9,82 @@ use std::net::TcpStream;
 
 use paper_utils::{
 	stream::{Buffer, StreamReader, StreamError},
+	sheet::{Sheet, SheetBuilder},
 	command::CommandByte,
 };
 
+use crate::expiry::Expiry;
+
+/// Command bytes for commands which are handled by this server but are not
+/// part of the paper-utils protocol.
+pub struct ServerCommandByte;
+
+impl ServerCommandByte {
+	pub const GETS: u8 = 0x20;
+	pub const CAS: u8 = 0x21;
+
+	pub const SETNX: u8 = 0x22;
+	pub const SETXX: u8 = 0x23;
+	pub const GETSET: u8 = 0x24;
+
+	pub const SET_EXPIRY: u8 = 0x25;
+	pub const EXPIRE: u8 = 0x26;
+	pub const PTTL: u8 = 0x27;
+
+	pub const SCAN: u8 = 0x28;
+	pub const DEL_PREFIX: u8 = 0x29;
+
+	pub const SET_TAGGED: u8 = 0x2a;
+	pub const INVALIDATE: u8 = 0x2b;
+
+	pub const TRACE: u8 = 0x2c;
+	pub const MRC: u8 = 0x2d;
+	pub const POLICY_HISTORY: u8 = 0x2e;
+
+	pub const PIN: u8 = 0x2f;
+	pub const UNPIN: u8 = 0x30;
+
+	pub const CLUSTER: u8 = 0x31;
+	pub const RESTORE: u8 = 0x32;
+
+	pub const MGET: u8 = 0x33;
+}
+
+/// The sub-command byte which follows the TRACE command byte.
+pub struct TraceByte;
+
+impl TraceByte {
+	pub const START: u8 = 0;
+	pub const STOP: u8 = 1;
+}
+
+/// The sub-command byte which follows the CLUSTER command byte.
+pub struct ClusterByte;
+
+impl ClusterByte {
+	pub const NODES: u8 = 0;
+
+	pub const MIGRATE: u8 = 1;
+	pub const IMPORT: u8 = 2;
+	pub const SET_SLOTS: u8 = 3;
+	pub const CANCEL_IMPORT: u8 = 4;
+}
+
+/// The kind byte which precedes the value of an expiry in the server's own
+/// commands which set an object or its expiry (e.g., SET_EXPIRY, CAS and
+/// EXPIRE).
+pub struct ExpiryByte;
+
+impl ExpiryByte {
+	pub const NEVER: u8 = 0;
+
+	pub const SECONDS: u8 = 1;
+	pub const MILLIS: u8 = 2;
+
+	pub const UNIX_SECONDS: u8 = 3;
+	pub const UNIX_MILLIS: u8 = 4;
+}
+
 pub enum Command {
 	Ping,
 	Version,
@@ -19,20 +92,64 @@ pub enum Command {
 	Auth(Buffer),
 
 	Get(Buffer),
-	Set(Buffer, Buffer, Option<u32>),
+	Set(Buffer, Buffer, Expiry, Vec<Buffer>),
 	Del(Buffer),
 
+	/// Gets the objects of several keys, responding with whether each
+	/// object was found and its value if it was.
+	MGet(Vec<Buffer>),
+
+	Gets(Buffer),
+	Cas(Buffer, Buffer, u64, Expiry),
+
+	SetNx(Buffer, Buffer, Expiry),
+	SetXx(Buffer, Buffer, Expiry),
+	GetSet(Buffer, Buffer, Expiry),
+
 	Has(Buffer),
 	Peek(Buffer),
-	Ttl(Buffer, Option<u32>),
+	Ttl(Buffer, Expiry),
+	Pttl(Buffer),
 	Size(Buffer),
 
+	Scan(Buffer, Buffer, u32),
+	DelPrefix(Buffer),
+
+	Invalidate(Buffer),
+
+	Pin(Buffer),
+	Unpin(Buffer),
+
 	Wipe,
 
 	Resize(u64),
 	Policy(String),
+	PolicyHistory,
 
 	Status,
+
+	TraceStart(String, f64),
+	TraceStop,
+
+	Mrc,
+
+	ClusterNodes,
+
+	/// Migrates a range of slots to the node with the ID.
+	ClusterMigrate(u32, u32, String),
+
+	/// Marks a range of slots as importing from the node with the ID.
+	ClusterImport(u32, u32, String),
+
+	/// Flips the ownership of a range of slots to the node with the ID.
+	ClusterSetSlots(u32, u32, String),
+
+	/// Cancels the import of a range of slots from the node with the ID.
+	ClusterCancelImport(u32, u32, String),
+
+	/// Sets an object migrated from another node, unless the key was
+	/// written on this node during the migration.
+	Restore(Buffer, Buffer, Expiry, Vec<Buffer>),
 }
 
 impl Command {
@@ -54,15 +171,37 @@ impl Command {
 			},
 
 			CommandByte::SET => {
+				let (key, value, expiry) = read_set_args(&mut reader)?;
+				Ok(Command::Set(key, value, expiry, Vec::new()))
+			},
+
+			ServerCommandByte::MGET => {
+				let num_keys = reader.read_u32()?;
+				let mut keys = Vec::new();
+
+				for _ in 0..num_keys {
+					keys.push(reader.read_buf()?);
+				}
+
+				Ok(Command::MGet(keys))
+			},
+
+			ServerCommandByte::SET_EXPIRY => {
 				let key = reader.read_buf()?;
 				let value = reader.read_buf()?;
+				let expiry = read_expiry(&mut reader)?;
+
+				Ok(Command::Set(key, value, expiry, Vec::new()))
+			},
 
-				let ttl = match reader.read_u32()? {
-					0 => None,
-					value => Some(value),
-				};
+			ServerCommandByte::SET_TAGGED => {
+				let (key, value, expiry, tags) = read_tagged_set_args(&mut reader)?;
+				Ok(Command::Set(key, value, expiry, tags))
+			},
 
-				Ok(Command::Set(key, value, ttl))
+			ServerCommandByte::INVALIDATE => {
+				let tag = reader.read_buf()?;
+				Ok(Command::Invalidate(tag))
 			},
 
 			CommandByte::DEL => {
@@ -70,6 +209,35 @@ impl Command {
 				Ok(Command::Del(key))
 			},
 
+			ServerCommandByte::GETS => {
+				let key = reader.read_buf()?;
+				Ok(Command::Gets(key))
+			},
+
+			ServerCommandByte::CAS => {
+				let key = reader.read_buf()?;
+				let value = reader.read_buf()?;
+				let version = reader.read_u64()?;
+				let expiry = read_expiry(&mut reader)?;
+
+				Ok(Command::Cas(key, value, version, expiry))
+			},
+
+			ServerCommandByte::SETNX => {
+				let (key, value, expiry) = read_expiring_set_args(&mut reader)?;
+				Ok(Command::SetNx(key, value, expiry))
+			},
+
+			ServerCommandByte::SETXX => {
+				let (key, value, expiry) = read_expiring_set_args(&mut reader)?;
+				Ok(Command::SetXx(key, value, expiry))
+			},
+
+			ServerCommandByte::GETSET => {
+				let (key, value, expiry) = read_expiring_set_args(&mut reader)?;
+				Ok(Command::GetSet(key, value, expiry))
+			},
+
 			CommandByte::HAS => {
 				let key = reader.read_buf()?;
 				Ok(Command::Has(key))
@@ -82,13 +250,21 @@ impl Command {
 
 			CommandByte::TTL => {
 				let key = reader.read_buf()?;
+				let expiry = read_ttl(&mut reader)?;
+
+				Ok(Command::Ttl(key, expiry))
+			},
+
+			ServerCommandByte::EXPIRE => {
+				let key = reader.read_buf()?;
+				let expiry = read_expiry(&mut reader)?;
 
-				let ttl = match reader.read_u32()? {
-					0 => None,
-					value => Some(value),
-				};
+				Ok(Command::Ttl(key, expiry))
+			},
 
-				Ok(Command::Ttl(key, ttl))
+			ServerCommandByte::PTTL => {
+				let key = reader.read_buf()?;
+				Ok(Command::Pttl(key))
 			},
 
 			CommandByte::SIZE => {
@@ -96,6 +272,29 @@ impl Command {
 				Ok(Command::Size(key))
 			},
 
+			ServerCommandByte::SCAN => {
+				let cursor = reader.read_buf()?;
+				let prefix = reader.read_buf()?;
+				let count = reader.read_u32()?;
+
+				Ok(Command::Scan(cursor, prefix, count))
+			},
+
+			ServerCommandByte::DEL_PREFIX => {
+				let prefix = reader.read_buf()?;
+				Ok(Command::DelPrefix(prefix))
+			},
+
+			ServerCommandByte::PIN => {
+				let key = reader.read_buf()?;
+				Ok(Command::Pin(key))
+			},
+
+			ServerCommandByte::UNPIN => {
+				let key = reader.read_buf()?;
+				Ok(Command::Unpin(key))
+			},
+
 			CommandByte::WIPE => Ok(Command::Wipe),
 
 			CommandByte::RESIZE => {
@@ -108,9 +307,492 @@ impl Command {
 				Ok(Command::Policy(policy_str))
 			},
 
+			ServerCommandByte::POLICY_HISTORY => Ok(Command::PolicyHistory),
+
 			CommandByte::STATUS => Ok(Command::Status),
 
+			ServerCommandByte::TRACE => match reader.read_u8()? {
+				TraceByte::START => {
+					let name = reader.read_string()?;
+					let sample_rate = reader.read_f64()?;
+
+					Ok(Command::TraceStart(name, sample_rate))
+				},
+
+				TraceByte::STOP => Ok(Command::TraceStop),
+
+				_ => Err(StreamError::InvalidData),
+			},
+
+			ServerCommandByte::MRC => Ok(Command::Mrc),
+
+			ServerCommandByte::CLUSTER => match reader.read_u8()? {
+				ClusterByte::NODES => Ok(Command::ClusterNodes),
+
+				ClusterByte::MIGRATE => {
+					let (start, end, node_id) = read_slot_range(&mut reader)?;
+					Ok(Command::ClusterMigrate(start, end, node_id))
+				},
+
+				ClusterByte::IMPORT => {
+					let (start, end, node_id) = read_slot_range(&mut reader)?;
+					Ok(Command::ClusterImport(start, end, node_id))
+				},
+
+				ClusterByte::SET_SLOTS => {
+					let (start, end, node_id) = read_slot_range(&mut reader)?;
+					Ok(Command::ClusterSetSlots(start, end, node_id))
+				},
+
+				ClusterByte::CANCEL_IMPORT => {
+					let (start, end, node_id) = read_slot_range(&mut reader)?;
+					Ok(Command::ClusterCancelImport(start, end, node_id))
+				},
+
+				_ => Err(StreamError::InvalidData),
+			},
+
+			ServerCommandByte::RESTORE => {
+				let (key, value, expiry, tags) = read_tagged_set_args(&mut reader)?;
+				Ok(Command::Restore(key, value, expiry, tags))
+			},
+
 			_ => Err(StreamError::InvalidData),
 		}
 	}
+
+	/// Serializes the command in the format read by `from_stream`, so that
+	/// it can be forwarded to another server.
+	pub fn to_sheet(&self) -> Sheet {
+		let builder = SheetBuilder::new();
+
+		let builder = match self {
+			Command::Ping => builder.write_u8(CommandByte::PING),
+			Command::Version => builder.write_u8(CommandByte::VERSION),
+
+			Command::Auth(token) => builder
+				.write_u8(CommandByte::AUTH)
+				.write_buf(token),
+
+			Command::Get(key) => builder
+				.write_u8(CommandByte::GET)
+				.write_buf(key),
+
+			Command::Set(key, value, expiry, tags) if tags.is_empty() => {
+				let builder = builder
+					.write_u8(ServerCommandByte::SET_EXPIRY)
+					.write_buf(key)
+					.write_buf(value);
+
+				write_expiry(builder, *expiry)
+			},
+
+			Command::Set(key, value, expiry, tags) => write_tagged_set_args(
+				builder.write_u8(ServerCommandByte::SET_TAGGED),
+				key,
+				value,
+				*expiry,
+				tags,
+			),
+
+			Command::Del(key) => builder
+				.write_u8(CommandByte::DEL)
+				.write_buf(key),
+
+			Command::MGet(keys) => keys
+				.iter()
+				.fold(
+					builder
+						.write_u8(ServerCommandByte::MGET)
+						.write_u32(keys.len() as u32),
+					|builder, key| builder.write_buf(key),
+				),
+
+			Command::Gets(key) => builder
+				.write_u8(ServerCommandByte::GETS)
+				.write_buf(key),
+
+			Command::Cas(key, value, version, expiry) => {
+				let builder = builder
+					.write_u8(ServerCommandByte::CAS)
+					.write_buf(key)
+					.write_buf(value)
+					.write_u64(*version);
+
+				write_expiry(builder, *expiry)
+			},
+
+			Command::SetNx(key, value, expiry) => write_set_args(
+				builder.write_u8(ServerCommandByte::SETNX),
+				key,
+				value,
+				*expiry,
+			),
+
+			Command::SetXx(key, value, expiry) => write_set_args(
+				builder.write_u8(ServerCommandByte::SETXX),
+				key,
+				value,
+				*expiry,
+			),
+
+			Command::GetSet(key, value, expiry) => write_set_args(
+				builder.write_u8(ServerCommandByte::GETSET),
+				key,
+				value,
+				*expiry,
+			),
+
+			Command::Has(key) => builder
+				.write_u8(CommandByte::HAS)
+				.write_buf(key),
+
+			Command::Peek(key) => builder
+				.write_u8(CommandByte::PEEK)
+				.write_buf(key),
+
+			Command::Ttl(key, expiry) => {
+				let builder = builder
+					.write_u8(ServerCommandByte::EXPIRE)
+					.write_buf(key);
+
+				write_expiry(builder, *expiry)
+			},
+
+			Command::Pttl(key) => builder
+				.write_u8(ServerCommandByte::PTTL)
+				.write_buf(key),
+
+			Command::Size(key) => builder
+				.write_u8(CommandByte::SIZE)
+				.write_buf(key),
+
+			Command::Scan(cursor, prefix, count) => builder
+				.write_u8(ServerCommandByte::SCAN)
+				.write_buf(cursor)
+				.write_buf(prefix)
+				.write_u32(*count),
+
+			Command::DelPrefix(prefix) => builder
+				.write_u8(ServerCommandByte::DEL_PREFIX)
+				.write_buf(prefix),
+
+			Command::Invalidate(tag) => builder
+				.write_u8(ServerCommandByte::INVALIDATE)
+				.write_buf(tag),
+
+			Command::Pin(key) => builder
+				.write_u8(ServerCommandByte::PIN)
+				.write_buf(key),
+
+			Command::Unpin(key) => builder
+				.write_u8(ServerCommandByte::UNPIN)
+				.write_buf(key),
+
+			Command::Wipe => builder.write_u8(CommandByte::WIPE),
+
+			Command::Resize(size) => builder
+				.write_u8(CommandByte::RESIZE)
+				.write_u64(*size),
+
+			Command::Policy(policy_str) => builder
+				.write_u8(CommandByte::POLICY)
+				.write_str(policy_str.clone()),
+
+			Command::PolicyHistory => builder.write_u8(ServerCommandByte::POLICY_HISTORY),
+
+			Command::Status => builder.write_u8(CommandByte::STATUS),
+
+			Command::TraceStart(name, sample_rate) => builder
+				.write_u8(ServerCommandByte::TRACE)
+				.write_u8(TraceByte::START)
+				.write_str(name.clone())
+				.write_f64(*sample_rate),
+
+			Command::TraceStop => builder
+				.write_u8(ServerCommandByte::TRACE)
+				.write_u8(TraceByte::STOP),
+
+			Command::Mrc => builder.write_u8(ServerCommandByte::MRC),
+
+			Command::ClusterNodes => builder
+				.write_u8(ServerCommandByte::CLUSTER)
+				.write_u8(ClusterByte::NODES),
+
+			Command::ClusterMigrate(start, end, node_id) => write_slot_range(
+				builder
+					.write_u8(ServerCommandByte::CLUSTER)
+					.write_u8(ClusterByte::MIGRATE),
+				*start,
+				*end,
+				node_id,
+			),
+
+			Command::ClusterImport(start, end, node_id) => write_slot_range(
+				builder
+					.write_u8(ServerCommandByte::CLUSTER)
+					.write_u8(ClusterByte::IMPORT),
+				*start,
+				*end,
+				node_id,
+			),
+
+			Command::ClusterSetSlots(start, end, node_id) => write_slot_range(
+				builder
+					.write_u8(ServerCommandByte::CLUSTER)
+					.write_u8(ClusterByte::SET_SLOTS),
+				*start,
+				*end,
+				node_id,
+			),
+
+			Command::ClusterCancelImport(start, end, node_id) => write_slot_range(
+				builder
+					.write_u8(ServerCommandByte::CLUSTER)
+					.write_u8(ClusterByte::CANCEL_IMPORT),
+				*start,
+				*end,
+				node_id,
+			),
+
+			Command::Restore(key, value, expiry, tags) => write_tagged_set_args(
+				builder.write_u8(ServerCommandByte::RESTORE),
+				key,
+				value,
+				*expiry,
+				tags,
+			),
+		};
+
+		builder.into_sheet()
+	}
+
+	/// Returns the key of the command if it reads or writes a single key.
+	pub fn key(&self) -> Option<&Buffer> {
+		match self {
+			Command::Get(key)
+				| Command::Set(key, _, _, _)
+				| Command::Del(key)
+				| Command::Gets(key)
+				| Command::Cas(key, _, _, _)
+				| Command::SetNx(key, _, _)
+				| Command::SetXx(key, _, _)
+				| Command::GetSet(key, _, _)
+				| Command::Has(key)
+				| Command::Peek(key)
+				| Command::Ttl(key, _)
+				| Command::Pttl(key)
+				| Command::Size(key)
+				| Command::Pin(key)
+				| Command::Unpin(key)
+				| Command::Restore(key, _, _, _) => Some(key),
+
+			_ => None,
+		}
+	}
+
+	/// Returns whether the command only reads its key.
+	pub fn is_read(&self) -> bool {
+		matches!(
+			self,
+			Command::Get(_)
+				| Command::Gets(_)
+				| Command::Has(_)
+				| Command::Peek(_)
+				| Command::Pttl(_)
+				| Command::Size(_)
+		)
+	}
+}
+
+fn read_set_args(
+	reader: &mut StreamReader,
+) -> Result<(Buffer, Buffer, Expiry), StreamError> {
+	let key = reader.read_buf()?;
+	let value = reader.read_buf()?;
+	let expiry = read_ttl(reader)?;
+
+	Ok((key, value, expiry))
+}
+
+/// Reads the arguments of the server's own set commands, which carry any
+/// expiry rather than only a TTL in seconds.
+fn read_expiring_set_args(
+	reader: &mut StreamReader,
+) -> Result<(Buffer, Buffer, Expiry), StreamError> {
+	let key = reader.read_buf()?;
+	let value = reader.read_buf()?;
+	let expiry = read_expiry(reader)?;
+
+	Ok((key, value, expiry))
+}
+
+/// Reads a TTL in seconds, where zero means the object never expires.
+fn read_ttl(reader: &mut StreamReader) -> Result<Expiry, StreamError> {
+	let expiry = match reader.read_u32()? {
+		0 => Expiry::Never,
+		value => Expiry::Seconds(value as u64),
+	};
+
+	Ok(expiry)
+}
+
+fn read_tagged_set_args(
+	reader: &mut StreamReader,
+) -> Result<(Buffer, Buffer, Expiry, Vec<Buffer>), StreamError> {
+	let key = reader.read_buf()?;
+	let value = reader.read_buf()?;
+	let expiry = read_expiry(reader)?;
+
+	let num_tags = reader.read_u32()?;
+	let mut tags = Vec::new();
+
+	for _ in 0..num_tags {
+		tags.push(reader.read_buf()?);
+	}
+
+	Ok((key, value, expiry, tags))
+}
+
+fn read_slot_range(
+	reader: &mut StreamReader,
+) -> Result<(u32, u32, String), StreamError> {
+	let start = reader.read_u32()?;
+	let end = reader.read_u32()?;
+	let node_id = reader.read_string()?;
+
+	Ok((start, end, node_id))
+}
+
+fn write_set_args(
+	builder: SheetBuilder,
+	key: &[u8],
+	value: &[u8],
+	expiry: Expiry,
+) -> SheetBuilder {
+	let builder = builder
+		.write_buf(key)
+		.write_buf(value);
+
+	write_expiry(builder, expiry)
+}
+
+fn write_tagged_set_args(
+	builder: SheetBuilder,
+	key: &[u8],
+	value: &[u8],
+	expiry: Expiry,
+	tags: &[Buffer],
+) -> SheetBuilder {
+	let builder = builder
+		.write_buf(key)
+		.write_buf(value);
+
+	let mut builder = write_expiry(builder, expiry)
+		.write_u32(tags.len() as u32);
+
+	for tag in tags {
+		builder = builder.write_buf(tag);
+	}
+
+	builder
+}
+
+fn write_slot_range(
+	builder: SheetBuilder,
+	start: u32,
+	end: u32,
+	node_id: &str,
+) -> SheetBuilder {
+	builder
+		.write_u32(start)
+		.write_u32(end)
+		.write_str(node_id.to_owned())
+}
+
+fn read_expiry(reader: &mut StreamReader) -> Result<Expiry, StreamError> {
+	let kind = reader.read_u8()?;
+	let value = reader.read_u64()?;
+
+	match kind {
+		ExpiryByte::NEVER => Ok(Expiry::Never),
+
+		// a zero relative expiry would remove the object as soon as it is
+		// written, and is rejected the same as `ex 0` and `px 0` are
+		ExpiryByte::SECONDS | ExpiryByte::MILLIS if value == 0 => Err(StreamError::InvalidData),
+
+		ExpiryByte::SECONDS => Ok(Expiry::Seconds(value)),
+		ExpiryByte::MILLIS => Ok(Expiry::Millis(value)),
+
+		ExpiryByte::UNIX_SECONDS => Ok(Expiry::UnixSeconds(value)),
+		ExpiryByte::UNIX_MILLIS => Ok(Expiry::UnixMillis(value)),
+
+		_ => Err(StreamError::InvalidData),
+	}
+}
+
+fn write_expiry(builder: SheetBuilder, expiry: Expiry) -> SheetBuilder {
+	let (kind, value) = match expiry {
+		Expiry::Never => (ExpiryByte::NEVER, 0),
+
+		Expiry::Seconds(value) => (ExpiryByte::SECONDS, value),
+		Expiry::Millis(value) => (ExpiryByte::MILLIS, value),
+
+		Expiry::UnixSeconds(value) => (ExpiryByte::UNIX_SECONDS, value),
+		Expiry::UnixMillis(value) => (ExpiryByte::UNIX_MILLIS, value),
+	};
+
+	builder
+		.write_u8(kind)
+		.write_u64(value)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{io::Write, net::TcpListener};
+	use super::*;
+
+	/// Writes the command to a connected stream and reads it back.
+	fn round_trip(command: &Command) -> Result<Command, StreamError> {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+		let (mut server, _) = listener.accept().unwrap();
+
+		client.write_all(command.to_sheet().serialize()).unwrap();
+		drop(client);
+
+		Command::from_stream(&mut server)
+	}
+
+	fn expire(expiry: Expiry) -> Command {
+		Command::Ttl(Buffer::from(b"key".as_slice()), expiry)
+	}
+
+	#[test]
+	fn it_round_trips_every_expiry() {
+		let expiries = [
+			Expiry::Never,
+			Expiry::Seconds(10),
+			Expiry::Millis(1500),
+			Expiry::UnixSeconds(1_700_000_000),
+			Expiry::UnixMillis(1_700_000_000_000),
+		];
+
+		for expiry in expiries {
+			assert!(matches!(round_trip(&expire(expiry)), Ok(Command::Ttl(_, read)) if read == expiry));
+
+			let set = Command::SetNx(Buffer::from(b"key".as_slice()), Buffer::from(b"value".as_slice()), expiry);
+			assert!(matches!(round_trip(&set), Ok(Command::SetNx(_, _, read)) if read == expiry));
+		}
+	}
+
+	#[test]
+	fn it_rejects_a_zero_relative_expiry() {
+		assert!(matches!(round_trip(&expire(Expiry::Seconds(0))), Err(StreamError::InvalidData)));
+		assert!(matches!(round_trip(&expire(Expiry::Millis(0))), Err(StreamError::InvalidData)));
+
+		// a zero TTL in seconds means the object never expires
+		let set = Command::Set(Buffer::from(b"key".as_slice()), Buffer::from(b"value".as_slice()), Expiry::Never, Vec::new());
+		assert!(matches!(round_trip(&set), Ok(Command::Set(_, _, Expiry::Never, _))));
+	}
 }
@@ -7,69 +7,338 @@
 
 use std::{
 	env,
+	fs,
+	fmt,
 	include_str,
+	borrow::Cow,
 	str::FromStr,
-	path::Path,
+	path::{Path, PathBuf},
 	hash::{DefaultHasher, Hash, Hasher},
 };
 
 use parse_size::parse_size;
+use serde::{Serialize, Serializer, Deserialize, Deserializer, de};
+use schemars::{JsonSchema, Schema, SchemaGenerator};
+use log::warn;
+use paper_cache::PaperPolicy;
 
-use kwik::file::{
-	FileReader,
-	text::TextReader,
+use crate::{
+	error::ServerError,
+	tier::TierConfig,
+	namespace::NamespaceConfig,
+	cluster::{Cluster, ClusterNodeConfig},
+	admission::AdmissionPolicy,
+	numa::{self, MemoryPolicy, Topology},
+	memory_limit::{self, MemorySize, RelativeMaxSize},
 };
 
-use paper_cache::PaperPolicy;
-use crate::error::ServerError;
+const DEFAULT_CONFIG: &str = include_str!("../default.pconf");
+
+// the keys of a config, where the keys of lists end with []
+const CONFIG_KEYS: &[&str] = &[
+	"host",
+	"port",
+
+	"max_size",
+	"max_size_headroom",
+	"policies[]",
+	"policy",
+	"tiers[]",
+	"namespaces[]",
+	"admission",
+	"mrc_sample_rate",
+	"trace_dir",
+	"max_pinned_size",
+
+	"max_connections",
+	"auth_token",
+
+	"cluster_node",
+	"cluster_nodes[]",
+	"cluster_auth_token",
+
+	"worker_cpus",
+	"worker_nodes",
+	"memory_nodes",
+	"memory_policy",
+];
+
+const ENV_PREFIX: &str = "PAPER_";
 
 #[derive(Debug)]
 pub struct Config {
 	host: String,
 	port: u32,
 
-	max_size: u64,
+	max_size: MemorySize,
+	max_size_headroom: MemorySize,
+	resolved_max_size: u64,
 	policies: Vec<PaperPolicy>,
 	policy: PaperPolicy,
+	tiers: Vec<TierConfig>,
+	namespaces: Vec<NamespaceConfig>,
+	admission: AdmissionPolicy,
+	mrc_sample_rate: Option<f64>,
+	trace_dir: Option<PathBuf>,
+	max_pinned_size: u64,
 
 	max_connections: usize,
-	auth_token: Option<u64>,
+	auth_token: Option<String>,
+
+	cluster_node: Option<String>,
+	cluster_nodes: Vec<ClusterNodeConfig>,
+	cluster_auth_token: Option<String>,
+
+	worker_cpus: Option<Vec<usize>>,
+	worker_nodes: Option<Vec<u32>>,
+	memory_nodes: Vec<u32>,
+	memory_policy: MemoryPolicy,
+}
+
+/// The formats of a config file. The TOML, YAML and JSON formats have the
+/// same keys and values as the pconf format, except that list keys such as
+/// `policies[]` are written as lists without the brackets.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigFormat {
+	Pconf,
+	Toml,
+	Yaml,
+	Json,
+}
+
+/// Builds a config programmatically. Every key which is not set takes the
+/// value of the default config, and the config is validated the same way
+/// as a config file when it is built.
+#[derive(Default)]
+pub struct ConfigBuilder {
+	file: ConfigFile,
+}
+
+/// A problem found while checking a config, at the 1-based line and column
+/// where it was found, if it could be located.
+#[derive(Debug)]
+pub struct ConfigIssue {
+	pub position: Option<(usize, usize)>,
+	pub kind: ConfigIssueKind,
+}
+
+#[derive(Debug)]
+pub enum ConfigIssueKind {
+	Error(ServerError),
+	Warning(String),
+}
+
+/// Values which replace those of a config, such as the values of command
+/// line flags and `PAPER_` environment variables. Every value is in the
+/// same form as in a pconf file, and the values of a list key replace the
+/// whole list.
+#[derive(Debug, Default)]
+pub struct ConfigOverrides {
+	overrides: Vec<ConfigOverride>,
+}
+
+#[derive(Debug)]
+struct ConfigOverride {
+	key: String,
+	origin: String,
+	values: Vec<String>,
+}
+
+/// A key and value of a config, along with the line and columns at which
+/// the key and value were found, if they were read from a config file, or
+/// the origin of the value if it is an override.
+struct ConfigEntry {
+	key: String,
+	value: String,
+
+	line: Option<usize>,
+	key_column: usize,
+	value_column: usize,
+
+	origin: Option<String>,
+	is_default: bool,
+}
+
+/// A config as it is written in the TOML, YAML and JSON formats. Every
+/// value is held in the same form as in a pconf file, so it is parsed by
+/// the same functions.
+#[derive(Default, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+	/// The host on which the server listens.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	host: Option<String>,
+
+	/// The port on which the server listens.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	port: Option<u32>,
+
+	/// The maximum size of the cache, in bytes, as a size such as `32GiB`,
+	/// as a percentage of the available memory such as `80%`, or `auto`.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	max_size: Option<ConfigSize>,
+
+	/// The memory which is not used by a relative max size, in bytes, as a
+	/// size such as `1GiB` or as a percentage of the memory limit.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	max_size_headroom: Option<ConfigSize>,
+
+	/// The configured eviction policies of the cache.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	policies: Option<Vec<String>>,
+
+	/// The initial eviction policy of the cache.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	policy: Option<String>,
+
+	/// The memory tiers of the cache, such as `dram:32GiB`.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	tiers: Option<Vec<String>>,
+
+	/// The namespaces of the cache, such as
+	/// `sessions:20GiB,prefix=session:,policy=lru`.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	namespaces: Option<Vec<String>>,
+
+	/// The admission policy of the cache (`none` or `tinylfu`).
+	#[serde(skip_serializing_if = "Option::is_none")]
+	admission: Option<String>,
+
+	/// The fraction of keys replayed against the miss-ratio curve shadow
+	/// caches.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	mrc_sample_rate: Option<f64>,
+
+	/// The directory in which TRACE writes its trace files.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	trace_dir: Option<String>,
+
+	/// The maximum total size of the pinned objects.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	max_pinned_size: Option<ConfigSize>,
+
+	/// The maximum number of concurrent connections.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	max_connections: Option<usize>,
+
+	/// The token which clients must supply to send commands.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	auth_token: Option<String>,
+
+	/// The ID of this server in the cluster.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	cluster_node: Option<String>,
+
+	/// The nodes of the cluster, such as `node-a:10.0.0.1:3145:0-8191`.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	cluster_nodes: Option<Vec<String>>,
+
+	/// The token with which a proxy or a migrating node authorizes its
+	/// connections to the cluster's nodes.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	cluster_auth_token: Option<String>,
+
+	/// The CPUs to which the worker threads are pinned, such as `0-15,32-47`.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	worker_cpus: Option<String>,
+
+	/// The NUMA nodes to whose CPUs the worker threads are pinned.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	worker_nodes: Option<String>,
+
+	/// The NUMA nodes on which the cache's memory is allocated.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	memory_nodes: Option<String>,
+
+	/// The policy with which the cache's memory is placed on its NUMA nodes
+	/// (`bind`, `preferred` or `interleave`).
+	#[serde(skip_serializing_if = "Option::is_none")]
+	memory_policy: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum ConfigSize {
+	Bytes(u64),
+	Text(String),
 }
 
 enum ConfigValue {
 	Host(String),
 	Port(u32),
 
-	MaxSize(u64),
+	MaxSize(MemorySize),
+	MaxSizeHeadroom(MemorySize),
 	PoliciesItem(PaperPolicy),
 	Policy(PaperPolicy),
+	TiersItem(TierConfig),
+	NamespacesItem(NamespaceConfig),
+	Admission(AdmissionPolicy),
+	MrcSampleRate(f64),
+	TraceDir(PathBuf),
+	MaxPinnedSize(u64),
 
 	MaxConnections(usize),
-	AuthToken(u64),
+	AuthToken(String),
+
+	ClusterNode(String),
+	ClusterNodesItem(ClusterNodeConfig),
+	ClusterAuthToken(String),
+
+	WorkerCpus(Vec<usize>),
+	WorkerNodes(Vec<u32>),
+	MemoryNodes(Vec<u32>),
+	MemoryPolicy(MemoryPolicy),
 }
 
 impl Config {
+	/// Loads a config file, whose format is determined by its extension.
 	pub fn from_file<P>(path: P) -> Result<Self, ServerError>
 	where
 		P: AsRef<Path>,
 	{
-		let reader = match TextReader::from_path(path) {
-			Ok(reader) => reader,
-			Err(_) => return Err(ServerError::InvalidConfig),
-		};
+		Config::load(Some(path.as_ref()), &ConfigOverrides::default())
+	}
 
-		let mut config = init_uninitialized_config();
+	/// Loads a config file, or the default config if no file is supplied,
+	/// and replaces its values with the overrides.
+	pub fn load(path: Option<&Path>, overrides: &ConfigOverrides) -> Result<Self, ServerError> {
+		let (config, issues) = Config::check_source(path, overrides)?;
+		Config::from_checked(config, issues)
+	}
 
-		let file_iter = reader
-			.into_iter()
-			.map(|line| line.trim().to_owned())
-			.filter(|line| !line.is_empty() && !line.starts_with('#'));
+	/// Parses a config in the supplied format. Keys which are not set take
+	/// the values of the default config. The first error found in the
+	/// config is returned, and any warnings are logged.
+	pub fn parse(data: &str, format: ConfigFormat) -> Result<Self, ServerError> {
+		let (config, issues) = Config::check_data(data, format, &ConfigOverrides::default());
+		Config::from_checked(config, issues)
+	}
 
-		for line in file_iter {
-			Config::parse_line(&mut config, &line)?;
-		}
+	/// Checks a config file without loading it, returning every problem
+	/// found in it. An error is only returned if the file cannot be read.
+	pub fn check_file<P>(path: P) -> Result<Vec<ConfigIssue>, ServerError>
+	where
+		P: AsRef<Path>,
+	{
+		Config::check_with_overrides(Some(path.as_ref()), &ConfigOverrides::default())
+	}
+
+	/// Checks a config file, or the default config if no file is supplied,
+	/// whose values are replaced by the overrides, returning every problem
+	/// found in them.
+	pub fn check_with_overrides(
+		path: Option<&Path>,
+		overrides: &ConfigOverrides,
+	) -> Result<Vec<ConfigIssue>, ServerError> {
+		Config::check_source(path, overrides).map(|(_, issues)| issues)
+	}
 
-		Ok(config)
+	/// Checks a config in the supplied format, returning every problem
+	/// found in it.
+	pub fn check(data: &str, format: ConfigFormat) -> Vec<ConfigIssue> {
+		let (_, issues) = Config::check_data(data, format, &ConfigOverrides::default());
+		issues
 	}
 
 	pub fn host(&self) -> &str {
@@ -80,8 +349,34 @@ impl Config {
 		self.port
 	}
 
+	/// Returns the maximum size of the cache. If memory tiers are
+	/// configured, this is the size of the dram tier, and if the max size is
+	/// relative, this is the size it resolved to when the config was loaded.
 	pub fn max_size(&self) -> u64 {
-		self.max_size
+		self.tiers
+			.iter()
+			.find(|tier| tier.is_dram())
+			.map(|tier| tier.size())
+			.unwrap_or(self.resolved_max_size)
+	}
+
+	/// Returns the max size if it is relative to the memory available to the
+	/// server, so that it can be resolved again when the memory limit
+	/// changes. The memory of the pinned objects and the namespaces, which
+	/// are held in addition to the cache, is not available to the cache.
+	pub fn relative_max_size(&self) -> Option<RelativeMaxSize> {
+		let has_dram_tier = self.tiers.iter().any(|tier| tier.is_dram());
+
+		if !self.max_size.is_relative() || has_dram_tier {
+			return None;
+		}
+
+		let reserved = self.max_pinned_size + self.namespaces
+			.iter()
+			.map(|namespace| namespace.size())
+			.sum::<u64>();
+
+		Some(RelativeMaxSize::new(self.max_size, self.max_size_headroom, reserved))
 	}
 
 	pub fn policies(&self) -> &[PaperPolicy] {
@@ -92,36 +387,333 @@ impl Config {
 		self.policy
 	}
 
+	pub fn tiers(&self) -> &[TierConfig] {
+		&self.tiers
+	}
+
+	pub fn namespaces(&self) -> &[NamespaceConfig] {
+		&self.namespaces
+	}
+
+	pub fn admission(&self) -> AdmissionPolicy {
+		self.admission
+	}
+
+	/// Returns the fraction of keys sampled by the shadow caches which
+	/// estimate miss-ratio curves, if they are enabled.
+	pub fn mrc_sample_rate(&self) -> Option<f64> {
+		self.mrc_sample_rate
+	}
+
+	/// Returns the directory in which TRACE writes its trace files. TRACE
+	/// is disabled if no directory is configured.
+	pub fn trace_dir(&self) -> Option<&Path> {
+		self.trace_dir.as_deref()
+	}
+
+	/// Returns the maximum total size of the pinned objects, which are
+	/// held outside of the cache.
+	pub fn max_pinned_size(&self) -> u64 {
+		self.max_pinned_size
+	}
+
 	pub fn max_connections(&self) -> usize {
 		self.max_connections
 	}
 
+	/// Returns the hash of the token which clients must supply to send
+	/// commands, if authorization is enabled.
 	pub fn auth_token(&self) -> Option<u64> {
-		self.auth_token
+		self.auth_token.as_deref().map(hash_token)
+	}
+
+	/// Returns the ID of this server in the cluster, if cluster mode is
+	/// enabled.
+	pub fn cluster_node(&self) -> Option<&str> {
+		self.cluster_node.as_deref()
+	}
+
+	pub fn cluster_nodes(&self) -> &[ClusterNodeConfig] {
+		&self.cluster_nodes
+	}
+
+	/// Returns the token with which a proxy or a migrating node authorizes
+	/// its connections to the cluster nodes.
+	pub fn cluster_auth_token(&self) -> Option<&str> {
+		self.cluster_auth_token.as_deref()
+	}
+
+	pub fn worker_cpus(&self) -> Option<&[usize]> {
+		self.worker_cpus.as_deref()
+	}
+
+	pub fn worker_nodes(&self) -> Option<&[u32]> {
+		self.worker_nodes.as_deref()
+	}
+
+	pub fn memory_nodes(&self) -> &[u32] {
+		&self.memory_nodes
+	}
+
+	pub fn memory_policy(&self) -> MemoryPolicy {
+		self.memory_policy
+	}
+
+	/// Builds a config from the values of a TOML, YAML or JSON config or of
+	/// a builder, where every key which is not set takes the values of the
+	/// default config.
+	fn from_file_values(values: Vec<(&str, Vec<String>)>) -> Result<Self, ServerError> {
+		let entries = file_entries(values, None);
+		let (config, issues) = Config::check_entries(&entries);
+
+		Config::from_checked(config, issues)
+	}
+
+	fn from_checked(config: Config, issues: Vec<ConfigIssue>) -> Result<Self, ServerError> {
+		for issue in &issues {
+			if let ConfigIssueKind::Warning(message) = &issue.kind {
+				match issue.position {
+					Some((line, column)) => warn!("config line {line}, column {column}: {message}"),
+					None => warn!("config: {message}"),
+				}
+			}
+		}
+
+		let error = issues
+			.into_iter()
+			.find_map(|issue| match issue.kind {
+				ConfigIssueKind::Error(err) => Some((issue.position, err)),
+				ConfigIssueKind::Warning(_) => None,
+			});
+
+		match error {
+			Some((Some((line, column)), err)) => Err(ServerError::InvalidConfigLine(line, column, Box::new(err))),
+			Some((None, err)) => Err(err),
+
+			None => Ok(config),
+		}
+	}
+
+	fn check_source(
+		path: Option<&Path>,
+		overrides: &ConfigOverrides,
+	) -> Result<(Config, Vec<ConfigIssue>), ServerError> {
+		// without a file, every key takes the value of the default config
+		let Some(path) = path else {
+			return Ok(Config::check_data("", ConfigFormat::Pconf, overrides));
+		};
+
+		let data = fs::read_to_string(path)
+			.map_err(|_| ServerError::InvalidConfig)?;
+
+		Ok(Config::check_data(&data, ConfigFormat::from_path(path), overrides))
+	}
+
+	fn check_data(
+		data: &str,
+		format: ConfigFormat,
+		overrides: &ConfigOverrides,
+	) -> (Config, Vec<ConfigIssue>) {
+		let file = match format {
+			ConfigFormat::Pconf => {
+				let (entries, mut issues) = pconf_entries(data);
+
+				let mut entries = with_default_entries(entries);
+				overrides.apply(&mut entries);
+
+				let (config, entry_issues) = Config::check_entries(&entries);
+
+				issues.extend(entry_issues);
+				issues.sort_by_key(|issue| issue.position);
+
+				return (config, issues);
+			},
+
+			ConfigFormat::Toml => toml::from_str::<ConfigFile>(data)
+				.map_err(|err| {
+					let position = err.span().map(|span| offset_position(data, span.start));
+					(position, err.message().to_owned())
+				}),
+
+			ConfigFormat::Yaml => serde_yaml::from_str::<ConfigFile>(data)
+				.map_err(|err| {
+					let position = err.location().map(|location| (location.line(), location.column()));
+					(position, strip_position(&err.to_string()))
+				}),
+
+			ConfigFormat::Json => serde_json::from_str::<ConfigFile>(data)
+				.map_err(|err| {
+					let position = (err.line() > 0).then(|| (err.line(), err.column()));
+					(position, strip_position(&err.to_string()))
+				}),
+		};
+
+		match file {
+			Ok(file) => {
+				let mut entries = file_entries(file.into_values(), Some(data));
+				overrides.apply(&mut entries);
+
+				Config::check_entries(&entries)
+			},
+
+			Err((position, message)) => {
+				let issue = ConfigIssue::error(position, ServerError::InvalidConfigFile(message));
+				(init_uninitialized_config(), vec![issue])
+			},
+		}
 	}
 
-	fn parse_line(config: &mut Config, line: &str) -> Result<(), ServerError> {
-		let tokens: Vec<&str> = line.split('=').collect();
+	/// Sets every entry of a config, collecting every problem with the
+	/// entries instead of stopping at the first one.
+	fn check_entries(entries: &[ConfigEntry]) -> (Config, Vec<ConfigIssue>) {
+		let mut config = init_uninitialized_config();
+		let mut issues = Vec::new();
+
+		for (index, entry) in entries.iter().enumerate() {
+			let is_list = entry.key.ends_with("[]");
+
+			let previous = entries[..index]
+				.iter()
+				.find(|other| other.key == entry.key && (!is_list || other.value == entry.value));
+
+			if let Some(previous) = previous {
+				let message = match (is_list, previous.line) {
+					(false, Some(line)) => format!("duplicate key <{}> overrides the value set on line {line}", entry.key),
+					(false, None) => format!("duplicate key <{}>", entry.key),
+					(true, _) => format!("duplicate value <{}> of <{}>", entry.value, entry.key),
+				};
+
+				issues.push(ConfigIssue::warning(entry.key_position(), message));
+			}
+
+			if let Err(err) = Config::set(&mut config, &entry.key, &entry.value) {
+				let position = match err {
+					ServerError::InvalidConfigKey(_) => entry.key_position(),
+					_ => entry.value_position(),
+				};
+
+				let err = match &entry.origin {
+					Some(origin) => ServerError::InvalidConfigOverride(origin.clone(), Box::new(err)),
+					None => err,
+				};
+
+				issues.push(ConfigIssue::error(position, err));
+			}
+		}
+
+		let policy = config.policy.to_string();
+
+		let is_configured_policy = config.policy.is_auto() || config.policies
+			.iter()
+			.any(|configured| configured.to_string() == policy);
+
+		if !is_configured_policy {
+			let position = entries
+				.iter()
+				.rfind(|entry| entry.key == "policy")
+				.and_then(ConfigEntry::value_position);
+
+			issues.push(ConfigIssue::error(position, ServerError::UnconfiguredConfigPolicy(policy)));
+		}
+
+		// the placement is checked against the machine's topology, which is
+		// only read if a placement is configured
+		let has_placement = config.worker_cpus.is_some()
+			|| config.worker_nodes.is_some()
+			|| !config.memory_nodes.is_empty();
+
+		let topology = has_placement
+			.then(Topology::detect)
+			.and_then(Result::ok);
+
+		let validations = [
+			("tiers[]", config.validate_tiers()),
+			("namespaces[]", config.validate_namespaces()),
+			("cluster_node", config.validate_cluster()),
+			("worker_cpus", numa::validate_cpus(config.worker_cpus(), topology.as_ref())),
+			("worker_nodes", numa::validate_nodes(config.worker_nodes().unwrap_or_default(), topology.as_ref())),
+			("memory_nodes", numa::validate_nodes(config.memory_nodes(), topology.as_ref())),
+		];
+
+		for (key, result) in validations {
+			if let Err(err) = result {
+				issues.push(ConfigIssue::error(validation_position(entries, key, &err), err));
+			}
+		}
+
+		let resolved_max_size = config
+			.relative_max_size()
+			.map(|max_size| resolve_max_size(&max_size, memory_limit::memory_limit()));
+
+		config.resolved_max_size = match resolved_max_size {
+			Some(Ok((size, maybe_warning))) => {
+				if let Some(message) = maybe_warning {
+					issues.push(ConfigIssue::warning(None, message));
+				}
+
+				size
+			},
+
+			Some(Err(err)) => {
+				issues.push(ConfigIssue::error(validation_position(entries, "max_size", &err), err));
+				0
+			},
+
+			None => config.max_size.resolve(0),
+		};
+
+		// the default max_size was a fixed 232GiB before it became relative
+		// to the memory limit, so a config which relied on it now gets a
+		// different size
+		let is_default_max_size = entries
+			.iter()
+			.any(|entry| entry.key == "max_size" && entry.is_default);
+
+		if is_default_max_size && config.relative_max_size().is_some() {
+			let message = format!(
+				"max_size is not set, so it defaults to {} ({} bytes) rather than the previous default of 232GiB",
+				config.max_size,
+				config.resolved_max_size,
+			);
 
-		if tokens.len() != 2 {
-			return Err(ServerError::InvalidConfigLine(line.into()));
+			issues.push(ConfigIssue::warning(None, message));
 		}
 
-		let token_value = try_parse_env(tokens[1])
-			.unwrap_or(tokens[1].into());
+		(config, issues)
+	}
+
+	fn set(config: &mut Config, key: &str, value: &str) -> Result<(), ServerError> {
+		let token_value = try_parse_env(value)
+			.unwrap_or(value.into());
 
-		let config_value = match tokens[0] {
+		let config_value = match key {
 			"host" => parse_host(&token_value),
 			"port" => parse_port(&token_value),
 
 			"max_size" => parse_max_size(&token_value),
+			"max_size_headroom" => parse_max_size_headroom(&token_value),
 			"policies[]" => parse_policies_item(&token_value),
 			"policy" => parse_policy(&token_value),
+			"tiers[]" => parse_tiers_item(&token_value),
+			"namespaces[]" => parse_namespaces_item(&token_value),
+			"admission" => parse_admission(&token_value),
+			"mrc_sample_rate" => parse_mrc_sample_rate(&token_value),
+			"trace_dir" => parse_trace_dir(&token_value),
+			"max_pinned_size" => parse_max_pinned_size(&token_value),
 
 			"max_connections" => parse_max_connections(&token_value),
 			"auth_token" => parse_auth_token(&token_value),
 
-			_ => Err(ServerError::InvalidConfigLine(line.into())),
+			"cluster_node" => parse_cluster_node(&token_value),
+			"cluster_nodes[]" => parse_cluster_nodes_item(&token_value),
+			"cluster_auth_token" => parse_cluster_auth_token(&token_value),
+
+			"worker_cpus" => parse_worker_cpus(&token_value),
+			"worker_nodes" => parse_worker_nodes(&token_value),
+			"memory_nodes" => parse_memory_nodes(&token_value),
+			"memory_policy" => parse_memory_policy(&token_value),
+
+			_ => Err(ServerError::InvalidConfigKey(key.into())),
 		};
 
 		match config_value {
@@ -130,11 +722,27 @@ impl Config {
 				ConfigValue::Port(port) => config.port = port,
 
 				ConfigValue::MaxSize(max_size) => config.max_size = max_size,
+				ConfigValue::MaxSizeHeadroom(headroom) => config.max_size_headroom = headroom,
 				ConfigValue::PoliciesItem(policy) => config.policies.push(policy),
 				ConfigValue::Policy(policy) => config.policy = policy,
+				ConfigValue::TiersItem(tier) => config.tiers.push(tier),
+				ConfigValue::NamespacesItem(namespace) => config.namespaces.push(namespace),
+				ConfigValue::Admission(admission) => config.admission = admission,
+				ConfigValue::MrcSampleRate(sample_rate) => config.mrc_sample_rate = Some(sample_rate),
+				ConfigValue::TraceDir(dir) => config.trace_dir = Some(dir),
+				ConfigValue::MaxPinnedSize(max_pinned_size) => config.max_pinned_size = max_pinned_size,
 
 				ConfigValue::MaxConnections(max_connections) => config.max_connections = max_connections,
 				ConfigValue::AuthToken(token) => config.auth_token = Some(token),
+
+				ConfigValue::ClusterNode(node) => config.cluster_node = Some(node),
+				ConfigValue::ClusterNodesItem(node) => config.cluster_nodes.push(node),
+				ConfigValue::ClusterAuthToken(token) => config.cluster_auth_token = Some(token),
+
+				ConfigValue::WorkerCpus(cpus) => config.worker_cpus = Some(cpus),
+				ConfigValue::WorkerNodes(nodes) => config.worker_nodes = Some(nodes),
+				ConfigValue::MemoryNodes(nodes) => config.memory_nodes = nodes,
+				ConfigValue::MemoryPolicy(policy) => config.memory_policy = policy,
 			},
 
 			Err(err) => return Err(err),
@@ -142,24 +750,595 @@ impl Config {
 
 		Ok(())
 	}
+
+	/// The dram tier is held by the cache itself, so if any tiers are
+	/// configured, it must be the first and only dram tier.
+	fn validate_tiers(&self) -> Result<(), ServerError> {
+		let Some(first_tier) = self.tiers.first() else {
+			return Ok(());
+		};
+
+		if !first_tier.is_dram() {
+			return Err(ServerError::InvalidConfigTier(first_tier.name().into()));
+		}
+
+		match self.tiers.iter().skip(1).find(|tier| tier.is_dram()) {
+			Some(tier) => Err(ServerError::InvalidConfigTier(tier.name().into())),
+			None => Ok(()),
+		}
+	}
+
+	/// If any cluster nodes are configured, every slot must be owned by
+	/// exactly one node, and this server must be one of the nodes if its
+	/// ID is set. The ID is not set when the server runs as a proxy.
+	fn validate_cluster(&self) -> Result<(), ServerError> {
+		match (&self.cluster_node, self.cluster_nodes.is_empty()) {
+			(None, true) => Ok(()),
+			(Some(_), true) => Err(ServerError::InvalidConfigParam("cluster_nodes")),
+
+			(node, false) => Cluster::new(&self.cluster_nodes, node.as_deref()).map(|_| ()),
+		}
+	}
+
+	/// Every namespace must have a unique name and prefix.
+	fn validate_namespaces(&self) -> Result<(), ServerError> {
+		for (index, namespace) in self.namespaces.iter().enumerate() {
+			let is_duplicate = self.namespaces[..index]
+				.iter()
+				.any(|other| other.name() == namespace.name() || other.prefix() == namespace.prefix());
+
+			if is_duplicate {
+				return Err(ServerError::InvalidConfigNamespace(namespace.name().into()));
+			}
+		}
+
+		Ok(())
+	}
 }
 
 impl Default for Config {
 	fn default() -> Self {
-		let default_config_data = include_str!("../default.pconf");
-		let mut config = init_uninitialized_config();
+		Config::parse(DEFAULT_CONFIG, ConfigFormat::Pconf)
+			.expect("An error occured when parsing default config")
+	}
+}
+
+/// Serializes every value of the config other than its tokens, which are
+/// omitted so that a serialized config can be logged or shared without
+/// exposing them.
+impl Serialize for Config {
+	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+	where
+		S: Serializer,
+	{
+		ConfigFile::from(self).serialize(serializer)
+	}
+}
+
+impl<'de> Deserialize<'de> for Config {
+	fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+	where
+		D: Deserializer<'de>,
+	{
+		let file = ConfigFile::deserialize(deserializer)?;
+
+		Config::from_file_values(file.into_values())
+			.map_err(de::Error::custom)
+	}
+}
+
+impl JsonSchema for Config {
+	fn schema_name() -> Cow<'static, str> {
+		"Config".into()
+	}
+
+	fn json_schema(generator: &mut SchemaGenerator) -> Schema {
+		ConfigFile::json_schema(generator)
+	}
+}
+
+impl ConfigFormat {
+	/// Returns the format of the file at the supplied path, where every
+	/// extension other than toml, yaml, yml and json is a pconf file.
+	pub fn from_path<P>(path: P) -> Self
+	where
+		P: AsRef<Path>,
+	{
+		match path.as_ref().extension().and_then(|extension| extension.to_str()) {
+			Some("toml") => ConfigFormat::Toml,
+			Some("yaml" | "yml") => ConfigFormat::Yaml,
+			Some("json") => ConfigFormat::Json,
+
+			_ => ConfigFormat::Pconf,
+		}
+	}
+}
+
+impl ConfigBuilder {
+	pub fn new() -> Self {
+		ConfigBuilder::default()
+	}
+
+	pub fn host(mut self, host: impl Into<String>) -> Self {
+		self.file.host = Some(host.into());
+		self
+	}
+
+	pub fn port(mut self, port: u32) -> Self {
+		self.file.port = Some(port);
+		self
+	}
+
+	pub fn max_size(mut self, max_size: impl Into<MemorySize>) -> Self {
+		self.file.max_size = Some(into_config_size(max_size.into()));
+		self
+	}
+
+	pub fn max_size_headroom(mut self, headroom: impl Into<MemorySize>) -> Self {
+		self.file.max_size_headroom = Some(into_config_size(headroom.into()));
+		self
+	}
+
+	pub fn policies(mut self, policies: &[PaperPolicy]) -> Self {
+		self.file.policies = Some(into_items(policies));
+		self
+	}
+
+	pub fn policy(mut self, policy: PaperPolicy) -> Self {
+		self.file.policy = Some(policy.to_string());
+		self
+	}
+
+	pub fn tiers(mut self, tiers: &[TierConfig]) -> Self {
+		self.file.tiers = Some(into_items(tiers));
+		self
+	}
+
+	pub fn namespaces(mut self, namespaces: &[NamespaceConfig]) -> Self {
+		self.file.namespaces = Some(into_items(namespaces));
+		self
+	}
+
+	pub fn admission(mut self, admission: AdmissionPolicy) -> Self {
+		self.file.admission = Some(admission.to_string());
+		self
+	}
+
+	pub fn mrc_sample_rate(mut self, sample_rate: f64) -> Self {
+		self.file.mrc_sample_rate = Some(sample_rate);
+		self
+	}
+
+	pub fn trace_dir<P>(mut self, dir: P) -> Self
+	where
+		P: AsRef<Path>,
+	{
+		self.file.trace_dir = Some(dir.as_ref().display().to_string());
+		self
+	}
+
+	pub fn max_pinned_size(mut self, max_pinned_size: u64) -> Self {
+		self.file.max_pinned_size = Some(ConfigSize::Bytes(max_pinned_size));
+		self
+	}
+
+	pub fn max_connections(mut self, max_connections: usize) -> Self {
+		self.file.max_connections = Some(max_connections);
+		self
+	}
+
+	pub fn auth_token(mut self, token: impl Into<String>) -> Self {
+		self.file.auth_token = Some(token.into());
+		self
+	}
+
+	pub fn cluster_node(mut self, node: impl Into<String>) -> Self {
+		self.file.cluster_node = Some(node.into());
+		self
+	}
+
+	pub fn cluster_nodes(mut self, nodes: &[ClusterNodeConfig]) -> Self {
+		self.file.cluster_nodes = Some(into_items(nodes));
+		self
+	}
+
+	pub fn cluster_auth_token(mut self, token: impl Into<String>) -> Self {
+		self.file.cluster_auth_token = Some(token.into());
+		self
+	}
+
+	pub fn worker_cpus(mut self, cpus: &[usize]) -> Self {
+		self.file.worker_cpus = Some(numa::format_cpu_list(cpus));
+		self
+	}
+
+	pub fn worker_nodes(mut self, nodes: &[u32]) -> Self {
+		self.file.worker_nodes = Some(format_node_list(nodes));
+		self
+	}
+
+	pub fn memory_nodes(mut self, nodes: &[u32]) -> Self {
+		self.file.memory_nodes = Some(format_node_list(nodes));
+		self
+	}
+
+	pub fn memory_policy(mut self, policy: MemoryPolicy) -> Self {
+		self.file.memory_policy = Some(policy.to_string());
+		self
+	}
+
+	pub fn build(self) -> Result<Config, ServerError> {
+		Config::from_file_values(self.file.into_values())
+	}
+}
+
+impl ConfigFile {
+	/// Returns the values of every key which is set, keyed and formatted
+	/// the same way as the lines of a pconf file.
+	fn into_values(self) -> Vec<(&'static str, Vec<String>)> {
+		let values = [
+			("host", self.host.map(into_item)),
+			("port", self.port.map(into_item)),
+
+			("max_size", self.max_size.map(into_item)),
+			("max_size_headroom", self.max_size_headroom.map(into_item)),
+			("policies[]", self.policies),
+			("policy", self.policy.map(into_item)),
+			("tiers[]", self.tiers),
+			("namespaces[]", self.namespaces),
+			("admission", self.admission.map(into_item)),
+			("mrc_sample_rate", self.mrc_sample_rate.map(into_item)),
+			("trace_dir", self.trace_dir.map(into_item)),
+			("max_pinned_size", self.max_pinned_size.map(into_item)),
+
+			("max_connections", self.max_connections.map(into_item)),
+			("auth_token", self.auth_token.map(into_item)),
+
+			("cluster_node", self.cluster_node.map(into_item)),
+			("cluster_nodes[]", self.cluster_nodes),
+			("cluster_auth_token", self.cluster_auth_token.map(into_item)),
+
+			("worker_cpus", self.worker_cpus.map(into_item)),
+			("worker_nodes", self.worker_nodes.map(into_item)),
+			("memory_nodes", self.memory_nodes.map(into_item)),
+			("memory_policy", self.memory_policy.map(into_item)),
+		];
+
+		values
+			.into_iter()
+			.filter_map(|(key, items)| Some((key, items?)))
+			.collect()
+	}
+}
+
+impl From<&Config> for ConfigFile {
+	fn from(config: &Config) -> Self {
+		ConfigFile {
+			host: Some(config.host.clone()),
+			port: Some(config.port),
+
+			max_size: Some(into_config_size(config.max_size)),
+			max_size_headroom: Some(into_config_size(config.max_size_headroom)),
+			policies: Some(into_items(&config.policies)),
+			policy: Some(config.policy.to_string()),
+			tiers: Some(into_items(&config.tiers)),
+			namespaces: Some(into_items(&config.namespaces)),
+			admission: Some(config.admission.to_string()),
+			mrc_sample_rate: config.mrc_sample_rate,
+			trace_dir: config.trace_dir.as_ref().map(|dir| dir.display().to_string()),
+			max_pinned_size: Some(ConfigSize::Bytes(config.max_pinned_size)),
+
+			max_connections: Some(config.max_connections),
+			auth_token: None,
+
+			cluster_node: config.cluster_node.clone(),
+			cluster_nodes: Some(into_items(&config.cluster_nodes)),
+			cluster_auth_token: None,
+
+			worker_cpus: config.worker_cpus.as_deref().map(numa::format_cpu_list),
+			worker_nodes: config.worker_nodes.as_deref().map(format_node_list),
+			memory_nodes: (!config.memory_nodes.is_empty()).then(|| format_node_list(&config.memory_nodes)),
+			memory_policy: Some(config.memory_policy.to_string()),
+		}
+	}
+}
+
+impl fmt::Display for ConfigSize {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ConfigSize::Bytes(size) => write!(f, "{size}"),
+			ConfigSize::Text(size) => write!(f, "{size}"),
+		}
+	}
+}
+
+impl ConfigOverrides {
+	pub fn new() -> Self {
+		ConfigOverrides::default()
+	}
+
+	/// Reads the overrides from the `PAPER_<KEY>` environment variables,
+	/// such as `PAPER_MAX_SIZE`, where the values of a list key such as
+	/// `PAPER_POLICIES` are separated by semicolons.
+	pub fn from_env() -> Self {
+		let mut overrides = ConfigOverrides::new();
+
+		for key in CONFIG_KEYS {
+			let name = format!("{ENV_PREFIX}{}", key.trim_end_matches("[]").to_uppercase());
+
+			let Ok(value) = env::var(&name) else {
+				continue;
+			};
+
+			let values = match key.ends_with("[]") {
+				true => value
+					.split(';')
+					.map(|item| item.trim().to_owned())
+					.filter(|item| !item.is_empty())
+					.collect(),
+
+				false => vec![value],
+			};
+
+			overrides.set(key, name, values);
+		}
+
+		overrides
+	}
+
+	/// Sets the values of a key as it is written in a pconf file (such as
+	/// `port` or `policies[]`), replacing any values previously set for the
+	/// key. The origin names where the values came from, such as a flag.
+	pub fn set(&mut self, key: &str, origin: impl Into<String>, values: Vec<String>) {
+		self.overrides.retain(|existing| existing.key != key);
+
+		self.overrides.push(ConfigOverride {
+			key: key.to_owned(),
+			origin: origin.into(),
+			values,
+		});
+	}
+
+	/// Adds the supplied overrides, which take precedence over these.
+	pub fn extend(&mut self, other: ConfigOverrides) {
+		for value in other.overrides {
+			self.set(&value.key, value.origin, value.values);
+		}
+	}
+
+	/// Replaces the entries of every overridden key with the overrides.
+	fn apply(&self, entries: &mut Vec<ConfigEntry>) {
+		entries.retain(|entry| self.overrides.iter().all(|value| value.key != entry.key));
+
+		for value in &self.overrides {
+			for item in &value.values {
+				entries.push(ConfigEntry {
+					key: value.key.clone(),
+					value: item.clone(),
+
+					line: None,
+					key_column: 0,
+					value_column: 0,
+
+					origin: Some(value.origin.clone()),
+					is_default: false,
+				});
+			}
+		}
+	}
+}
+
+impl ConfigIssue {
+	fn error(position: Option<(usize, usize)>, err: ServerError) -> Self {
+		ConfigIssue {
+			position,
+			kind: ConfigIssueKind::Error(err),
+		}
+	}
+
+	fn warning(position: Option<(usize, usize)>, message: String) -> Self {
+		ConfigIssue {
+			position,
+			kind: ConfigIssueKind::Warning(message),
+		}
+	}
+
+	pub fn is_error(&self) -> bool {
+		matches!(self.kind, ConfigIssueKind::Error(_))
+	}
+}
+
+impl fmt::Display for ConfigIssue {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		if let Some((line, column)) = self.position {
+			write!(f, "line {line}, column {column}: ")?;
+		}
+
+		match &self.kind {
+			ConfigIssueKind::Error(err) => write!(f, "error: {err}"),
+			ConfigIssueKind::Warning(message) => write!(f, "warning: {message}"),
+		}
+	}
+}
+
+impl ConfigEntry {
+	fn key_position(&self) -> Option<(usize, usize)> {
+		self.line.map(|line| (line, self.key_column))
+	}
+
+	fn value_position(&self) -> Option<(usize, usize)> {
+		self.line.map(|line| (line, self.value_column))
+	}
+}
+
+/// Returns the entries of a pconf config, along with an error for every
+/// line which is not of the form `<key>=<value>`.
+fn pconf_entries(data: &str) -> (Vec<ConfigEntry>, Vec<ConfigIssue>) {
+	let mut entries = Vec::new();
+	let mut issues = Vec::new();
+
+	for (index, raw_line) in data.lines().enumerate() {
+		let line = raw_line.trim();
+
+		if line.is_empty() || line.starts_with('#') {
+			continue;
+		}
+
+		let key_column = raw_line.chars().count() - raw_line.trim_start().chars().count() + 1;
+
+		// only the first '=' separates the key, so values may contain '='
+		match line.split_once('=') {
+			Some((key, value)) => entries.push(ConfigEntry {
+				key: key.to_owned(),
+				value: value.to_owned(),
+
+				line: Some(index + 1),
+				key_column,
+				value_column: key_column + key.chars().count() + 1,
+
+				origin: None,
+				is_default: false,
+			}),
+
+			None => {
+				let err = ServerError::InvalidConfigSyntax(line.into());
+				issues.push(ConfigIssue::error(Some((index + 1, key_column)), err));
+			},
+		}
+	}
+
+	(entries, issues)
+}
+
+/// Returns the entries of the values of a TOML, YAML or JSON config, where
+/// every key which is not set takes the values of the default config. The
+/// entries are located at their keys in the config's data, if it is
+/// supplied.
+fn file_entries(values: Vec<(&str, Vec<String>)>, data: Option<&str>) -> Vec<ConfigEntry> {
+	let mut entries = Vec::new();
+
+	for (key, items) in values {
+		let position = data.and_then(|data| key_position(data, key.trim_end_matches("[]")));
+
+		for item in items {
+			entries.push(ConfigEntry {
+				key: key.to_owned(),
+				value: item,
 
-		let line_iter = default_config_data
-			.split('\n')
-			.map(|line| line.trim().to_owned())
-			.filter(|line| !line.is_empty() && !line.starts_with('#'));
+				line: position.map(|(line, _)| line),
+				key_column: position.map_or(0, |(_, column)| column),
+				value_column: position.map_or(0, |(_, column)| column),
 
-		for line in line_iter {
-			Config::parse_line(&mut config, &line)
-				.expect("An error occured when parsing default config");
+				origin: None,
+				is_default: false,
+			});
 		}
+	}
+
+	with_default_entries(entries)
+}
+
+/// Adds the entries of the default config for every key which is not set
+/// by the supplied entries.
+fn with_default_entries(entries: Vec<ConfigEntry>) -> Vec<ConfigEntry> {
+	let (default_entries, _) = pconf_entries(DEFAULT_CONFIG);
+
+	let mut all_entries = default_entries
+		.into_iter()
+		.filter(|default_entry| entries.iter().all(|entry| entry.key != default_entry.key))
+		.map(|default_entry| ConfigEntry {
+			line: None,
+			is_default: true,
+			..default_entry
+		})
+		.collect::<Vec<_>>();
+
+	all_entries.extend(entries);
+	all_entries
+}
+
+/// Returns the position of the first line which sets the supplied key in
+/// a TOML, YAML or JSON config.
+fn key_position(data: &str, key: &str) -> Option<(usize, usize)> {
+	let quoted_key = format!("\"{key}\"");
 
-		config
+	data
+		.lines()
+		.enumerate()
+		.find_map(|(index, line)| {
+			let trimmed = line.trim_start();
+
+			let is_key = trimmed
+				.strip_prefix(key)
+				.map(|rest| rest.trim_start())
+				.is_some_and(|rest| rest.starts_with('=') || rest.starts_with(':'));
+
+			let column = line.chars().count() - trimmed.chars().count() + 1;
+
+			(is_key || trimmed.starts_with(&quoted_key)).then_some((index + 1, column))
+		})
+}
+
+/// Returns the position of the entry which caused a validation error, which
+/// is the tier or namespace named in the error or else the first entry of
+/// the validated key.
+fn validation_position(entries: &[ConfigEntry], key: &str, err: &ServerError) -> Option<(usize, usize)> {
+	let name = match err {
+		ServerError::InvalidConfigTier(name) | ServerError::InvalidConfigNamespace(name) => Some(name),
+		_ => None,
+	};
+
+	let mut key_entries = entries
+		.iter()
+		.filter(|entry| entry.key.starts_with(key));
+
+	let entry = match name {
+		Some(name) => key_entries
+			.rfind(|entry| entry.value.trim().starts_with(&format!("{name}:"))),
+
+		None => key_entries.next(),
+	};
+
+	entry.and_then(ConfigEntry::key_position)
+}
+
+/// Returns the 1-based line and column of a byte offset in the data.
+fn offset_position(data: &str, offset: usize) -> (usize, usize) {
+	let before = &data[..offset.min(data.len())];
+
+	let line = before.matches('\n').count() + 1;
+	let column = before.rsplit('\n').next().map_or(0, |line| line.chars().count()) + 1;
+
+	(line, column)
+}
+
+/// Removes the trailing position from a parser's error message, since the
+/// position is reported separately.
+fn strip_position(message: &str) -> String {
+	match message.rsplit_once(" at line ") {
+		Some((message, _)) => message.to_owned(),
+		None => message.to_owned(),
+	}
+}
+
+/// Resolves a relative max size against the memory limit. If the limit
+/// cannot be detected, the max size falls back to a fixed size rather than
+/// failing, along with a warning.
+fn resolve_max_size(
+	max_size: &RelativeMaxSize,
+	limit: Option<u64>,
+) -> Result<(u64, Option<String>), ServerError> {
+	match limit {
+		Some(limit) => Ok((max_size.resolve_against(limit)?, None)),
+
+		None => {
+			let message = format!(
+				"the memory limit could not be detected, so max_size falls back to {} bytes",
+				memory_limit::FALLBACK_MAX_SIZE,
+			);
+
+			Ok((memory_limit::FALLBACK_MAX_SIZE, Some(message)))
+		},
 	}
 }
 
@@ -168,15 +1347,72 @@ fn init_uninitialized_config() -> Config {
 		host: String::new(),
 		port: 0,
 
-		max_size: 0,
+		max_size: MemorySize::Bytes(0),
+		max_size_headroom: MemorySize::Bytes(0),
+		resolved_max_size: 0,
 		policies: Vec::new(),
 		policy: PaperPolicy::Lfu,
+		tiers: Vec::new(),
+		namespaces: Vec::new(),
+		admission: AdmissionPolicy::None,
+		mrc_sample_rate: None,
+		trace_dir: None,
+		max_pinned_size: 0,
 
 		max_connections: 0,
 		auth_token: None,
+
+		cluster_node: None,
+		cluster_nodes: Vec::new(),
+		cluster_auth_token: None,
+
+		worker_cpus: None,
+		worker_nodes: None,
+		memory_nodes: Vec::new(),
+		memory_policy: MemoryPolicy::Bind,
+	}
+}
+
+fn into_item<T>(value: T) -> Vec<String>
+where
+	T: ToString,
+{
+	vec![value.to_string()]
+}
+
+fn into_items<T>(values: &[T]) -> Vec<String>
+where
+	T: ToString,
+{
+	values
+		.iter()
+		.map(|value| value.to_string())
+		.collect()
+}
+
+fn into_config_size(size: MemorySize) -> ConfigSize {
+	match size {
+		MemorySize::Bytes(size) => ConfigSize::Bytes(size),
+		size => ConfigSize::Text(size.to_string()),
 	}
 }
 
+fn format_node_list(nodes: &[u32]) -> String {
+	let nodes = nodes
+		.iter()
+		.map(|node| *node as usize)
+		.collect::<Vec<_>>();
+
+	numa::format_cpu_list(&nodes)
+}
+
+fn hash_token(token: &str) -> u64 {
+	let mut s = DefaultHasher::new();
+	token.hash(&mut s);
+
+	s.finish()
+}
+
 fn try_parse_env(value: &str) -> Option<String> {
 	let value = value.trim();
 
@@ -202,9 +1438,16 @@ fn parse_port(value: &str) -> Result<ConfigValue, ServerError> {
 }
 
 fn parse_max_size(value: &str) -> Result<ConfigValue, ServerError> {
-	match parse_size(value) {
-		Ok(0) | Err(_) => Err(ServerError::InvalidConfigParam("max_size")),
-		Ok(value) => Ok(ConfigValue::MaxSize(value)),
+	match MemorySize::parse(value) {
+		Some(MemorySize::Bytes(0)) | None => Err(ServerError::InvalidConfigParam("max_size")),
+		Some(value) => Ok(ConfigValue::MaxSize(value)),
+	}
+}
+
+fn parse_max_size_headroom(value: &str) -> Result<ConfigValue, ServerError> {
+	match MemorySize::parse(value) {
+		Some(MemorySize::Auto) | None => Err(ServerError::InvalidConfigParam("max_size_headroom")),
+		Some(value) => Ok(ConfigValue::MaxSizeHeadroom(value)),
 	}
 }
 
@@ -222,6 +1465,40 @@ fn parse_policy(value: &str) -> Result<ConfigValue, ServerError> {
 	}
 }
 
+fn parse_tiers_item(value: &str) -> Result<ConfigValue, ServerError> {
+	TierConfig::from_str(value).map(ConfigValue::TiersItem)
+}
+
+fn parse_namespaces_item(value: &str) -> Result<ConfigValue, ServerError> {
+	NamespaceConfig::from_str(value).map(ConfigValue::NamespacesItem)
+}
+
+fn parse_admission(value: &str) -> Result<ConfigValue, ServerError> {
+	AdmissionPolicy::from_str(value).map(ConfigValue::Admission)
+}
+
+fn parse_mrc_sample_rate(value: &str) -> Result<ConfigValue, ServerError> {
+	match value.parse::<f64>() {
+		Ok(value) if value > 0.0 && value <= 1.0 => Ok(ConfigValue::MrcSampleRate(value)),
+		_ => Err(ServerError::InvalidConfigParam("mrc_sample_rate")),
+	}
+}
+
+fn parse_trace_dir(value: &str) -> Result<ConfigValue, ServerError> {
+	if value.is_empty() {
+		return Err(ServerError::InvalidConfigParam("trace_dir"));
+	}
+
+	Ok(ConfigValue::TraceDir(PathBuf::from(value)))
+}
+
+fn parse_max_pinned_size(value: &str) -> Result<ConfigValue, ServerError> {
+	match parse_size(value) {
+		Ok(value) => Ok(ConfigValue::MaxPinnedSize(value)),
+		Err(_) => Err(ServerError::InvalidConfigParam("max_pinned_size")),
+	}
+}
+
 fn parse_max_connections(value: &str) -> Result<ConfigValue, ServerError> {
 	match value.parse::<usize>() {
 		Ok(0) | Err(_) => Err(ServerError::InvalidConfigParam("max_connections")),
@@ -234,8 +1511,308 @@ fn parse_auth_token(value: &str) -> Result<ConfigValue, ServerError> {
 		return Err(ServerError::InvalidConfigParam("auth_token"));
 	}
 
-	let mut s = DefaultHasher::new();
-	value.hash(&mut s);
+	Ok(ConfigValue::AuthToken(value.to_owned()))
+}
+
+fn parse_cluster_node(value: &str) -> Result<ConfigValue, ServerError> {
+	if value.is_empty() {
+		return Err(ServerError::InvalidConfigParam("cluster_node"));
+	}
+
+	Ok(ConfigValue::ClusterNode(value.to_owned()))
+}
+
+fn parse_cluster_nodes_item(value: &str) -> Result<ConfigValue, ServerError> {
+	ClusterNodeConfig::from_str(value).map(ConfigValue::ClusterNodesItem)
+}
+
+fn parse_cluster_auth_token(value: &str) -> Result<ConfigValue, ServerError> {
+	if value.is_empty() {
+		return Err(ServerError::InvalidConfigParam("cluster_auth_token"));
+	}
+
+	Ok(ConfigValue::ClusterAuthToken(value.to_owned()))
+}
+
+fn parse_worker_cpus(value: &str) -> Result<ConfigValue, ServerError> {
+	match numa::parse_cpu_list(value) {
+		Some(cpus) if !cpus.is_empty() => Ok(ConfigValue::WorkerCpus(cpus)),
+		_ => Err(ServerError::InvalidConfigParam("worker_cpus")),
+	}
+}
+
+fn parse_worker_nodes(value: &str) -> Result<ConfigValue, ServerError> {
+	match parse_node_list(value) {
+		Some(nodes) if !nodes.is_empty() => Ok(ConfigValue::WorkerNodes(nodes)),
+		_ => Err(ServerError::InvalidConfigParam("worker_nodes")),
+	}
+}
+
+fn parse_memory_nodes(value: &str) -> Result<ConfigValue, ServerError> {
+	match parse_node_list(value) {
+		Some(nodes) if !nodes.is_empty() => Ok(ConfigValue::MemoryNodes(nodes)),
+		_ => Err(ServerError::InvalidConfigParam("memory_nodes")),
+	}
+}
+
+fn parse_memory_policy(value: &str) -> Result<ConfigValue, ServerError> {
+	MemoryPolicy::from_str(value).map(ConfigValue::MemoryPolicy)
+}
+
+fn parse_node_list(value: &str) -> Option<Vec<u32>> {
+	numa::parse_cpu_list(value)?
+		.into_iter()
+		.map(|node| u32::try_from(node).ok())
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn it_parses_every_format() {
+		let configs = [
+			("port=4000\nmax_connections=8\n", ConfigFormat::Pconf),
+			("port = 4000\nmax_connections = 8\n", ConfigFormat::Toml),
+			("port: 4000\nmax_connections: 8\n", ConfigFormat::Yaml),
+			("{\"port\": 4000, \"max_connections\": 8}", ConfigFormat::Json),
+		];
+
+		for (data, format) in configs {
+			let config = Config::parse(data, format).unwrap();
+
+			assert_eq!(config.port(), 4000);
+			assert_eq!(config.max_connections(), 8);
+
+			// every key which is not set takes the value of the default config
+			assert_eq!(config.host(), Config::default().host());
+		}
+	}
+
+	#[test]
+	fn it_builds_a_config() {
+		let config = ConfigBuilder::new()
+			.host("0.0.0.0")
+			.port(4000)
+			.max_size(1 << 20)
+			.mrc_sample_rate(0.5)
+			.build()
+			.unwrap();
+
+		assert_eq!(config.host(), "0.0.0.0");
+		assert_eq!(config.port(), 4000);
+		assert_eq!(config.max_size(), 1 << 20);
+		assert_eq!(config.mrc_sample_rate(), Some(0.5));
+
+		assert!(ConfigBuilder::new().mrc_sample_rate(2.0).build().is_err());
+	}
 
-	Ok(ConfigValue::AuthToken(s.finish()))
+	#[test]
+	fn it_round_trips_a_serialized_config() {
+		let config = ConfigBuilder::new()
+			.port(4000)
+			.max_size(1 << 20)
+			.max_connections(8)
+			.worker_cpus(&[0])
+			.build()
+			.unwrap();
+
+		let toml = toml::to_string(&config).unwrap();
+		let config = Config::parse(&toml, ConfigFormat::Toml).unwrap();
+
+		assert_eq!(config.port(), 4000);
+		assert_eq!(config.max_size(), 1 << 20);
+		assert_eq!(config.max_connections(), 8);
+		assert_eq!(config.worker_cpus(), Some(&[0][..]));
+	}
+
+	#[test]
+	fn it_reports_every_issue_with_its_position() {
+		let issues = Config::check("port=abc\nunknown=1\n  max_connections=x\nmax_size=1MiB\n", ConfigFormat::Pconf);
+
+		assert_eq!(issues.len(), 3);
+		assert!(issues.iter().all(ConfigIssue::is_error));
+
+		assert!(matches!(
+			&issues[0],
+			ConfigIssue { position: Some((1, 6)), kind: ConfigIssueKind::Error(ServerError::InvalidConfigParam("port")) },
+		));
+
+		assert!(matches!(
+			&issues[1],
+			ConfigIssue { position: Some((2, 1)), kind: ConfigIssueKind::Error(ServerError::InvalidConfigKey(_)) },
+		));
+
+		assert!(matches!(
+			&issues[2],
+			ConfigIssue { position: Some((3, 19)), kind: ConfigIssueKind::Error(ServerError::InvalidConfigParam("max_connections")) },
+		));
+
+		assert!(matches!(
+			Config::parse("port=abc\n", ConfigFormat::Pconf),
+			Err(ServerError::InvalidConfigLine(1, 6, _)),
+		));
+	}
+
+	#[test]
+	fn it_reports_the_position_of_a_file_error() {
+		let issues = Config::check("host = \"localhost\"\nport = \"abc\"\n", ConfigFormat::Toml);
+
+		assert!(matches!(
+			&issues[..],
+			[ConfigIssue { position: Some((2, 8)), kind: ConfigIssueKind::Error(ServerError::InvalidConfigFile(_)) }],
+		));
+	}
+
+	#[test]
+	fn it_parses_values_which_contain_equals_signs() {
+		let config = Config::parse("cluster_auth_token=dG9rZW4=\n", ConfigFormat::Pconf).unwrap();
+		assert_eq!(config.cluster_auth_token(), Some("dG9rZW4="));
+	}
+
+	#[test]
+	fn it_warns_about_duplicate_keys() {
+		let issues = Config::check("port=4000\nport=5000\nmax_size=1MiB\n", ConfigFormat::Pconf);
+
+		assert!(matches!(
+			&issues[..],
+			[ConfigIssue { position: Some((2, 1)), kind: ConfigIssueKind::Warning(_) }],
+		));
+
+		assert_eq!(Config::parse("port=4000\nport=5000\n", ConfigFormat::Pconf).unwrap().port(), 5000);
+	}
+
+	#[test]
+	fn it_rejects_a_policy_which_is_not_configured() {
+		let data = "policies[]=lfu\npolicy=lru\nmax_size=1MiB\n";
+
+		assert!(matches!(
+			&Config::check(data, ConfigFormat::Pconf)[..],
+			[ConfigIssue { position: Some((2, 8)), kind: ConfigIssueKind::Error(ServerError::UnconfiguredConfigPolicy(_)) }],
+		));
+
+		assert!(Config::parse(data, ConfigFormat::Pconf).is_err());
+		assert!(Config::parse("policies[]=lfu\npolicy=lfu\n", ConfigFormat::Pconf).is_ok());
+	}
+
+	#[test]
+	fn it_replaces_the_values_of_overridden_keys() {
+		let mut overrides = ConfigOverrides::new();
+
+		overrides.set("port", "--port", vec!["5000".into()]);
+		overrides.set("policies[]", "--policies", vec!["lfu".into(), "lru".into()]);
+
+		let data = "port=4000\nmax_connections=8\npolicies[]=fifo\npolicy=lru\nmax_size=1MiB\n";
+		let (config, issues) = Config::check_data(data, ConfigFormat::Pconf, &overrides);
+
+		assert!(issues.is_empty());
+
+		assert_eq!(config.port(), 5000);
+		assert_eq!(config.max_connections(), 8);
+		assert_eq!(config.policies(), [PaperPolicy::Lfu, PaperPolicy::Lru]);
+	}
+
+	#[test]
+	fn it_gives_precedence_to_the_extending_overrides() {
+		let mut overrides = ConfigOverrides::new();
+		overrides.set("port", "PAPER_PORT", vec!["5000".into()]);
+
+		let mut flags = ConfigOverrides::new();
+		flags.set("port", "--port", vec!["6000".into()]);
+
+		overrides.extend(flags);
+
+		let (config, _) = Config::check_data("port=4000\n", ConfigFormat::Toml, &overrides);
+		assert_eq!(config.port(), 6000);
+	}
+
+	#[test]
+	fn it_reports_the_origin_of_an_invalid_override() {
+		let mut overrides = ConfigOverrides::new();
+		overrides.set("port", "--port", vec!["abc".into()]);
+
+		let (_, issues) = Config::check_data("max_size=1MiB\n", ConfigFormat::Pconf, &overrides);
+
+		assert!(matches!(
+			&issues[..],
+			[ConfigIssue { position: None, kind: ConfigIssueKind::Error(ServerError::InvalidConfigOverride(origin, _)) }]
+				if origin == "--port"
+		));
+	}
+
+	#[test]
+	fn it_reads_the_overrides_from_the_environment() {
+		unsafe {
+			env::set_var("PAPER_MRC_SAMPLE_RATE", "0.25");
+			env::set_var("PAPER_NAMESPACES", "users:1MiB,prefix=u:,policy=lru; ;sessions:1MiB,prefix=s:,policy=lfu");
+		}
+
+		let overrides = ConfigOverrides::from_env();
+		let (config, issues) = Config::check_data("max_size=1MiB\n", ConfigFormat::Pconf, &overrides);
+
+		assert!(issues.is_empty());
+		assert_eq!(config.mrc_sample_rate(), Some(0.25));
+		assert_eq!(config.namespaces().len(), 2);
+	}
+
+	#[test]
+	fn it_warns_about_the_default_max_size() {
+		let is_max_size_warning = |issue: &ConfigIssue| matches!(
+			&issue.kind,
+			ConfigIssueKind::Warning(message) if message.starts_with("max_size is not set"),
+		);
+
+		assert!(Config::check("", ConfigFormat::Pconf).iter().any(is_max_size_warning));
+
+		assert!(!Config::check("max_size=auto\n", ConfigFormat::Pconf).iter().any(is_max_size_warning));
+		assert!(!Config::check("max_size=1MiB\n", ConfigFormat::Pconf).iter().any(is_max_size_warning));
+	}
+
+	#[test]
+	fn it_resolves_a_relative_max_size() {
+		let config = Config::parse("max_size=50%\nmax_size_headroom=0\n", ConfigFormat::Pconf).unwrap();
+		let limit = crate::memory_limit::memory_limit().unwrap();
+
+		assert!(config.relative_max_size().is_some());
+		assert_eq!(config.max_size(), limit / 2);
+
+		let config = Config::parse("max_size=1MiB\n", ConfigFormat::Pconf).unwrap();
+
+		assert!(config.relative_max_size().is_none());
+		assert_eq!(config.max_size(), 1 << 20);
+
+		assert!(Config::parse("max_size=0%\n", ConfigFormat::Pconf).is_err());
+	}
+
+	#[test]
+	fn it_falls_back_to_a_fixed_max_size_without_a_memory_limit() {
+		let max_size = RelativeMaxSize::new(MemorySize::Auto, MemorySize::Percent(20.0), 0);
+
+		assert_eq!(resolve_max_size(&max_size, Some(1000)).unwrap(), (800, None));
+
+		let (size, maybe_warning) = resolve_max_size(&max_size, None).unwrap();
+
+		assert_eq!(size, memory_limit::FALLBACK_MAX_SIZE);
+		assert!(maybe_warning.is_some());
+	}
+
+	#[test]
+	fn it_does_not_serialize_the_tokens() {
+		let config = ConfigBuilder::new()
+			.auth_token("secret-token")
+			.cluster_auth_token("secret-cluster-token")
+			.build()
+			.unwrap();
+
+		let json = serde_json::to_string(&config).unwrap();
+
+		assert!(!json.contains("secret"));
+		assert!(!json.contains("auth_token"));
+
+		let config: Config = serde_json::from_str(&json).unwrap();
+
+		assert_eq!(config.auth_token(), None);
+		assert_eq!(config.cluster_auth_token(), None);
+	}
 }
@@ -20,6 +20,7 @@ use crate::{
 
 pub struct Connection {
 	stream: TcpStream,
+	address: String,
 
 	auth_token: Option<u64>,
 	is_authorized: bool,
@@ -28,18 +29,29 @@ pub struct Connection {
 impl Connection {
 	pub fn new(
 		stream: TcpStream,
+		address: String,
 		auth_token: Option<u64>,
 	) -> Self {
 		let is_authorized = auth_token.is_none();
 
+		// responses are written as soon as they are ready, so that the
+		// responses to pipelined commands are not held back waiting on
+		// acknowledgements of the previous responses
+		let _ = stream.set_nodelay(true);
+
 		Connection {
 			stream,
+			address,
 
 			auth_token,
 			is_authorized,
 		}
 	}
 
+	pub fn address(&self) -> &str {
+		&self.address
+	}
+
 	pub fn is_authorized(&self) -> bool {
 		self.is_authorized
 	}
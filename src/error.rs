@@ -35,8 +35,20 @@ pub enum ServerError {
 	#[error("could not open config file")]
 	InvalidConfig,
 
-	#[error("invalid config line <{0}>")]
-	InvalidConfigLine(String),
+	#[error("invalid config at line {0}, column {1} ({2})")]
+	InvalidConfigLine(usize, usize, Box<ServerError>),
+
+	#[error("invalid config line <{0}>, expected <key>=<value>")]
+	InvalidConfigSyntax(String),
+
+	#[error("unknown config key <{0}>")]
+	InvalidConfigKey(String),
+
+	#[error("invalid config override <{0}> ({1})")]
+	InvalidConfigOverride(String, Box<ServerError>),
+
+	#[error("invalid config file ({0})")]
+	InvalidConfigFile(String),
 
 	#[error("invalid {0} config")]
 	InvalidConfigParam(&'static str),
@@ -44,17 +56,151 @@ pub enum ServerError {
 	#[error("invalid policy <{0}> in config")]
 	InvalidConfigPolicy(String),
 
+	#[error("policy <{0}> is not one of the configured policies")]
+	UnconfiguredConfigPolicy(String),
+
+	#[error("invalid tier <{0}> in config")]
+	InvalidConfigTier(String),
+
+	#[error("invalid namespace <{0}> in config")]
+	InvalidConfigNamespace(String),
+
+	#[error("invalid cluster <{0}> in config")]
+	InvalidConfigCluster(String),
+
+	#[error("could not resolve max_size ({0})")]
+	InvalidMaxSize(String),
+
+	#[error("could not map memory for tier <{0}>")]
+	InvalidTier(String),
+
+	#[error("tier <{0}> is larger than its device ({1} bytes)")]
+	InvalidTierSize(String, u64),
+
+	#[error("invalid placement: {0}")]
+	InvalidPlacement(String),
+
+	#[error("could not write trace ({0})")]
+	InvalidTrace(String),
+
+	#[error("tracing is not enabled")]
+	TraceDisabled,
+
+	#[error("the maximum pinned size was exceeded")]
+	PinnedSizeExceeded,
+
+	#[error("miss-ratio curves are not enabled")]
+	MrcDisabled,
+
+	#[error("cluster mode is not enabled")]
+	ClusterDisabled,
+
+	#[error("invalid slot range")]
+	InvalidSlotRange,
+
+	#[error("unknown cluster node <{0}>")]
+	UnknownNode(String),
+
+	#[error("could not migrate slots ({0})")]
+	MigrationFailed(String),
+
+	#[error("slot {0} is owned by {1}")]
+	Moved(u16, String),
+
+	#[error("backend <{0}> is unavailable")]
+	BackendUnavailable(String),
+
 	#[error("unauthorized")]
 	Unauthorized,
+
+	#[error("the object version does not match")]
+	VersionMismatch,
+
+	#[error("the object was not admitted")]
+	NotAdmitted,
+
+	#[error("invalid object in cache")]
+	InvalidObject,
+}
+
+/// The code which follows the failure flag of a failed response.
+pub struct ErrorCode;
+
+impl ErrorCode {
+	/// Followed by a `CacheErrorCode`.
+	pub const CACHE: u8 = 0;
+	pub const SERVER: u8 = 1;
+
+	pub const MAX_CONNECTIONS: u8 = 2;
+	pub const UNAUTHORIZED: u8 = 3;
+
+	/// Followed by the slot and the address of the slot's owner.
+	pub const MOVED: u8 = 4;
+	pub const BACKEND_UNAVAILABLE: u8 = 5;
+}
+
+/// The code which follows a cache error's `ErrorCode`.
+pub struct CacheErrorCode;
+
+impl CacheErrorCode {
+	pub const OTHER: u8 = 0;
+
+	pub const KEY_NOT_FOUND: u8 = 1;
+
+	pub const ZERO_VALUE_SIZE: u8 = 2;
+	pub const EXCEEDING_VALUE_SIZE: u8 = 3;
+
+	pub const ZERO_CACHE_SIZE: u8 = 4;
+
+	pub const UNCONFIGURED_POLICY: u8 = 5;
+	pub const INVALID_POLICY: u8 = 6;
+
+	pub const VERSION_MISMATCH: u8 = 7;
+	pub const PINNED_SIZE_EXCEEDED: u8 = 8;
+	pub const NOT_ADMITTED: u8 = 9;
 }
 
 impl ServerError {
+	/// Returns the error which a cache error code stands for, so that
+	/// clients can describe it with the server's message.
+	pub fn from_cache_error_code(code: u8) -> Option<Self> {
+		let error = match code {
+			CacheErrorCode::KEY_NOT_FOUND => CacheError::KeyNotFound.into(),
+
+			CacheErrorCode::ZERO_VALUE_SIZE => CacheError::ZeroValueSize.into(),
+			CacheErrorCode::EXCEEDING_VALUE_SIZE => CacheError::ExceedingValueSize.into(),
+
+			CacheErrorCode::ZERO_CACHE_SIZE => CacheError::ZeroCacheSize.into(),
+
+			CacheErrorCode::UNCONFIGURED_POLICY => CacheError::UnconfiguredPolicy.into(),
+			CacheErrorCode::INVALID_POLICY => CacheError::InvalidPolicy.into(),
+
+			CacheErrorCode::VERSION_MISMATCH => ServerError::VersionMismatch,
+			CacheErrorCode::PINNED_SIZE_EXCEEDED => ServerError::PinnedSizeExceeded,
+			CacheErrorCode::NOT_ADMITTED => ServerError::NotAdmitted,
+
+			_ => return None,
+		};
+
+		Some(error)
+	}
+
 	pub fn to_sheet(&self) -> Sheet {
-		if let ServerError::CacheError(err) = self {
+		// a redirect carries the slot and the address of the slot's owner
+		if let ServerError::Moved(slot, address) = self {
 			return SheetBuilder::new()
 				.write_bool(false)
 				.write_u8(get_error_code(self))
-				.write_u8(get_cache_error_code(err))
+				.write_u32(*slot as u32)
+				.write_str(address.clone())
+				.into_sheet();
+		}
+
+		if let Some(cache_error_code) = get_cache_error_code(self) {
+			return SheetBuilder::new()
+				.write_bool(false)
+				.write_u8(get_error_code(self))
+				.write_u8(cache_error_code)
 				.into_sheet();
 		}
 
@@ -67,7 +213,10 @@ impl ServerError {
 
 fn get_error_code(error: &ServerError) -> u8 {
 	match error {
-		ServerError::CacheError(_)					=> 0,
+		ServerError::CacheError(_)
+			| ServerError::VersionMismatch
+			| ServerError::PinnedSizeExceeded
+			| ServerError::NotAdmitted				=> ErrorCode::CACHE,
 
 		ServerError::InvalidAddress
 			| ServerError::InvalidConnection
@@ -75,27 +224,90 @@ fn get_error_code(error: &ServerError) -> u8 {
 			| ServerError::InvalidResponse
 			| ServerError::Disconnected
 			| ServerError::InvalidConfig
-			| ServerError::InvalidConfigLine(_)
+			| ServerError::InvalidConfigLine(_, _, _)
+			| ServerError::InvalidConfigSyntax(_)
+			| ServerError::InvalidConfigKey(_)
+			| ServerError::InvalidConfigOverride(_, _)
+			| ServerError::InvalidConfigFile(_)
 			| ServerError::InvalidConfigParam(_)
-			| ServerError::InvalidConfigPolicy(_)	=> 1,
+			| ServerError::InvalidConfigPolicy(_)
+			| ServerError::UnconfiguredConfigPolicy(_)
+			| ServerError::InvalidConfigTier(_)
+			| ServerError::InvalidConfigNamespace(_)
+			| ServerError::InvalidConfigCluster(_)
+			| ServerError::InvalidMaxSize(_)
+			| ServerError::InvalidTier(_)
+			| ServerError::InvalidTierSize(_, _)
+			| ServerError::InvalidPlacement(_)
+			| ServerError::InvalidTrace(_)
+			| ServerError::TraceDisabled
+			| ServerError::MrcDisabled
+			| ServerError::ClusterDisabled
+			| ServerError::InvalidSlotRange
+			| ServerError::UnknownNode(_)
+			| ServerError::MigrationFailed(_)
+			| ServerError::InvalidObject			=> ErrorCode::SERVER,
 
-		ServerError::MaxConnectionsExceeded			=> 2,
-		ServerError::Unauthorized					=> 3,
+		ServerError::MaxConnectionsExceeded			=> ErrorCode::MAX_CONNECTIONS,
+		ServerError::Unauthorized					=> ErrorCode::UNAUTHORIZED,
+		ServerError::Moved(_, _)					=> ErrorCode::MOVED,
+		ServerError::BackendUnavailable(_)			=> ErrorCode::BACKEND_UNAVAILABLE,
 	}
 }
 
-fn get_cache_error_code(error: &CacheError) -> u8 {
-	match error {
-		CacheError::KeyNotFound			=> 1,
+fn get_cache_error_code(error: &ServerError) -> Option<u8> {
+	let code = match error {
+		ServerError::CacheError(err) => match err {
+			CacheError::KeyNotFound			=> CacheErrorCode::KEY_NOT_FOUND,
+
+			CacheError::ZeroValueSize		=> CacheErrorCode::ZERO_VALUE_SIZE,
+			CacheError::ExceedingValueSize	=> CacheErrorCode::EXCEEDING_VALUE_SIZE,
+
+			CacheError::ZeroCacheSize		=> CacheErrorCode::ZERO_CACHE_SIZE,
+
+			CacheError::UnconfiguredPolicy	=> CacheErrorCode::UNCONFIGURED_POLICY,
+			CacheError::InvalidPolicy		=> CacheErrorCode::INVALID_POLICY,
+
+			_								=> CacheErrorCode::OTHER,
+		},
+
+		ServerError::VersionMismatch		=> CacheErrorCode::VERSION_MISMATCH,
+		ServerError::PinnedSizeExceeded		=> CacheErrorCode::PINNED_SIZE_EXCEEDED,
+		ServerError::NotAdmitted			=> CacheErrorCode::NOT_ADMITTED,
+
+		_									=> return None,
+	};
 
-		CacheError::ZeroValueSize		=> 2,
-		CacheError::ExceedingValueSize	=> 3,
+	Some(code)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn it_maps_cache_error_codes_both_ways() {
+		for code in CacheErrorCode::KEY_NOT_FOUND..=CacheErrorCode::NOT_ADMITTED {
+			let error = ServerError::from_cache_error_code(code).unwrap();
+
+			assert_eq!(get_error_code(&error), ErrorCode::CACHE);
+			assert_eq!(get_cache_error_code(&error), Some(code));
+		}
+
+		assert_eq!(ServerError::from_cache_error_code(CacheErrorCode::OTHER), None);
+	}
 
-		CacheError::ZeroCacheSize		=> 4,
+	#[test]
+	fn it_writes_a_redirect_with_its_owner() {
+		let sheet = ServerError::Moved(7, "127.0.0.1:3145".into()).to_sheet();
 
-		CacheError::UnconfiguredPolicy	=> 5,
-		CacheError::InvalidPolicy		=> 6,
+		let expected = SheetBuilder::new()
+			.write_bool(false)
+			.write_u8(ErrorCode::MOVED)
+			.write_u32(7)
+			.write_str("127.0.0.1:3145".into())
+			.into_sheet();
 
-		_								=> 0,
+		assert_eq!(sheet.serialize(), expected.serialize());
 	}
 }
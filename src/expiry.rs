@@ -0,0 +1,142 @@
+/*
+ * Copyright (c) Kia Shakiba
+ *
+ * This source code is licensed under the GNU AGPLv3 license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Expiry {
+	Never,
+
+	Seconds(u64),
+	Millis(u64),
+
+	UnixSeconds(u64),
+	UnixMillis(u64),
+}
+
+impl Expiry {
+	/// Converts the expiry into an absolute unix timestamp in milliseconds,
+	/// or `None` if the object should never expire.
+	pub fn expires_at(&self, now: u64) -> Option<u64> {
+		match *self {
+			Expiry::Never => None,
+
+			Expiry::Seconds(seconds) => Some(now.saturating_add(seconds.saturating_mul(1000))),
+			Expiry::Millis(millis) => Some(now.saturating_add(millis)),
+
+			Expiry::UnixSeconds(seconds) => Some(seconds.saturating_mul(1000)),
+			Expiry::UnixMillis(millis) => Some(millis),
+		}
+	}
+}
+
+/// Parses an expiry from the leading arguments of a command and returns the
+/// remaining arguments. The expiry is either a TTL in seconds, where zero
+/// means the object never expires, or one of `ex <seconds>`,
+/// `px <milliseconds>`, `exat <unix seconds>` and `pxat <unix milliseconds>`.
+/// No arguments mean the object never expires.
+pub fn parse_args<S>(args: &[S]) -> Result<(Expiry, &[S]), String>
+where
+	S: AsRef<str>,
+{
+	let Some((first, rest)) = args.split_first() else {
+		return Ok((Expiry::Never, args));
+	};
+
+	let first = first.as_ref();
+
+	let kind: Option<fn(u64) -> Expiry> = match first.to_lowercase().as_str() {
+		"ex" => Some(Expiry::Seconds),
+		"px" => Some(Expiry::Millis),
+		"exat" => Some(Expiry::UnixSeconds),
+		"pxat" => Some(Expiry::UnixMillis),
+
+		_ => None,
+	};
+
+	let Some(kind) = kind else {
+		return match first.parse::<u64>() {
+			Ok(0) => Ok((Expiry::Never, rest)),
+			Ok(seconds) => Ok((Expiry::Seconds(seconds), rest)),
+			Err(_) => Err(format!("invalid ttl <{first}>")),
+		};
+	};
+
+	let Some((value, rest)) = rest.split_first() else {
+		return Err(format!("expected a value after <{first}>"));
+	};
+
+	let value = value.as_ref();
+
+	match value.parse::<u64>() {
+		Ok(value) if value > 0 => Ok((kind(value), rest)),
+		_ => Err(format!("invalid expiry <{first} {value}>")),
+	}
+}
+
+/// Returns the current unix timestamp in milliseconds.
+pub fn now_millis() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|duration| duration.as_millis() as u64)
+		.unwrap_or(0)
+}
+
+/// Converts a remaining lifetime in milliseconds into the TTL in seconds
+/// which is passed to the cache, rounding up so the cache never expires an
+/// object before the server considers it expired.
+pub fn ttl_seconds(remaining_millis: u64) -> u32 {
+	remaining_millis
+		.div_ceil(1000)
+		.clamp(1, u32::MAX as u64) as u32
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn it_resolves_relative_expiries() {
+		assert_eq!(Expiry::Never.expires_at(1000), None);
+		assert_eq!(Expiry::Seconds(2).expires_at(1000), Some(3000));
+		assert_eq!(Expiry::Millis(2).expires_at(1000), Some(1002));
+	}
+
+	#[test]
+	fn it_resolves_absolute_expiries() {
+		assert_eq!(Expiry::UnixSeconds(5).expires_at(1000), Some(5000));
+		assert_eq!(Expiry::UnixMillis(5).expires_at(1000), Some(5));
+	}
+
+	#[test]
+	fn it_parses_expiry_args() {
+		assert_eq!(parse_args::<&str>(&[]), Ok((Expiry::Never, &[][..])));
+		assert_eq!(parse_args(&["0"]), Ok((Expiry::Never, &[][..])));
+		assert_eq!(parse_args(&["10", "tag"]), Ok((Expiry::Seconds(10), &["tag"][..])));
+
+		assert_eq!(parse_args(&["EX", "10"]), Ok((Expiry::Seconds(10), &[][..])));
+		assert_eq!(parse_args(&["px", "1500", "tag"]), Ok((Expiry::Millis(1500), &["tag"][..])));
+		assert_eq!(parse_args(&["exat", "1700000000"]), Ok((Expiry::UnixSeconds(1_700_000_000), &[][..])));
+		assert_eq!(parse_args(&["pxat", "1700000000000"]), Ok((Expiry::UnixMillis(1_700_000_000_000), &[][..])));
+	}
+
+	#[test]
+	fn it_rejects_invalid_expiry_args() {
+		assert!(parse_args(&["soon"]).is_err());
+		assert!(parse_args(&["px"]).is_err());
+		assert!(parse_args(&["px", "0"]).is_err());
+		assert!(parse_args(&["pxat", "-1"]).is_err());
+	}
+
+	#[test]
+	fn it_rounds_ttls_up_to_whole_seconds() {
+		assert_eq!(ttl_seconds(0), 1);
+		assert_eq!(ttl_seconds(1), 1);
+		assert_eq!(ttl_seconds(1000), 1);
+		assert_eq!(ttl_seconds(1001), 2);
+	}
+}
@@ -0,0 +1,361 @@
+/*
+ * Copyright (c) Kia Shakiba
+ *
+ * This source code is licensed under the GNU AGPLv3 license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::{
+	fs::{self, File, OpenOptions},
+	io::{self, Seek, SeekFrom},
+	path::Path,
+	collections::{HashMap, VecDeque},
+	sync::{Mutex, MutexGuard},
+};
+
+use memmap2::{MmapMut, MmapOptions};
+use log::info;
+use paper_utils::stream::Buffer;
+
+use crate::{
+	numa,
+	error::ServerError,
+	tier::{TierConfig, TierBacking, TierStats, TierStatus},
+};
+
+const DAX_DEVICES_PATH: &str = "/sys/bus/dax/devices";
+
+/// A far memory tier. Objects are written sequentially into a ring over
+/// the tier's memory, which suits the write characteristics of far memory,
+/// and the oldest objects are evicted once the ring wraps around. Only the
+/// object values are held in far memory; the index stays in DRAM.
+pub struct FarTier {
+	name: String,
+
+	ring: Mutex<Ring>,
+	stats: TierStats,
+}
+
+pub struct Ring {
+	memory: MmapMut,
+	head: usize,
+	used_size: usize,
+
+	index: HashMap<Buffer, Slot>,
+	entries: VecDeque<Entry>,
+}
+
+#[derive(Clone, Copy)]
+struct Slot {
+	offset: usize,
+	len: usize,
+}
+
+struct Entry {
+	key: Buffer,
+	slot: Slot,
+}
+
+impl FarTier {
+	pub fn new(config: &TierConfig) -> Result<Self, ServerError> {
+		if let TierBacking::Device(path) = config.backing() {
+			// mapping past the end of the device would fault on access
+			let device_size = device_size(path)
+				.map_err(|_| ServerError::InvalidTier(config.name().into()))?;
+
+			if device_size < config.size() {
+				return Err(ServerError::InvalidTierSize(config.name().into(), device_size));
+			}
+		}
+
+		let memory = map_memory(config)
+			.map_err(|_| ServerError::InvalidTier(config.name().into()))?;
+
+		info!("Mapped {} bytes for tier {}", memory.len(), config.name());
+
+		let ring = Ring {
+			memory,
+			head: 0,
+			used_size: 0,
+
+			index: HashMap::new(),
+			entries: VecDeque::new(),
+		};
+
+		Ok(FarTier {
+			name: config.name().to_owned(),
+
+			ring: Mutex::new(ring),
+			stats: TierStats::default(),
+		})
+	}
+
+	pub fn stats(&self) -> &TierStats {
+		&self.stats
+	}
+
+	pub fn status(&self) -> TierStatus {
+		let ring = self.lock();
+
+		TierStatus {
+			name: self.name.clone(),
+
+			used_size: ring.used_size as u64,
+			max_size: ring.memory.len() as u64,
+			num_objects: ring.index.len() as u64,
+
+			hits: self.stats.hits(),
+			promotions: self.stats.promotions(),
+			demotions: self.stats.demotions(),
+
+			avg_access_nanos: self.stats.avg_access_nanos(),
+		}
+	}
+
+	pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+		self.lock().get(key)
+	}
+
+	pub fn lock(&self) -> MutexGuard<'_, Ring> {
+		self.ring
+			.lock()
+			.unwrap_or_else(|err| err.into_inner())
+	}
+}
+
+impl Ring {
+	pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+		let slot = self.index.get(key)?;
+		Some(self.memory[slot.offset..slot.offset + slot.len].to_vec())
+	}
+
+	/// Writes the object at the head of the ring, evicting the objects it
+	/// overwrites. If `should_collect` is set, the evicted objects which
+	/// were still current are returned so they can be demoted.
+	pub fn insert(
+		&mut self,
+		key: &Buffer,
+		value: &[u8],
+		should_collect: bool,
+	) -> Vec<(Buffer, Vec<u8>)> {
+		let mut evicted = Vec::new();
+
+		if value.len() > self.memory.len() {
+			// the object can never fit, so any stale copy must not be served
+			self.remove(key);
+			return evicted;
+		}
+
+		if self.head + value.len() > self.memory.len() {
+			// the objects between the head and the end of the ring are the
+			// oldest in the ring, so they are evicted before wrapping around
+			while let Some(entry) = self.entries.front() && entry.slot.offset >= self.head {
+				self.evict_front(should_collect, &mut evicted);
+			}
+
+			self.head = 0;
+		}
+
+		let end = self.head + value.len();
+
+		while let Some(entry) = self.entries.front()
+			&& entry.slot.offset >= self.head
+			&& entry.slot.offset < end
+		{
+			self.evict_front(should_collect, &mut evicted);
+		}
+
+		let slot = Slot {
+			offset: self.head,
+			len: value.len(),
+		};
+
+		self.memory[slot.offset..end].copy_from_slice(value);
+		self.remove(key);
+
+		self.index.insert(key.clone(), slot);
+		self.used_size += slot.len;
+
+		self.entries.push_back(Entry {
+			key: key.clone(),
+			slot,
+		});
+
+		self.head = end;
+
+		evicted
+	}
+
+	pub fn remove(&mut self, key: &[u8]) -> bool {
+		let Some(slot) = self.index.remove(key) else {
+			return false;
+		};
+
+		self.used_size -= slot.len;
+		true
+	}
+
+	pub fn clear(&mut self) {
+		self.index.clear();
+		self.entries.clear();
+
+		self.head = 0;
+		self.used_size = 0;
+	}
+
+	fn evict_front(&mut self, should_collect: bool, evicted: &mut Vec<(Buffer, Vec<u8>)>) {
+		let Some(entry) = self.entries.pop_front() else {
+			return;
+		};
+
+		// the key may have been written again since this entry, in which
+		// case the entry is already stale
+		let is_current = self.index
+			.get(&entry.key[..])
+			.is_some_and(|slot| slot.offset == entry.slot.offset);
+
+		if !is_current {
+			return;
+		}
+
+		self.remove(&entry.key);
+
+		if should_collect {
+			let value = self.memory[entry.slot.offset..entry.slot.offset + entry.slot.len].to_vec();
+			evicted.push((entry.key, value));
+		}
+	}
+}
+
+/// Returns the size of a block device or file, or of a character DAX
+/// device (whose size is only reported by sysfs).
+fn device_size(path: &Path) -> io::Result<u64> {
+	let size = File::open(path)?.seek(SeekFrom::End(0))?;
+
+	if size > 0 {
+		return Ok(size);
+	}
+
+	let name = path
+		.file_name()
+		.ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+
+	fs::read_to_string(Path::new(DAX_DEVICES_PATH).join(name).join("size"))?
+		.trim()
+		.parse()
+		.map_err(io::Error::other)
+}
+
+fn map_memory(config: &TierConfig) -> io::Result<MmapMut> {
+	let size = config.size() as usize;
+
+	match config.backing() {
+		TierBacking::Dram | TierBacking::Anonymous => MmapMut::map_anon(size),
+
+		TierBacking::NumaNode(node) => {
+			let memory = MmapMut::map_anon(size)?;
+			numa::bind_range(memory.as_ptr(), memory.len(), *node)?;
+
+			Ok(memory)
+		},
+
+		TierBacking::Device(path) => {
+			let device = OpenOptions::new()
+				.read(true)
+				.write(true)
+				.open(path)?;
+
+			unsafe { MmapOptions::new().len(size).map_mut(&device) }
+		},
+
+		TierBacking::File(path) => {
+			let file = OpenOptions::new()
+				.read(true)
+				.write(true)
+				.create(true)
+				.truncate(true)
+				.open(path)?;
+
+			file.set_len(size as u64)?;
+
+			unsafe { MmapOptions::new().len(size).map_mut(&file) }
+		},
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{fs, path::PathBuf};
+	use super::*;
+
+	fn temp_path(name: &str) -> PathBuf {
+		std::env::temp_dir().join(format!("paper-far-tier-{}-{name}", std::process::id()))
+	}
+
+	fn tier(config: &str) -> Result<FarTier, ServerError> {
+		FarTier::new(&config.parse::<TierConfig>().unwrap())
+	}
+
+	fn key(index: u8) -> Buffer {
+		Buffer::from(&[index][..])
+	}
+
+	#[test]
+	fn it_evicts_the_oldest_objects_when_the_ring_wraps() {
+		let tier = tier("far:100").unwrap();
+		let mut ring = tier.lock();
+
+		for index in 0..5 {
+			assert!(ring.insert(&key(index), &[index; 20], true).is_empty());
+		}
+
+		let evicted = ring.insert(&key(5), &[5; 30], true);
+
+		assert_eq!(evicted, [(key(0), vec![0; 20]), (key(1), vec![1; 20])]);
+		assert_eq!(ring.get(&key(0)), None);
+		assert_eq!(ring.get(&key(2)), Some(vec![2; 20]));
+		assert_eq!(ring.get(&key(5)), Some(vec![5; 30]));
+	}
+
+	#[test]
+	fn it_does_not_collect_overwritten_objects() {
+		let tier = tier("far:100").unwrap();
+		let mut ring = tier.lock();
+
+		ring.insert(&key(0), &[0; 50], true);
+		ring.insert(&key(0), &[1; 50], true);
+
+		let evicted = ring.insert(&key(2), &[2; 50], true);
+
+		assert!(evicted.is_empty());
+		assert_eq!(ring.get(&key(0)), Some(vec![1; 50]));
+	}
+
+	#[test]
+	fn it_maps_a_file_backed_tier() {
+		let path = temp_path("file");
+		let tier = tier(&format!("far:4KiB,file={}", path.display())).unwrap();
+
+		tier.lock().insert(&key(0), b"value", false);
+
+		assert_eq!(tier.get(&key(0)), Some(b"value".to_vec()));
+		assert_eq!(fs::metadata(&path).unwrap().len(), 4096);
+
+		fs::remove_file(path).unwrap();
+	}
+
+	#[test]
+	fn it_rejects_a_tier_larger_than_its_device() {
+		let path = temp_path("device");
+		fs::write(&path, [0; 4096]).unwrap();
+
+		assert!(tier(&format!("far:4KiB,path={}", path.display())).is_ok());
+
+		assert_eq!(
+			tier(&format!("far:8KiB,path={}", path.display())).err(),
+			Some(ServerError::InvalidTierSize("far".into(), 4096)),
+		);
+
+		fs::remove_file(path).unwrap();
+	}
+}
@@ -0,0 +1,165 @@
+/*
+ * Copyright (c) Kia Shakiba
+ *
+ * This source code is licensed under the GNU AGPLv3 license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::{
+	collections::BTreeSet,
+	ops::Bound,
+	sync::{Mutex, RwLock},
+};
+
+use paper_utils::stream::Buffer;
+
+/// An ordered index of the keys written to the cache, used to iterate keys
+/// by prefix. The cache evicts and expires objects without notifying the
+/// server, so the index may hold keys which are no longer in the cache.
+/// Those keys are pruned by the store as they are encountered, and by the
+/// sweep which the store runs on every write and periodically.
+#[derive(Default)]
+pub struct KeyIndex {
+	keys: RwLock<BTreeSet<Buffer>>,
+	sweep_cursor: Mutex<Option<Buffer>>,
+}
+
+impl KeyIndex {
+	pub fn insert(&self, key: &Buffer) {
+		let mut keys = self.keys
+			.write()
+			.unwrap_or_else(|err| err.into_inner());
+
+		if !keys.contains(&key[..]) {
+			keys.insert(key.clone());
+		}
+	}
+
+	pub fn remove(&self, key: &[u8]) {
+		self.keys
+			.write()
+			.unwrap_or_else(|err| err.into_inner())
+			.remove(key);
+	}
+
+	pub fn clear(&self) {
+		self.keys
+			.write()
+			.unwrap_or_else(|err| err.into_inner())
+			.clear();
+	}
+
+	/// Returns up to `count` keys which start with `prefix` and come after
+	/// `cursor` (or from the start of the prefix if `cursor` is empty), and
+	/// whether the iteration has reached the end of the prefix.
+	pub fn range(
+		&self,
+		cursor: &[u8],
+		prefix: &[u8],
+		count: usize,
+	) -> (Vec<Buffer>, bool) {
+		let keys = self.keys
+			.read()
+			.unwrap_or_else(|err| err.into_inner());
+
+		let start = match cursor.is_empty() || cursor < prefix {
+			true => Bound::Included(prefix),
+			false => Bound::Excluded(cursor),
+		};
+
+		let mut iter = keys
+			.range::<[u8], _>((start, Bound::Unbounded))
+			.take_while(|key| key.starts_with(prefix));
+
+		let batch = iter
+			.by_ref()
+			.take(count)
+			.cloned()
+			.collect::<Vec<_>>();
+
+		let is_done = iter.next().is_none();
+
+		(batch, is_done)
+	}
+
+	/// Returns the next `count` keys in the background sweep of the index,
+	/// wrapping around to the start once the end is reached.
+	pub fn next_sweep(&self, count: usize) -> Vec<Buffer> {
+		let mut sweep_cursor = self.sweep_cursor
+			.lock()
+			.unwrap_or_else(|err| err.into_inner());
+
+		let cursor = sweep_cursor.take().unwrap_or_default();
+		let (batch, is_done) = self.range(&cursor, &[], count);
+
+		if !is_done {
+			*sweep_cursor = batch.last().cloned();
+		}
+
+		batch
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn index(keys: &[&str]) -> KeyIndex {
+		let index = KeyIndex::default();
+
+		for key in keys {
+			index.insert(&Buffer::from(key.as_bytes()));
+		}
+
+		index
+	}
+
+	fn strings(keys: &[Buffer]) -> Vec<&str> {
+		keys
+			.iter()
+			.map(|key| std::str::from_utf8(key).unwrap())
+			.collect()
+	}
+
+	#[test]
+	fn it_iterates_a_prefix_in_batches() {
+		let index = index(&["a:1", "a:2", "a:3", "b:1"]);
+
+		let (batch, is_done) = index.range(b"", b"a:", 2);
+		assert_eq!(strings(&batch), ["a:1", "a:2"]);
+		assert!(!is_done);
+
+		let (batch, is_done) = index.range(&batch[1], b"a:", 2);
+		assert_eq!(strings(&batch), ["a:3"]);
+		assert!(is_done);
+	}
+
+	#[test]
+	fn it_finishes_a_batch_which_ends_at_the_prefix_end() {
+		let index = index(&["a:1", "a:2", "b:1"]);
+
+		let (batch, is_done) = index.range(b"", b"a:", 2);
+
+		assert_eq!(strings(&batch), ["a:1", "a:2"]);
+		assert!(is_done);
+	}
+
+	#[test]
+	fn it_continues_from_a_removed_cursor() {
+		let index = index(&["a", "b", "c"]);
+
+		index.remove(b"b");
+
+		let (batch, _) = index.range(b"b", b"", 10);
+		assert_eq!(strings(&batch), ["c"]);
+	}
+
+	#[test]
+	fn it_wraps_the_sweep_around() {
+		let index = index(&["a", "b", "c"]);
+
+		assert_eq!(strings(&index.next_sweep(2)), ["a", "b"]);
+		assert_eq!(strings(&index.next_sweep(2)), ["c"]);
+		assert_eq!(strings(&index.next_sweep(2)), ["a", "b"]);
+	}
+}
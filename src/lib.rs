@@ -0,0 +1,47 @@
+/*
+ * Copyright (c) Kia Shakiba
+ *
+ * This source code is licensed under the GNU AGPLv3 license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+pub mod trace;
+pub mod command;
+pub mod expiry;
+pub mod node_client;
+pub mod error;
+
+mod object;
+mod store;
+mod key_index;
+mod tag_index;
+mod resident_index;
+mod tier;
+mod far_tier;
+mod pinned;
+mod admission;
+mod namespace;
+mod cluster;
+mod migration;
+mod proxy;
+mod numa;
+mod memory_limit;
+mod mrc;
+mod policy_history;
+mod server;
+mod connection;
+mod config;
+
+pub use crate::{
+	error::ServerError,
+	config::{Config, ConfigBuilder, ConfigFormat, ConfigIssue, ConfigIssueKind, ConfigOverrides},
+	tier::TierConfig,
+	namespace::NamespaceConfig,
+	cluster::ClusterNodeConfig,
+	admission::AdmissionPolicy,
+	numa::MemoryPolicy,
+	memory_limit::{MemorySize, RelativeMaxSize},
+	store::Cache,
+	server::{Server, ServerBuilder, ServerHandle},
+	proxy::Proxy,
+};
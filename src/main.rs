@@ -6,13 +6,11 @@
  */
 
 mod logo;
-mod error;
-mod command;
-mod server;
-mod connection;
-mod config;
 
-use std::path::{Path, PathBuf};
+use std::{
+	process,
+	path::{Path, PathBuf},
+};
 use clap::Parser;
 use dotenv::dotenv;
 use log::error;
@@ -20,57 +18,186 @@ use log::error;
 #[cfg(not(target_env = "msvc"))]
 use tikv_jemallocator::Jemalloc;
 
-use crate::{
-	server::{Server, Cache},
-	config::Config,
-};
+use paper_server::{ServerBuilder, Proxy, Config, ConfigOverrides};
 
 #[cfg(not(target_env = "msvc"))]
 #[global_allocator]
 static GLOBAL: Jemalloc = Jemalloc;
 
 #[derive(Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(
+	author,
+	version,
+	about,
+	long_about = None,
+	after_help = "Every config value is taken from its flag, else its PAPER_<KEY> environment \
+		variable, else the config file, else the default config. The items of a list are \
+		separated by semicolons.",
+)]
 struct Args {
-	/// Optional path to PaperConfig (pconf) file
+	/// Optional path to config file, either a PaperConfig (pconf) file or a
+	/// TOML, YAML or JSON file with the same keys
 	#[arg(short, long)]
 	config: Option<PathBuf>,
 
 	#[arg(short, long)]
 	/// Optional path to log4rs config file
 	log_config: Option<PathBuf>,
+
+	/// Forward commands to the configured cluster nodes instead of serving
+	/// them from a local cache
+	#[arg(long)]
+	proxy: bool,
+
+	/// Print the JSON schema of TOML, YAML and JSON config files and exit
+	#[arg(long)]
+	config_schema: bool,
+
+	/// Report every problem in the config and exit without starting the
+	/// server
+	#[arg(long)]
+	check_config: bool,
+
+	#[command(flatten)]
+	overrides: OverrideArgs,
+}
+
+/// Overrides of the config's keys, which take precedence over the
+/// `PAPER_<KEY>` environment variables, which in turn take precedence over
+/// the config file. The values are written the same way as in a pconf
+/// file, and the items of a list are separated by semicolons.
+#[derive(clap::Args)]
+#[command(next_help_heading = "Config overrides")]
+struct OverrideArgs {
+	/// Host on which the server listens [env: PAPER_HOST]
+	#[arg(long, value_name = "HOST")]
+	host: Option<String>,
+
+	/// Port on which the server listens [env: PAPER_PORT]
+	#[arg(long, value_name = "PORT")]
+	port: Option<String>,
+
+	/// Maximum size of the cache [env: PAPER_MAX_SIZE]
+	#[arg(long, value_name = "SIZE")]
+	max_size: Option<String>,
+
+	/// Memory not used by a relative max size [env: PAPER_MAX_SIZE_HEADROOM]
+	#[arg(long, value_name = "SIZE")]
+	max_size_headroom: Option<String>,
+
+	/// Configured eviction policies [env: PAPER_POLICIES]
+	#[arg(long, value_name = "POLICY", value_delimiter = ';')]
+	policies: Vec<String>,
+
+	/// Initial eviction policy [env: PAPER_POLICY]
+	#[arg(long, value_name = "POLICY")]
+	policy: Option<String>,
+
+	/// Memory tiers of the cache [env: PAPER_TIERS]
+	#[arg(long, value_name = "TIER", value_delimiter = ';')]
+	tiers: Vec<String>,
+
+	/// Namespaces of the cache [env: PAPER_NAMESPACES]
+	#[arg(long, value_name = "NAMESPACE", value_delimiter = ';')]
+	namespaces: Vec<String>,
+
+	/// Admission policy of the cache [env: PAPER_ADMISSION]
+	#[arg(long, value_name = "POLICY")]
+	admission: Option<String>,
+
+	/// Sample rate of the miss-ratio curves [env: PAPER_MRC_SAMPLE_RATE]
+	#[arg(long, value_name = "RATE")]
+	mrc_sample_rate: Option<String>,
+
+	/// Directory of the trace files written by TRACE [env: PAPER_TRACE_DIR]
+	#[arg(long, value_name = "DIR")]
+	trace_dir: Option<String>,
+
+	/// Maximum total size of the pinned objects [env: PAPER_MAX_PINNED_SIZE]
+	#[arg(long, value_name = "SIZE")]
+	max_pinned_size: Option<String>,
+
+	/// Maximum number of concurrent connections [env: PAPER_MAX_CONNECTIONS]
+	#[arg(long, value_name = "COUNT")]
+	max_connections: Option<String>,
+
+	/// Authorization token of the clients [env: PAPER_AUTH_TOKEN, which is
+	/// preferred since flags are visible to other processes]
+	#[arg(long, value_name = "TOKEN")]
+	auth_token: Option<String>,
+
+	/// ID of this server in the cluster [env: PAPER_CLUSTER_NODE]
+	#[arg(long, value_name = "ID")]
+	cluster_node: Option<String>,
+
+	/// Nodes of the cluster [env: PAPER_CLUSTER_NODES]
+	#[arg(long, value_name = "NODE", value_delimiter = ';')]
+	cluster_nodes: Vec<String>,
+
+	/// Token with which cluster connections are authorized [env: PAPER_CLUSTER_AUTH_TOKEN]
+	#[arg(long, value_name = "TOKEN")]
+	cluster_auth_token: Option<String>,
+
+	/// CPUs to which the worker threads are pinned [env: PAPER_WORKER_CPUS]
+	#[arg(long, value_name = "CPUS")]
+	worker_cpus: Option<String>,
+
+	/// NUMA nodes to which the worker threads are pinned [env: PAPER_WORKER_NODES]
+	#[arg(long, value_name = "NODES")]
+	worker_nodes: Option<String>,
+
+	/// NUMA nodes on which the cache is allocated [env: PAPER_MEMORY_NODES]
+	#[arg(long, value_name = "NODES")]
+	memory_nodes: Option<String>,
+
+	/// NUMA policy of the cache's memory [env: PAPER_MEMORY_POLICY]
+	#[arg(long, value_name = "POLICY")]
+	memory_policy: Option<String>,
 }
 
 fn main() {
 	let args = Args::parse();
 
+	if args.config_schema {
+		let schema = schemars::schema_for!(Config);
+		println!("{}", serde_json::to_string_pretty(&schema).expect("Invalid config schema"));
+		return;
+	}
+
 	dotenv().ok();
+
+	let mut overrides = ConfigOverrides::from_env();
+	overrides.extend(args.overrides.into_overrides());
+
+	if args.check_config {
+		process::exit(check_config(args.config.as_deref(), &overrides));
+	}
+
 	init_logging(args.log_config);
 
-	let config = match &args.config {
-		Some(path) => match Config::from_file(path) {
-			Ok(config) => config,
+	let config = match Config::load(args.config.as_deref(), &overrides) {
+		Ok(config) => config,
 
-			Err(err) => {
-				error!("{err}");
-				return;
-			},
+		Err(err) => {
+			error!("{err}");
+			return;
 		},
-
-		None => Config::default(),
 	};
 
-	let cache = Cache::new(
-		config.max_size(),
-		config.policies(),
-		config.policy(),
-	).expect("Could not configure cache");
+	if args.proxy {
+		run_proxy(&config);
+		return;
+	}
+
+	let port = config.port();
 
-	let cache_version = cache.version();
+	let server_builder = ServerBuilder::new()
+		.config(config)
+		.handle_reload_signal();
 
-	let mut server = match Server::new(&config, cache) {
+	let mut server = match server_builder.build() {
 		Ok(server) => {
-			logo::print(&cache_version, config.port());
+			logo::print(&server.version(), port);
 			server
 		},
 
@@ -80,8 +207,100 @@ fn main() {
 		},
 	};
 
+	server.run();
+}
+
+fn run_proxy(config: &Config) {
+	let mut proxy = match Proxy::new(config) {
+		Ok(proxy) => {
+			logo::print(env!("CARGO_PKG_VERSION"), config.port());
+			proxy
+		},
+
+		Err(err) => {
+			error!("{err}");
+			return;
+		},
+	};
+
 	loop {
-		let _ = server.listen();
+		let _ = proxy.listen();
+	}
+}
+
+impl OverrideArgs {
+	fn into_overrides(self) -> ConfigOverrides {
+		let mut overrides = ConfigOverrides::new();
+
+		let values = [
+			("host", "--host", self.host.into_iter().collect()),
+			("port", "--port", self.port.into_iter().collect()),
+
+			("max_size", "--max-size", self.max_size.into_iter().collect()),
+			("max_size_headroom", "--max-size-headroom", self.max_size_headroom.into_iter().collect()),
+			("policies[]", "--policies", self.policies),
+			("policy", "--policy", self.policy.into_iter().collect()),
+			("tiers[]", "--tiers", self.tiers),
+			("namespaces[]", "--namespaces", self.namespaces),
+			("admission", "--admission", self.admission.into_iter().collect()),
+			("mrc_sample_rate", "--mrc-sample-rate", self.mrc_sample_rate.into_iter().collect()),
+			("trace_dir", "--trace-dir", self.trace_dir.into_iter().collect()),
+			("max_pinned_size", "--max-pinned-size", self.max_pinned_size.into_iter().collect()),
+
+			("max_connections", "--max-connections", self.max_connections.into_iter().collect()),
+			("auth_token", "--auth-token", self.auth_token.into_iter().collect()),
+
+			("cluster_node", "--cluster-node", self.cluster_node.into_iter().collect()),
+			("cluster_nodes[]", "--cluster-nodes", self.cluster_nodes),
+			("cluster_auth_token", "--cluster-auth-token", self.cluster_auth_token.into_iter().collect()),
+
+			("worker_cpus", "--worker-cpus", self.worker_cpus.into_iter().collect()),
+			("worker_nodes", "--worker-nodes", self.worker_nodes.into_iter().collect()),
+			("memory_nodes", "--memory-nodes", self.memory_nodes.into_iter().collect()),
+			("memory_policy", "--memory-policy", self.memory_policy.into_iter().collect()),
+		];
+
+		for (key, flag, values) in values {
+			if !values.is_empty() {
+				overrides.set(key, flag, values);
+			}
+		}
+
+		overrides
+	}
+}
+
+/// Prints every problem in the config, returning the exit code of the
+/// check, which is non-zero if the config has any errors.
+fn check_config(path: Option<&Path>, overrides: &ConfigOverrides) -> i32 {
+	let name = path.map_or("config".into(), |path| path.display().to_string());
+
+	let issues = match Config::check_with_overrides(path, overrides) {
+		Ok(issues) => issues,
+
+		Err(err) => {
+			eprintln!("{name}: error: {err}");
+			return 1;
+		},
+	};
+
+	for issue in &issues {
+		eprintln!("{name}: {issue}");
+	}
+
+	let num_errors = issues.iter().filter(|issue| issue.is_error()).count();
+	let num_warnings = issues.len() - num_errors;
+
+	match num_errors {
+		0 => {
+			println!("{name}: ok, {num_warnings} warning(s)");
+			0
+		},
+
+		_ => {
+			println!("{name}: {num_errors} error(s), {num_warnings} warning(s)");
+			1
+		},
 	}
 }
 
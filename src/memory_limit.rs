@@ -0,0 +1,288 @@
+/*
+ * Copyright (c) Kia Shakiba
+ *
+ * This source code is licensed under the GNU AGPLv3 license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::{
+	fs,
+	fmt,
+	path::Path,
+	sync::atomic::{AtomicBool, Ordering},
+};
+
+use parse_size::parse_size;
+use crate::error::ServerError;
+
+const CGROUP_PATH: &str = "/sys/fs/cgroup";
+const PROC_CGROUP_PATH: &str = "/proc/self/cgroup";
+
+// the max size used when a relative max size cannot be resolved because the
+// memory limit cannot be detected, which was the fixed default max_size
+// before it became relative
+pub const FALLBACK_MAX_SIZE: u64 = 232 * 1024 * 1024 * 1024;
+
+static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// A size which is either a number of bytes or relative to the memory
+/// available to the server, as a percentage (e.g. `80%`) or as all of it
+/// (`auto`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MemorySize {
+	Bytes(u64),
+	Percent(f64),
+	Auto,
+}
+
+/// A max size which is resolved against the memory available to the
+/// server, after the headroom and the memory reserved outside of the cache
+/// (such as the pinned objects and the namespaces) are subtracted.
+#[derive(Debug, Clone, Copy)]
+pub struct RelativeMaxSize {
+	size: MemorySize,
+	headroom: MemorySize,
+	reserved: u64,
+}
+
+impl MemorySize {
+	pub fn parse(value: &str) -> Option<Self> {
+		if value == "auto" {
+			return Some(MemorySize::Auto);
+		}
+
+		if let Some(percent) = value.strip_suffix('%') {
+			return match percent.trim().parse::<f64>() {
+				Ok(percent) if percent > 0.0 && percent <= 100.0 => Some(MemorySize::Percent(percent)),
+				_ => None,
+			};
+		}
+
+		parse_size(value).ok().map(MemorySize::Bytes)
+	}
+
+	pub fn is_relative(&self) -> bool {
+		!matches!(self, MemorySize::Bytes(_))
+	}
+
+	/// Resolves the size against the supplied amount of memory.
+	pub fn resolve(&self, memory: u64) -> u64 {
+		match self {
+			MemorySize::Bytes(size) => *size,
+			MemorySize::Percent(percent) => (memory as f64 * percent / 100.0) as u64,
+			MemorySize::Auto => memory,
+		}
+	}
+}
+
+impl From<u64> for MemorySize {
+	fn from(size: u64) -> Self {
+		MemorySize::Bytes(size)
+	}
+}
+
+impl fmt::Display for MemorySize {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			MemorySize::Bytes(size) => write!(f, "{size}"),
+			MemorySize::Percent(percent) => write!(f, "{percent}%"),
+			MemorySize::Auto => write!(f, "auto"),
+		}
+	}
+}
+
+impl RelativeMaxSize {
+	pub fn new(size: MemorySize, headroom: MemorySize, reserved: u64) -> Self {
+		RelativeMaxSize {
+			size,
+			headroom,
+			reserved,
+		}
+	}
+
+	/// Resolves the max size against the current memory limit.
+	pub fn resolve(&self) -> Result<u64, ServerError> {
+		match memory_limit() {
+			Some(limit) => self.resolve_against(limit),
+			None => Err(ServerError::InvalidMaxSize("could not detect the memory limit".into())),
+		}
+	}
+
+	pub fn resolve_against(&self, limit: u64) -> Result<u64, ServerError> {
+		let available = limit
+			.saturating_sub(self.headroom.resolve(limit))
+			.saturating_sub(self.reserved);
+
+		match self.size.resolve(available) {
+			0 => Err(ServerError::InvalidMaxSize(format!(
+				"no memory is left of the {limit} byte limit after the headroom and reserved memory",
+			))),
+
+			size => Ok(size),
+		}
+	}
+}
+
+/// Returns the memory available to the server, which is the lowest
+/// `memory.max` of its cgroup v2 and the cgroup's ancestors, or the total
+/// memory of the system if the cgroup is not limited.
+pub fn memory_limit() -> Option<u64> {
+	let total_memory = total_memory();
+
+	match cgroup_memory_max() {
+		Some(max) => Some(total_memory.map_or(max, |total| total.min(max))),
+		None => total_memory,
+	}
+}
+
+/// Requests a reload of the memory limit whenever the process receives a
+/// SIGHUP.
+#[cfg(unix)]
+pub fn handle_reload_signal() {
+	extern "C" fn request_reload(_: libc::c_int) {
+		RELOAD_REQUESTED.store(true, Ordering::Relaxed);
+	}
+
+	let handler = request_reload as extern "C" fn(libc::c_int);
+
+	unsafe {
+		libc::signal(libc::SIGHUP, handler as libc::sighandler_t);
+	}
+}
+
+#[cfg(not(unix))]
+pub fn handle_reload_signal() {}
+
+/// Returns true if a reload was requested since the last call.
+pub fn take_reload_request() -> bool {
+	RELOAD_REQUESTED.swap(false, Ordering::Relaxed)
+}
+
+fn cgroup_memory_max() -> Option<u64> {
+	let cgroups = fs::read_to_string(PROC_CGROUP_PATH).ok()?;
+	cgroup_memory_max_at(Path::new(CGROUP_PATH), &cgroups)
+}
+
+/// Returns the lowest `memory.max` of the cgroup listed in the supplied
+/// `/proc/self/cgroup` and of its ancestors, within the cgroup root.
+fn cgroup_memory_max_at(root: &Path, cgroups: &str) -> Option<u64> {
+	// the cgroup v2 hierarchy has the ID 0 (e.g., 0::/system.slice/paper.service)
+	let cgroup = cgroups
+		.lines()
+		.find_map(|line| line.strip_prefix("0::"))?;
+
+	let mut path = root.join(cgroup.trim().trim_start_matches('/'));
+	let mut limit: Option<u64> = None;
+
+	// within a cgroup namespace, the cgroup may not exist at its path, in
+	// which case the limits of its ancestors are still read
+	while path.starts_with(root) {
+		if let Some(max) = read_memory_max(&path) {
+			limit = Some(limit.map_or(max, |limit| limit.min(max)));
+		}
+
+		if !path.pop() {
+			break;
+		}
+	}
+
+	limit
+}
+
+/// Returns the `memory.max` of a cgroup, or `None` if it is not limited.
+fn read_memory_max(path: &Path) -> Option<u64> {
+	fs::read_to_string(path.join("memory.max"))
+		.ok()?
+		.trim()
+		.parse()
+		.ok()
+}
+
+#[cfg(unix)]
+fn total_memory() -> Option<u64> {
+	let (num_pages, page_size) = unsafe {
+		(libc::sysconf(libc::_SC_PHYS_PAGES), libc::sysconf(libc::_SC_PAGESIZE))
+	};
+
+	match (u64::try_from(num_pages), u64::try_from(page_size)) {
+		(Ok(num_pages), Ok(page_size)) if num_pages > 0 => Some(num_pages * page_size),
+		_ => None,
+	}
+}
+
+#[cfg(not(unix))]
+fn total_memory() -> Option<u64> {
+	None
+}
+
+#[cfg(test)]
+mod tests {
+	use std::path::PathBuf;
+	use super::*;
+
+	/// Writes a fake cgroup hierarchy with the supplied `memory.max` of
+	/// every cgroup, where a cgroup without a limit holds `max`.
+	fn fake_cgroups(name: &str, cgroups: &[(&str, &str)]) -> PathBuf {
+		let root = std::env::temp_dir().join(format!("paper-cgroup-{}-{name}", std::process::id()));
+		let _ = fs::remove_dir_all(&root);
+
+		for (cgroup, memory_max) in cgroups {
+			let path = root.join(cgroup);
+
+			fs::create_dir_all(&path).unwrap();
+			fs::write(path.join("memory.max"), format!("{memory_max}\n")).unwrap();
+		}
+
+		root
+	}
+
+	#[test]
+	fn it_parses_a_memory_size() {
+		assert_eq!(MemorySize::parse("auto"), Some(MemorySize::Auto));
+		assert_eq!(MemorySize::parse("80%"), Some(MemorySize::Percent(80.0)));
+		assert_eq!(MemorySize::parse("1KiB"), Some(MemorySize::Bytes(1024)));
+
+		assert_eq!(MemorySize::parse("0%"), None);
+		assert_eq!(MemorySize::parse("101%"), None);
+		assert_eq!(MemorySize::parse("lots"), None);
+	}
+
+	#[test]
+	fn it_resolves_a_max_size_against_a_limit() {
+		let max_size = RelativeMaxSize::new(MemorySize::Percent(50.0), MemorySize::Percent(20.0), 100);
+		assert_eq!(max_size.resolve_against(1000).unwrap(), 350);
+
+		let max_size = RelativeMaxSize::new(MemorySize::Auto, MemorySize::Bytes(200), 0);
+		assert_eq!(max_size.resolve_against(1000).unwrap(), 800);
+
+		let max_size = RelativeMaxSize::new(MemorySize::Auto, MemorySize::Bytes(600), 400);
+		assert!(max_size.resolve_against(1000).is_err());
+	}
+
+	#[test]
+	fn it_reads_the_lowest_limit_of_a_cgroup_and_its_ancestors() {
+		let root = fake_cgroups("nested", &[
+			("", "max"),
+			("system.slice", "4096"),
+			("system.slice/paper.service", "8192"),
+		]);
+
+		let limit = cgroup_memory_max_at(&root, "0::/system.slice/paper.service\n");
+		assert_eq!(limit, Some(4096));
+
+		fs::remove_dir_all(root).unwrap();
+	}
+
+	#[test]
+	fn it_reads_the_ancestors_of_a_missing_cgroup() {
+		let root = fake_cgroups("missing", &[("", "max"), ("user.slice", "2048")]);
+
+		assert_eq!(cgroup_memory_max_at(&root, "0::/user.slice/missing.scope\n"), Some(2048));
+		assert_eq!(cgroup_memory_max_at(&root, "0::/\n"), None);
+
+		// only the cgroup v2 hierarchy holds memory.max
+		assert_eq!(cgroup_memory_max_at(&root, "1:memory:/user.slice\n"), None);
+
+		fs::remove_dir_all(root).unwrap();
+	}
+}
@@ -0,0 +1,439 @@
+/*
+ * Copyright (c) Kia Shakiba
+ *
+ * This source code is licensed under the GNU AGPLv3 license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::{
+	thread,
+	ops::RangeInclusive,
+	sync::{Arc, Mutex},
+};
+
+use log::{info, warn, error};
+use paper_utils::stream::Buffer;
+use paper_cache::CacheError;
+
+use crate::{
+	error::ServerError,
+	command::Command,
+	store::Store,
+	expiry::Expiry,
+	cluster::{self, Cluster},
+	node_client::{self, NodeClient},
+};
+
+const MIGRATE_BATCH_SIZE: usize = 1000;
+
+/// Moves ranges of slots from this node to another node while both nodes
+/// stay online. The target is first told to import the slots, after which
+/// this node redirects writes to the slots to the target while their
+/// objects are copied to it. Once every object is copied, the ownership
+/// of the slots is flipped on the target, on this node and then on the
+/// rest of the cluster, and this node's copies are removed.
+///
+/// If the migration fails before the target owns the slots, the target
+/// cancels the import and removes its copies, and this node serves the
+/// slots again from the copies it kept. Objects which were written to the
+/// target during the migration are lost.
+pub struct Migrator {
+	store: Arc<Store>,
+	cluster: Arc<Cluster>,
+	auth_token: Option<String>,
+
+	// held while a migration is started so that two migrations cannot claim
+	// the same slots
+	start_lock: Mutex<()>,
+}
+
+impl Migrator {
+	pub fn new(
+		store: Arc<Store>,
+		cluster: Arc<Cluster>,
+		auth_token: Option<String>,
+	) -> Self {
+		Migrator {
+			store,
+			cluster,
+			auth_token,
+
+			start_lock: Mutex::default(),
+		}
+	}
+
+	/// Starts migrating the slots to the target node in the background.
+	pub fn start(
+		self: &Arc<Self>,
+		range: RangeInclusive<u16>,
+		target_id: &str,
+	) -> Result<(), ServerError> {
+		let _guard = self.start_lock
+			.lock()
+			.unwrap_or_else(|err| err.into_inner());
+
+		let (Some(target), Some(node)) = (self.cluster.node_index(target_id), self.cluster.node()) else {
+			return Err(ServerError::UnknownNode(target_id.into()));
+		};
+
+		if node.id() == target_id || !self.cluster.can_migrate(&range) {
+			return Err(ServerError::InvalidSlotRange);
+		}
+
+		let mut client = self.connect(target)?;
+
+		send(&mut client, &Command::ClusterImport(
+			*range.start() as u32,
+			*range.end() as u32,
+			node.id().to_owned(),
+		))?;
+
+		self.cluster.set_migrating(range.clone(), Some(target));
+
+		let migrator = self.clone();
+
+		thread::spawn(move || {
+			info!("Migrating slots {range:?} to {}", migrator.cluster.nodes()[target].id());
+
+			match migrator.migrate(&mut client, &range, target) {
+				Ok(num_migrated) => info!("Migrated {num_migrated} objects in slots {range:?}"),
+
+				Err(err) => {
+					error!("Could not migrate slots {range:?}: {err}");
+
+					if let Err(err) = migrator.cancel(&range, target) {
+						warn!("Could not cancel the import of slots {range:?}: {err}");
+					}
+
+					migrator.cluster.set_migrating(range, None);
+				},
+			}
+		});
+
+		Ok(())
+	}
+
+	/// Copies every object in the slots to the target, flips the ownership
+	/// of the slots and removes this node's copies. Returns the number of
+	/// migrated objects.
+	fn migrate(
+		&self,
+		client: &mut NodeClient,
+		range: &RangeInclusive<u16>,
+		target: usize,
+	) -> Result<u64, ServerError> {
+		let mut num_migrated = 0;
+		let mut cursor = Buffer::default();
+
+		loop {
+			let (keys, next_cursor) = self.store.scan(&cursor, b"", MIGRATE_BATCH_SIZE)?;
+
+			for key in keys.iter().filter(|key| range.contains(&cluster::key_slot(key))) {
+				let is_migrated = self.store.export_key(key, |object, tags| {
+					let expiry = match object.expires_at() {
+						Some(expires_at) => Expiry::UnixMillis(expires_at),
+						None => Expiry::Never,
+					};
+
+					send(client, &Command::Restore(
+						key.clone(),
+						Buffer::from(object.value()),
+						expiry,
+						tags.to_vec(),
+					))
+				})?;
+
+				if is_migrated {
+					num_migrated += 1;
+				}
+			}
+
+			match next_cursor {
+				Some(next_cursor) => cursor = next_cursor,
+				None => break,
+			}
+		}
+
+		let set_slots = Command::ClusterSetSlots(
+			*range.start() as u32,
+			*range.end() as u32,
+			self.cluster.nodes()[target].id().to_owned(),
+		);
+
+		// the target owns the slots before this node stops redirecting to
+		// it, so the slots are always served by one of the two nodes
+		send(client, &set_slots)?;
+		self.cluster.set_owner(range.clone(), target);
+
+		// the slots are redirected to the target from here on, so the copies
+		// are never served
+		remove_slots(&self.store, range)?;
+
+		// the other nodes learn of the new owner on a best-effort basis, and
+		// redirect to the wrong node until they do
+		for (index, node) in self.cluster.nodes().iter().enumerate() {
+			if index == target || self.cluster.is_self(node) {
+				continue;
+			}
+
+			let result = self
+				.connect(index)
+				.and_then(|mut client| send(&mut client, &set_slots));
+
+			if let Err(err) = result {
+				warn!("Could not update slots on {}: {err}", node.id());
+			}
+		}
+
+		Ok(num_migrated)
+	}
+
+	/// Tells the target to stop importing the slots, on a new connection
+	/// since the migration's connection may have failed.
+	fn cancel(&self, range: &RangeInclusive<u16>, target: usize) -> Result<(), ServerError> {
+		let Some(node) = self.cluster.node() else {
+			return Ok(());
+		};
+
+		let mut client = self.connect(target)?;
+
+		send(&mut client, &Command::ClusterCancelImport(
+			*range.start() as u32,
+			*range.end() as u32,
+			node.id().to_owned(),
+		))
+	}
+
+	fn connect(&self, node_index: usize) -> Result<NodeClient, ServerError> {
+		let address = self.cluster.nodes()[node_index].address();
+
+		NodeClient::connect(&address, self.auth_token.as_deref())
+			.map_err(|err| ServerError::MigrationFailed(format!("{address}: {err}")))
+	}
+}
+
+/// Removes every object in the slots and returns the number of removed
+/// objects.
+pub fn remove_slots(store: &Store, range: &RangeInclusive<u16>) -> Result<u64, ServerError> {
+	let mut num_removed = 0;
+	let mut cursor = Buffer::default();
+
+	loop {
+		let (keys, next_cursor) = store.scan(&cursor, b"", MIGRATE_BATCH_SIZE)?;
+
+		for key in keys.iter().filter(|key| range.contains(&cluster::key_slot(key))) {
+			match store.del(key) {
+				Ok(_) => num_removed += 1,
+				Err(ServerError::CacheError(CacheError::KeyNotFound)) => {},
+				Err(err) => return Err(err),
+			}
+		}
+
+		match next_cursor {
+			Some(next_cursor) => cursor = next_cursor,
+			None => break,
+		}
+	}
+
+	Ok(num_removed)
+}
+
+fn send(client: &mut NodeClient, command: &Command) -> Result<(), ServerError> {
+	let fields = client
+		.send(command)
+		.map_err(|err| ServerError::MigrationFailed(err.to_string()))?;
+
+	match node_client::is_ok(&fields) {
+		true => Ok(()),
+		false => Err(ServerError::MigrationFailed("rejected by node".into())),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{
+		io::Write,
+		net::{TcpListener, TcpStream},
+		time::Duration,
+	};
+
+	use paper_cache::PaperPolicy;
+	use paper_utils::sheet::SheetBuilder;
+
+	use crate::{
+		store::Cache,
+		namespace::Namespaces,
+		tier::Tiers,
+		pinned::PinnedObjects,
+		admission::{Admission, AdmissionPolicy},
+		cluster::ClusterNodeConfig,
+	};
+
+	use super::*;
+
+	/// A target node which accepts every command except CLUSTER SETSLOTS if
+	/// `is_failing`, and records the commands it receives.
+	fn spawn_target(is_failing: bool) -> (String, Arc<Mutex<Vec<String>>>) {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let address = listener.local_addr().unwrap().to_string();
+
+		let received = Arc::new(Mutex::new(Vec::new()));
+		let thread_received = received.clone();
+
+		thread::spawn(move || {
+			for stream in listener.incoming() {
+				let received = thread_received.clone();
+				thread::spawn(move || serve(stream.unwrap(), is_failing, received));
+			}
+		});
+
+		(address, received)
+	}
+
+	fn serve(mut stream: TcpStream, is_failing: bool, received: Arc<Mutex<Vec<String>>>) {
+		let ok = || SheetBuilder::new()
+			.write_bool(true)
+			.into_sheet();
+
+		stream.write_all(ok().serialize()).unwrap();
+
+		while let Ok(command) = Command::from_stream(&mut stream) {
+			let (name, is_ok) = match &command {
+				Command::ClusterImport(..) => ("import".into(), true),
+				Command::ClusterSetSlots(..) => ("setslots".into(), !is_failing),
+				Command::ClusterCancelImport(..) => ("cancel".into(), true),
+				Command::Restore(key, ..) => (format!("restore {}", String::from_utf8_lossy(key)), true),
+
+				_ => ("unknown".into(), false),
+			};
+
+			received.lock().unwrap().push(name);
+
+			let response = match (is_ok, command) {
+				// a restore responds with whether the object was set
+				(true, Command::Restore(..)) => SheetBuilder::new()
+					.write_bool(true)
+					.write_bool(true)
+					.into_sheet(),
+
+				(true, _) => ok(),
+				(false, _) => ServerError::InvalidSlotRange.to_sheet(),
+			};
+
+			if stream.write_all(response.serialize()).is_err() {
+				return;
+			}
+		}
+	}
+
+	fn store() -> Arc<Store> {
+		Arc::new(Store::new(
+			Cache::new(1 << 20, &[PaperPolicy::Lru], PaperPolicy::Lru).unwrap(),
+			Namespaces::default(),
+			Tiers::default(),
+			PinnedObjects::new(1 << 20),
+			Admission::new(AdmissionPolicy::None),
+		))
+	}
+
+	fn cluster(target_address: &str) -> Arc<Cluster> {
+		let (host, port) = target_address.rsplit_once(':').unwrap();
+
+		let nodes = [
+			"a:127.0.0.1:1:0-8191".parse::<ClusterNodeConfig>().unwrap(),
+			format!("b:{host}:{port}:8192-16383").parse().unwrap(),
+		];
+
+		Arc::new(Cluster::new(&nodes, Some("a")).unwrap())
+	}
+
+	/// Returns keys whose slots are owned by node `a`.
+	fn keys(count: usize) -> Vec<Buffer> {
+		(0..)
+			.map(|index| Buffer::from(format!("key{index}").as_bytes()))
+			.filter(|key| cluster::key_slot(key) < 8192)
+			.take(count)
+			.collect()
+	}
+
+	fn wait_until(f: impl Fn() -> bool) {
+		for _ in 0..200 {
+			if f() {
+				return;
+			}
+
+			thread::sleep(Duration::from_millis(10));
+		}
+
+		panic!("timed out");
+	}
+
+	#[test]
+	fn it_removes_the_copies_once_the_target_owns_the_slots() {
+		let (address, received) = spawn_target(false);
+
+		let store = store();
+		let cluster = cluster(&address);
+		let migrator = Arc::new(Migrator::new(store.clone(), cluster.clone(), None));
+
+		for key in keys(3) {
+			store.set(key, b"value", Expiry::Never, &[]).unwrap();
+		}
+
+		migrator.start(0..=8191, "b").unwrap();
+
+		wait_until(|| keys(3).iter().all(|key| !store.has(key).unwrap()));
+
+		let received = received.lock().unwrap();
+
+		assert_eq!(received.first().map(String::as_str), Some("import"));
+		assert_eq!(received.iter().filter(|name| name.starts_with("restore")).count(), 3);
+		assert_eq!(received.last().map(String::as_str), Some("setslots"));
+
+		assert!(keys(3).iter().all(|key| cluster.owner(key) == 1));
+	}
+
+	#[test]
+	fn it_cancels_the_import_and_keeps_the_copies_if_the_migration_fails() {
+		let (address, received) = spawn_target(true);
+
+		let store = store();
+		let cluster = cluster(&address);
+		let migrator = Arc::new(Migrator::new(store.clone(), cluster.clone(), None));
+
+		for key in keys(3) {
+			store.set(key, b"value", Expiry::Never, &[]).unwrap();
+		}
+
+		migrator.start(0..=8191, "b").unwrap();
+
+		wait_until(|| cluster.can_migrate(&(0..=8191)));
+
+		assert_eq!(received.lock().unwrap().last().map(String::as_str), Some("cancel"));
+
+		for key in keys(3) {
+			assert_eq!(store.get(&key, |object| object.value().to_vec()).unwrap(), b"value");
+			assert_eq!(cluster.owner(&key), 0);
+		}
+	}
+
+	#[test]
+	fn it_removes_only_the_objects_in_the_slots() {
+		let store = store();
+
+		let key_a = keys(1).remove(0);
+
+		let key_b = (0..)
+			.map(|index| Buffer::from(format!("key{index}").as_bytes()))
+			.find(|key| cluster::key_slot(key) >= 8192)
+			.unwrap();
+
+		store.set(key_a.clone(), b"a", Expiry::Never, &[]).unwrap();
+		store.set(key_b.clone(), b"b", Expiry::Never, &[]).unwrap();
+
+		assert_eq!(remove_slots(&store, &(0..=8191)).unwrap(), 1);
+
+		assert!(!store.has(&key_a).unwrap());
+		assert!(store.has(&key_b).unwrap());
+	}
+}
@@ -0,0 +1,293 @@
+/*
+ * Copyright (c) Kia Shakiba
+ *
+ * This source code is licensed under the GNU AGPLv3 license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::sync::{
+	RwLock,
+	RwLockReadGuard,
+	atomic::{AtomicU64, Ordering},
+};
+
+use paper_cache::PaperPolicy;
+use paper_utils::stream::Buffer;
+
+use crate::{
+	error::ServerError,
+	store::Cache,
+	trace::{self, TraceOp},
+};
+
+const SAMPLE_MODULUS: u64 = 1 << 24;
+
+// the sizes at which the miss ratio is estimated, as fractions of the
+// cache's current size
+const SIZE_FRACTIONS: &[f64] = &[0.25, 0.5, 0.75, 1.0, 1.25, 1.5, 2.0];
+
+/// Estimates the miss-ratio curve of every configured policy using
+/// SHARDS-style spatially sampled shadow caches. Only the keys whose hash
+/// falls under the sample threshold are replayed against the shadow caches,
+/// and each shadow cache is scaled down by the sample rate, so the memory
+/// held by the shadow caches is roughly the sample rate multiplied by the
+/// sum of the estimated sizes.
+pub struct ShadowCaches {
+	sample_rate: f64,
+	sample_threshold: u64,
+
+	policies: Vec<PaperPolicy>,
+	caches: RwLock<Vec<ShadowCache>>,
+}
+
+struct ShadowCache {
+	policy: PaperPolicy,
+	size: u64,
+	cache: Cache,
+
+	gets: AtomicU64,
+	misses: AtomicU64,
+}
+
+/// A single point of a miss-ratio curve, with the size in the scale of the
+/// real cache.
+pub struct MrcPoint {
+	pub policy: PaperPolicy,
+	pub size: u64,
+
+	pub gets: u64,
+	pub misses: u64,
+}
+
+impl ShadowCaches {
+	pub fn new(
+		sample_rate: f64,
+		policies: &[PaperPolicy],
+		max_size: u64,
+	) -> Result<Self, ServerError> {
+		let shadow_caches = ShadowCaches {
+			sample_rate,
+			sample_threshold: (sample_rate * SAMPLE_MODULUS as f64) as u64,
+
+			policies: policies.to_vec(),
+			caches: RwLock::new(Vec::new()),
+		};
+
+		shadow_caches.resize(max_size)?;
+
+		Ok(shadow_caches)
+	}
+
+	pub fn sample_rate(&self) -> f64 {
+		self.sample_rate
+	}
+
+	/// Returns the hash of the key if the key is sampled by the shadow
+	/// caches.
+	pub fn sample(&self, key: &[u8]) -> Option<u64> {
+		let key_hash = trace::hash_key(key);
+
+		let is_sampled = key_hash % SAMPLE_MODULUS < self.sample_threshold;
+		is_sampled.then_some(key_hash)
+	}
+
+	/// Replays an access to a sampled key against every shadow cache. The
+	/// value size of a read is the size of the object in the real cache, or
+	/// zero if the real cache missed.
+	pub fn record(&self, key_hash: u64, value_size: u32, op: TraceOp) {
+		let key = Buffer::from(key_hash.to_le_bytes().to_vec());
+
+		for shadow in self.read_caches().iter() {
+			match op {
+				TraceOp::Get => {
+					shadow.gets.fetch_add(1, Ordering::Relaxed);
+
+					if shadow.cache.get(&key).is_err() {
+						shadow.misses.fetch_add(1, Ordering::Relaxed);
+
+						// an object missed by the shadow cache but held by
+						// the real cache would have been set by the client
+						// after the miss
+						shadow.set(key.clone(), value_size);
+					}
+				},
+
+				TraceOp::Set => shadow.set(key.clone(), value_size),
+				TraceOp::Del => { let _ = shadow.cache.del(&key); },
+
+				// neither command affects the eviction policy
+				TraceOp::Has | TraceOp::Peek => {},
+			}
+		}
+	}
+
+	/// Rebuilds the shadow caches around the new size of the real cache.
+	/// The rebuilt shadow caches start cold.
+	pub fn resize(&self, max_size: u64) -> Result<(), ServerError> {
+		let mut caches = Vec::new();
+
+		for policy in &self.policies {
+			for fraction in SIZE_FRACTIONS {
+				let size = (max_size as f64 * fraction) as u64;
+				let shadow_size = ((size as f64 * self.sample_rate) as u64).max(1);
+
+				caches.push(ShadowCache {
+					policy: *policy,
+					size,
+					cache: Cache::new(shadow_size, &[*policy], *policy)?,
+
+					gets: AtomicU64::default(),
+					misses: AtomicU64::default(),
+				});
+			}
+		}
+
+		*self.caches
+			.write()
+			.unwrap_or_else(|err| err.into_inner()) = caches;
+
+		Ok(())
+	}
+
+	pub fn wipe(&self) {
+		for shadow in self.read_caches().iter() {
+			let _ = shadow.cache.wipe();
+		}
+	}
+
+	pub fn curves(&self) -> Vec<MrcPoint> {
+		self.read_caches()
+			.iter()
+			.map(|shadow| MrcPoint {
+				policy: shadow.policy,
+				size: shadow.size,
+
+				gets: shadow.gets.load(Ordering::Relaxed),
+				misses: shadow.misses.load(Ordering::Relaxed),
+			})
+			.collect()
+	}
+
+	fn read_caches(&self) -> RwLockReadGuard<'_, Vec<ShadowCache>> {
+		self.caches
+			.read()
+			.unwrap_or_else(|err| err.into_inner())
+	}
+}
+
+impl ShadowCache {
+	fn set(&self, key: Buffer, value_size: u32) {
+		if value_size == 0 {
+			return;
+		}
+
+		let value = Buffer::from(vec![0; value_size as usize]);
+
+		// objects larger than the shadow cache are rejected, which is
+		// counted the same as the object being evicted
+		let _ = self.cache.set(key, value, None);
+	}
+}
+
+impl MrcPoint {
+	pub fn miss_ratio(&self) -> f64 {
+		match self.gets {
+			0 => 0.0,
+			gets => self.misses as f64 / gets as f64,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn curve(shadow_caches: &ShadowCaches) -> Vec<(u64, u64, u64)> {
+		shadow_caches
+			.curves()
+			.iter()
+			.map(|point| (point.size, point.gets, point.misses))
+			.collect()
+	}
+
+	#[test]
+	fn it_samples_the_configured_fraction_of_keys() {
+		let all = ShadowCaches::new(1.0, &[PaperPolicy::Lru], 1000).unwrap();
+		let some = ShadowCaches::new(0.1, &[PaperPolicy::Lru], 1000).unwrap();
+
+		let keys = (0..10_000u32)
+			.map(|key| key.to_le_bytes())
+			.collect::<Vec<_>>();
+
+		assert!(keys.iter().all(|key| all.sample(key).is_some()));
+
+		let num_sampled = keys
+			.iter()
+			.filter(|key| some.sample(*key).is_some())
+			.count();
+
+		assert!((500..1500).contains(&num_sampled));
+	}
+
+	#[test]
+	fn it_estimates_a_point_for_every_policy_and_size() {
+		let shadow_caches = ShadowCaches::new(1.0, &[PaperPolicy::Lru, PaperPolicy::Lfu], 1000).unwrap();
+		let curves = shadow_caches.curves();
+
+		assert_eq!(curves.len(), 2 * SIZE_FRACTIONS.len());
+
+		let sizes = curves[..SIZE_FRACTIONS.len()]
+			.iter()
+			.map(|point| point.size)
+			.collect::<Vec<_>>();
+
+		assert_eq!(sizes, [250, 500, 750, 1000, 1250, 1500, 2000]);
+		assert!(curves[SIZE_FRACTIONS.len()..].iter().all(|point| point.policy == PaperPolicy::Lfu));
+	}
+
+	#[test]
+	fn it_misses_less_in_larger_caches() {
+		let shadow_caches = ShadowCaches::new(1.0, &[PaperPolicy::Lru], 40).unwrap();
+
+		// a loop over four objects of 10 bytes always misses in the smallest
+		// cache, which holds one object, and only misses on the first pass
+		// in the largest cache, which holds all of them
+		for _ in 0..5 {
+			for key_hash in 0..4 {
+				shadow_caches.record(key_hash, 10, TraceOp::Get);
+			}
+		}
+
+		let curve = curve(&shadow_caches);
+
+		assert_eq!(curve.first(), Some(&(10, 20, 20)));
+		assert_eq!(curve.last(), Some(&(80, 20, 4)));
+
+		assert!(curve.windows(2).all(|points| points[0].2 >= points[1].2));
+		assert_eq!(shadow_caches.curves().last().unwrap().miss_ratio(), 0.2);
+	}
+
+	#[test]
+	fn it_does_not_count_other_operations() {
+		let shadow_caches = ShadowCaches::new(1.0, &[PaperPolicy::Lru], 100).unwrap();
+
+		shadow_caches.record(1, 10, TraceOp::Set);
+		shadow_caches.record(1, 10, TraceOp::Has);
+		shadow_caches.record(1, 10, TraceOp::Get);
+
+		shadow_caches.record(1, 10, TraceOp::Del);
+		shadow_caches.record(1, 10, TraceOp::Get);
+
+		// a read which missed the real cache is not set in the shadow cache
+		shadow_caches.record(2, 0, TraceOp::Get);
+		shadow_caches.record(2, 0, TraceOp::Get);
+
+		assert!(shadow_caches.curves().iter().all(|point| point.gets == 4 && point.misses == 3));
+
+		shadow_caches.resize(200).unwrap();
+
+		assert!(shadow_caches.curves().iter().all(|point| point.gets == 0 && point.misses == 0));
+		assert_eq!(shadow_caches.curves()[3].size, 200);
+		assert_eq!(shadow_caches.curves()[3].miss_ratio(), 0.0);
+	}
+}
@@ -0,0 +1,305 @@
+/*
+ * Copyright (c) Kia Shakiba
+ *
+ * This source code is licensed under the GNU AGPLv3 license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::{
+	fmt,
+	str::FromStr,
+};
+
+use parse_size::parse_size;
+use paper_cache::{PaperPolicy, CacheError};
+
+use crate::{
+	error::ServerError,
+	object,
+	store::Cache,
+};
+
+/// A namespace as configured with
+/// `namespaces[]=<name>:<size>,prefix=<prefix>,policy=<policy>`. Every key
+/// which starts with the prefix is held by the namespace's own cache, which
+/// has its own size and eviction policy.
+#[derive(Debug, Clone)]
+pub struct NamespaceConfig {
+	name: String,
+	prefix: String,
+
+	size: u64,
+	policy: PaperPolicy,
+}
+
+/// The caches of the configured namespaces. A key which matches the
+/// prefixes of several namespaces belongs to the namespace with the longest
+/// prefix.
+#[derive(Default)]
+pub struct Namespaces {
+	namespaces: Vec<Namespace>,
+}
+
+struct Namespace {
+	name: String,
+	prefix: Vec<u8>,
+	cache: Cache,
+}
+
+pub struct NamespaceStatus {
+	pub name: String,
+
+	pub used_size: u64,
+	pub max_size: u64,
+	pub num_objects: u64,
+
+	pub miss_ratio: f64,
+	pub policy: PaperPolicy,
+}
+
+impl NamespaceConfig {
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+
+	pub fn prefix(&self) -> &str {
+		&self.prefix
+	}
+
+	pub fn size(&self) -> u64 {
+		self.size
+	}
+
+	pub fn policy(&self) -> PaperPolicy {
+		self.policy
+	}
+}
+
+impl FromStr for NamespaceConfig {
+	type Err = ServerError;
+
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		let invalid = || ServerError::InvalidConfigNamespace(value.into());
+
+		let (name, params) = value.split_once(':').ok_or_else(invalid)?;
+		let mut params = params.split(',');
+
+		let name = name.trim();
+
+		if name.is_empty() {
+			return Err(invalid());
+		}
+
+		let size = match params.next().map(|size| parse_size(size.trim())) {
+			Some(Ok(size)) if size > 0 => size,
+			_ => return Err(invalid()),
+		};
+
+		let mut prefix = None;
+		let mut policy = None;
+
+		for param in params {
+			let (key, param_value) = param.split_once('=').ok_or_else(invalid)?;
+
+			match (key.trim(), param_value.trim()) {
+				("prefix", value) if !value.is_empty() => prefix = Some(value.to_owned()),
+
+				("policy", value) => match PaperPolicy::from_str(value) {
+					Ok(value) if !value.is_auto() => policy = Some(value),
+					_ => return Err(invalid()),
+				},
+
+				_ => return Err(invalid()),
+			}
+		}
+
+		let (Some(prefix), Some(policy)) = (prefix, policy) else {
+			return Err(invalid());
+		};
+
+		Ok(NamespaceConfig {
+			name: name.to_owned(),
+			prefix,
+
+			size,
+			policy,
+		})
+	}
+}
+
+impl fmt::Display for NamespaceConfig {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}:{},prefix={},policy={}", self.name, self.size, self.prefix, self.policy)
+	}
+}
+
+impl Namespaces {
+	pub fn new(configs: &[NamespaceConfig]) -> Result<Self, CacheError> {
+		let mut namespaces = Vec::with_capacity(configs.len());
+
+		for config in configs {
+			namespaces.push(Namespace {
+				name: config.name().to_owned(),
+				prefix: config.prefix().as_bytes().to_vec(),
+				cache: Cache::new(config.size(), &[config.policy()], config.policy())?,
+			});
+		}
+
+		// the longest prefix is matched first
+		namespaces.sort_by_key(|namespace| std::cmp::Reverse(namespace.prefix.len()));
+
+		Ok(Namespaces {
+			namespaces,
+		})
+	}
+
+	/// Returns the cache of the namespace which the key belongs to, if any.
+	pub fn find(&self, key: &[u8]) -> Option<&Cache> {
+		self.namespaces
+			.iter()
+			.find(|namespace| key.starts_with(&namespace.prefix))
+			.map(|namespace| &namespace.cache)
+	}
+
+	pub fn wipe(&self) -> Result<(), CacheError> {
+		for namespace in &self.namespaces {
+			namespace.cache.wipe()?;
+		}
+
+		Ok(())
+	}
+
+	/// Returns the status of every namespace, in the order of their names.
+	pub fn status(&self) -> Result<Vec<NamespaceStatus>, CacheError> {
+		let mut statuses = self.namespaces
+			.iter()
+			.map(|namespace| {
+				let status = namespace.cache.status()?;
+
+				// the object headers are server-side metadata, so they are not
+				// reported as part of the used size
+				let used_size = status
+					.used_size()
+					.saturating_sub(status.num_objects() * object::HEADER_SIZE as u64);
+
+				Ok(NamespaceStatus {
+					name: namespace.name.clone(),
+
+					used_size,
+					max_size: status.max_size(),
+					num_objects: status.num_objects(),
+
+					miss_ratio: status.miss_ratio(),
+					policy: status.policy(),
+				})
+			})
+			.collect::<Result<Vec<_>, CacheError>>()?;
+
+		statuses.sort_by(|a, b| a.name.cmp(&b.name));
+
+		Ok(statuses)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use paper_utils::stream::Buffer;
+	use super::*;
+
+	fn namespaces(configs: &[&str]) -> Namespaces {
+		let configs = configs
+			.iter()
+			.map(|config| config.parse::<NamespaceConfig>().unwrap())
+			.collect::<Vec<_>>();
+
+		Namespaces::new(&configs).unwrap()
+	}
+
+	fn buffer(value: &str) -> Buffer {
+		Buffer::from(value.as_bytes())
+	}
+
+	#[test]
+	fn it_parses_a_namespace() {
+		let config = "sessions:1KiB, prefix=session:, policy=lfu"
+			.parse::<NamespaceConfig>()
+			.unwrap();
+
+		assert_eq!(config.name(), "sessions");
+		assert_eq!(config.prefix(), "session:");
+		assert_eq!(config.size(), 1024);
+		assert_eq!(config.policy(), PaperPolicy::Lfu);
+
+		assert_eq!(config.to_string(), "sessions:1024,prefix=session:,policy=lfu");
+	}
+
+	#[test]
+	fn it_rejects_an_invalid_namespace() {
+		let invalid = [
+			"sessions",
+			":1KiB,prefix=s:,policy=lru",
+			"sessions:0,prefix=s:,policy=lru",
+			"sessions:1KiB,policy=lru",
+			"sessions:1KiB,prefix=s:",
+			"sessions:1KiB,prefix=,policy=lru",
+			"sessions:1KiB,prefix=s:,policy=auto",
+			"sessions:1KiB,prefix=s:,policy=lru,size=1",
+		];
+
+		for config in invalid {
+			assert!(config.parse::<NamespaceConfig>().is_err(), "{config}");
+		}
+	}
+
+	#[test]
+	fn it_finds_the_namespace_with_the_longest_prefix() {
+		let namespaces = namespaces(&[
+			"users:1KiB,prefix=user:,policy=lru",
+			"admins:1KiB,prefix=user:admin:,policy=lru",
+		]);
+
+		namespaces.find(b"user:1").unwrap().set(buffer("user:1"), buffer("a"), None).unwrap();
+		namespaces.find(b"user:admin:1").unwrap().set(buffer("user:admin:1"), buffer("b"), None).unwrap();
+
+		assert!(namespaces.find(b"user:1").unwrap().has(&buffer("user:1")));
+		assert!(!namespaces.find(b"user:1").unwrap().has(&buffer("user:admin:1")));
+		assert!(namespaces.find(b"user:admin:1").unwrap().has(&buffer("user:admin:1")));
+
+		assert!(namespaces.find(b"page:1").is_none());
+		assert!(namespaces.find(b"use").is_none());
+	}
+
+	#[test]
+	fn it_reports_the_status_of_every_namespace() {
+		let namespaces = namespaces(&[
+			"users:1KiB,prefix=user:,policy=lru",
+			"admins:2KiB,prefix=user:admin:,policy=lfu",
+		]);
+
+		let statuses = namespaces.status().unwrap();
+
+		let names = statuses
+			.iter()
+			.map(|status| (status.name.as_str(), status.policy))
+			.collect::<Vec<_>>();
+
+		assert_eq!(names, [("admins", PaperPolicy::Lfu), ("users", PaperPolicy::Lru)]);
+	}
+
+	#[test]
+	fn it_wipes_every_namespace() {
+		let namespaces = namespaces(&[
+			"users:1KiB,prefix=user:,policy=lru",
+			"pages:1KiB,prefix=page:,policy=lru",
+		]);
+
+		for key in ["user:1", "page:1"] {
+			namespaces.find(key.as_bytes()).unwrap().set(buffer(key), buffer("a"), None).unwrap();
+		}
+
+		namespaces.wipe().unwrap();
+
+		assert!(!namespaces.find(b"user:1").unwrap().has(&buffer("user:1")));
+		assert!(!namespaces.find(b"page:1").unwrap().has(&buffer("page:1")));
+	}
+}
@@ -0,0 +1,473 @@
+/*
+ * Copyright (c) Kia Shakiba
+ *
+ * This source code is licensed under the GNU AGPLv3 license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::{
+	io::Write,
+	time::Duration,
+	net::{TcpStream, ToSocketAddrs},
+};
+
+use paper_utils::{
+	stream::{Buffer, StreamReader, StreamError},
+	sheet::{Sheet, SheetBuilder},
+};
+
+use crate::{
+	command::Command,
+	error::ErrorCode,
+};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A connection to a paper-server, which is used by the proxy to forward
+/// commands, by a node to migrate slots and by paper-cli and paper-bench.
+pub struct NodeClient {
+	stream: TcpStream,
+}
+
+/// A single field of a response. The fields of a response are read as they
+/// were written, so that they can be written back to a client unchanged.
+pub enum Field {
+	Bool(bool),
+	U8(u8),
+	U32(u32),
+	U64(u64),
+	F64(f64),
+	Buf(Buffer),
+}
+
+impl NodeClient {
+	/// Opens a connection to the server at `address`, completing the
+	/// handshake and authorizing the connection if a token is supplied.
+	pub fn connect(address: &str, auth_token: Option<&str>) -> Result<Self, StreamError> {
+		let addr = address
+			.to_socket_addrs()
+			.ok()
+			.and_then(|mut addrs| addrs.next())
+			.ok_or(StreamError::InvalidStream)?;
+
+		let mut stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)
+			.map_err(|_| StreamError::InvalidStream)?;
+
+		stream
+			.set_read_timeout(Some(READ_TIMEOUT))
+			.map_err(|_| StreamError::InvalidStream)?;
+
+		// commands are small and often pipelined, so they are sent without
+		// waiting to be coalesced
+		stream
+			.set_nodelay(true)
+			.map_err(|_| StreamError::InvalidStream)?;
+
+		if StreamReader::new(&mut stream).read_u8()? == 0 {
+			return Err(StreamError::InvalidData);
+		}
+
+		let mut client = NodeClient {
+			stream,
+		};
+
+		if let Some(token) = auth_token {
+			let fields = client.send(&Command::Auth(Buffer::from(token.as_bytes())))?;
+
+			if !is_ok(&fields) {
+				return Err(StreamError::InvalidData);
+			}
+		}
+
+		Ok(client)
+	}
+
+	/// Sends the command and returns the fields of its response.
+	pub fn send(&mut self, command: &Command) -> Result<Vec<Field>, StreamError> {
+		self.write(command)?;
+		self.read(command)
+	}
+
+	/// Sends the command without waiting for its response, so that several
+	/// commands can be pipelined. The responses must be read in the order in
+	/// which the commands were sent.
+	pub fn write(&mut self, command: &Command) -> Result<(), StreamError> {
+		self.stream
+			.write_all(command.to_sheet().serialize())
+			.map_err(|_| StreamError::ClosedStream)
+	}
+
+	/// Reads the fields of the response to the command.
+	pub fn read(&mut self, command: &Command) -> Result<Vec<Field>, StreamError> {
+		read_response(&mut self.stream, command)
+	}
+}
+
+/// Reads the response to a command, which is framed according to the
+/// command.
+fn read_response(stream: &mut TcpStream, command: &Command) -> Result<Vec<Field>, StreamError> {
+	let mut reader = StreamReader::new(stream);
+	let mut fields = Vec::new();
+
+	if !read_bool(&mut reader, &mut fields)? {
+		read_error(&mut reader, &mut fields)?;
+		return Ok(fields);
+	}
+
+	match command {
+		Command::Ping | Command::Get(_) | Command::Peek(_) | Command::Version => {
+			read_buf(&mut reader, &mut fields)?;
+		},
+
+		Command::Gets(_) => {
+			read_buf(&mut reader, &mut fields)?;
+			read_u64(&mut reader, &mut fields)?;
+		},
+
+		Command::MGet(_) => {
+			for _ in 0..read_u32(&mut reader, &mut fields)? {
+				if read_bool(&mut reader, &mut fields)? {
+					read_buf(&mut reader, &mut fields)?;
+				}
+			}
+		},
+
+		Command::SetNx(..) | Command::SetXx(..) | Command::Has(_) | Command::Restore(..) => {
+			read_bool(&mut reader, &mut fields)?;
+		},
+
+		Command::GetSet(..) => {
+			if read_bool(&mut reader, &mut fields)? {
+				read_buf(&mut reader, &mut fields)?;
+			}
+		},
+
+		Command::Pttl(_) => {
+			if read_bool(&mut reader, &mut fields)? {
+				read_u64(&mut reader, &mut fields)?;
+			}
+		},
+
+		Command::Size(_) => {
+			read_u32(&mut reader, &mut fields)?;
+		},
+
+		Command::DelPrefix(_) | Command::Invalidate(_) => {
+			read_u64(&mut reader, &mut fields)?;
+		},
+
+		Command::Scan(..) => {
+			read_bool(&mut reader, &mut fields)?;
+			read_buf(&mut reader, &mut fields)?;
+
+			for _ in 0..read_u32(&mut reader, &mut fields)? {
+				read_buf(&mut reader, &mut fields)?;
+			}
+		},
+
+		Command::PolicyHistory => {
+			for _ in 0..read_u32(&mut reader, &mut fields)? {
+				read_u64(&mut reader, &mut fields)?;
+				read_buf(&mut reader, &mut fields)?;
+				read_buf(&mut reader, &mut fields)?;
+				read_f64(&mut reader, &mut fields)?;
+				read_bool(&mut reader, &mut fields)?;
+				read_buf(&mut reader, &mut fields)?;
+			}
+		},
+
+		Command::Mrc => {
+			read_f64(&mut reader, &mut fields)?;
+
+			for _ in 0..read_u32(&mut reader, &mut fields)? {
+				read_buf(&mut reader, &mut fields)?;
+				read_u64(&mut reader, &mut fields)?;
+				read_u64(&mut reader, &mut fields)?;
+				read_u64(&mut reader, &mut fields)?;
+				read_f64(&mut reader, &mut fields)?;
+			}
+		},
+
+		Command::Status => {
+			read_u32(&mut reader, &mut fields)?;
+
+			for _ in 0..8 {
+				read_u64(&mut reader, &mut fields)?;
+			}
+
+			read_f64(&mut reader, &mut fields)?;
+
+			for _ in 0..read_u32(&mut reader, &mut fields)? {
+				read_buf(&mut reader, &mut fields)?;
+			}
+
+			read_buf(&mut reader, &mut fields)?;
+			read_bool(&mut reader, &mut fields)?;
+			read_u64(&mut reader, &mut fields)?;
+
+			for _ in 0..read_u32(&mut reader, &mut fields)? {
+				read_buf(&mut reader, &mut fields)?;
+
+				for _ in 0..7 {
+					read_u64(&mut reader, &mut fields)?;
+				}
+			}
+
+			if read_bool(&mut reader, &mut fields)? {
+				read_u64(&mut reader, &mut fields)?;
+				read_u64(&mut reader, &mut fields)?;
+			}
+
+			read_u64(&mut reader, &mut fields)?;
+			read_u64(&mut reader, &mut fields)?;
+
+			for _ in 0..read_u32(&mut reader, &mut fields)? {
+				read_buf(&mut reader, &mut fields)?;
+
+				for _ in 0..3 {
+					read_u64(&mut reader, &mut fields)?;
+				}
+
+				read_f64(&mut reader, &mut fields)?;
+				read_buf(&mut reader, &mut fields)?;
+			}
+		},
+
+		Command::ClusterNodes => {
+			for _ in 0..read_u32(&mut reader, &mut fields)? {
+				read_buf(&mut reader, &mut fields)?;
+				read_buf(&mut reader, &mut fields)?;
+				read_u32(&mut reader, &mut fields)?;
+				read_bool(&mut reader, &mut fields)?;
+
+				for _ in 0..read_u32(&mut reader, &mut fields)? {
+					read_u32(&mut reader, &mut fields)?;
+					read_u32(&mut reader, &mut fields)?;
+				}
+			}
+		},
+
+		Command::Auth(_)
+			| Command::Set(..)
+			| Command::Del(_)
+			| Command::Cas(..)
+			| Command::Ttl(..)
+			| Command::Pin(_)
+			| Command::Unpin(_)
+			| Command::Wipe
+			| Command::Resize(_)
+			| Command::Policy(_)
+			| Command::TraceStart(..)
+			| Command::TraceStop
+			| Command::ClusterMigrate(..)
+			| Command::ClusterImport(..)
+			| Command::ClusterCancelImport(..)
+			| Command::ClusterSetSlots(..) => {},
+	}
+
+	Ok(fields)
+}
+
+/// Reads the error of a failed response. Every error has a code, and cache
+/// errors and redirects are followed by further fields.
+fn read_error(reader: &mut StreamReader, fields: &mut Vec<Field>) -> Result<(), StreamError> {
+	match read_u8(reader, fields)? {
+		ErrorCode::CACHE => { read_u8(reader, fields)?; },
+
+		ErrorCode::MOVED => {
+			read_u32(reader, fields)?;
+			read_buf(reader, fields)?;
+		},
+
+		_ => {},
+	}
+
+	Ok(())
+}
+
+fn read_bool(reader: &mut StreamReader, fields: &mut Vec<Field>) -> Result<bool, StreamError> {
+	let value = reader.read_u8()? != 0;
+	fields.push(Field::Bool(value));
+
+	Ok(value)
+}
+
+fn read_u8(reader: &mut StreamReader, fields: &mut Vec<Field>) -> Result<u8, StreamError> {
+	let value = reader.read_u8()?;
+	fields.push(Field::U8(value));
+
+	Ok(value)
+}
+
+fn read_u32(reader: &mut StreamReader, fields: &mut Vec<Field>) -> Result<u32, StreamError> {
+	let value = reader.read_u32()?;
+	fields.push(Field::U32(value));
+
+	Ok(value)
+}
+
+fn read_u64(reader: &mut StreamReader, fields: &mut Vec<Field>) -> Result<u64, StreamError> {
+	let value = reader.read_u64()?;
+	fields.push(Field::U64(value));
+
+	Ok(value)
+}
+
+fn read_f64(reader: &mut StreamReader, fields: &mut Vec<Field>) -> Result<f64, StreamError> {
+	let value = reader.read_f64()?;
+	fields.push(Field::F64(value));
+
+	Ok(value)
+}
+
+fn read_buf(reader: &mut StreamReader, fields: &mut Vec<Field>) -> Result<(), StreamError> {
+	fields.push(Field::Buf(reader.read_buf()?));
+	Ok(())
+}
+
+pub fn is_ok(fields: &[Field]) -> bool {
+	matches!(fields.first(), Some(Field::Bool(true)))
+}
+
+pub fn into_sheet(fields: Vec<Field>) -> Sheet {
+	fields
+		.into_iter()
+		.fold(SheetBuilder::new(), |builder, field| match field {
+			Field::Bool(value) => builder.write_bool(value),
+			Field::U8(value) => builder.write_u8(value),
+			Field::U32(value) => builder.write_u32(value),
+			Field::U64(value) => builder.write_u64(value),
+			Field::F64(value) => builder.write_f64(value),
+			Field::Buf(value) => builder.write_buf(&value),
+		})
+		.into_sheet()
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{io::Write, net::TcpListener, thread};
+	use super::*;
+
+	/// Sends the response in two parts, split at `split_at`, with a delay in
+	/// between, and reads it back as the response to `command`.
+	fn read_split(command: &Command, response: Sheet, split_at: usize) -> Vec<Field> {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+
+		let bytes = response.serialize().to_vec();
+
+		let writer = thread::spawn(move || {
+			let (mut stream, _) = listener.accept().unwrap();
+
+			stream.write_all(&bytes[..split_at]).unwrap();
+			thread::sleep(Duration::from_millis(100));
+			stream.write_all(&bytes[split_at..]).unwrap();
+		});
+
+		let mut stream = TcpStream::connect(addr).unwrap();
+		let fields = read_response(&mut stream, command).unwrap();
+
+		writer.join().unwrap();
+		fields
+	}
+
+	fn status_sheet(num_tiers: u32, admission: Option<(u64, u64)>, num_namespaces: u32) -> Sheet {
+		let mut builder = SheetBuilder::new()
+			.write_bool(true)
+			.write_u32(1);
+
+		for _ in 0..8 {
+			builder = builder.write_u64(0);
+		}
+
+		builder = builder
+			.write_f64(0.0)
+			.write_u32(1)
+			.write_str("lru".into())
+			.write_str("lru".into())
+			.write_bool(false)
+			.write_u64(0)
+			.write_u32(num_tiers);
+
+		for _ in 0..num_tiers {
+			builder = builder.write_str("far".into());
+
+			for _ in 0..7 {
+				builder = builder.write_u64(0);
+			}
+		}
+
+		builder = builder.write_bool(admission.is_some());
+
+		if let Some((admitted, rejected)) = admission {
+			builder = builder
+				.write_u64(admitted)
+				.write_u64(rejected);
+		}
+
+		builder = builder
+			.write_u64(5)
+			.write_u64(6)
+			.write_u32(num_namespaces);
+
+		for _ in 0..num_namespaces {
+			builder = builder
+				.write_str("sessions".into())
+				.write_u64(0)
+				.write_u64(0)
+				.write_u64(0)
+				.write_f64(0.0)
+				.write_str("lru".into());
+		}
+
+		builder.into_sheet()
+	}
+
+	#[test]
+	fn it_reads_a_status_with_every_section() {
+		let sheet = status_sheet(2, Some((3, 4)), 2);
+		let size = sheet.serialize().len();
+
+		let fields = read_split(&Command::Status, sheet, size - 20);
+
+		// the namespace count is followed by six fields for each namespace
+		let (fields, namespace_fields) = fields.split_at(fields.len() - 13);
+
+		assert!(matches!(
+			fields,
+			[.., Field::Bool(true), Field::U64(3), Field::U64(4), Field::U64(5), Field::U64(6)]
+		));
+
+		assert!(matches!(
+			namespace_fields,
+			[Field::U32(2), .., Field::F64(_), Field::Buf(policy)] if &policy[..] == b"lru"
+		));
+	}
+
+	#[test]
+	fn it_reads_a_status_without_optional_sections() {
+		let sheet = status_sheet(0, None, 0);
+		let size = sheet.serialize().len();
+
+		let fields = read_split(&Command::Status, sheet, size - 1);
+
+		assert!(matches!(
+			fields.as_slice(),
+			[.., Field::U32(0), Field::Bool(false), Field::U64(5), Field::U64(6), Field::U32(0)]
+		));
+	}
+
+	#[test]
+	fn it_reads_a_cache_error() {
+		let sheet = SheetBuilder::new()
+			.write_bool(false)
+			.write_u8(0)
+			.write_u8(1)
+			.into_sheet();
+
+		let fields = read_split(&Command::Status, sheet, 2);
+
+		assert!(matches!(fields.as_slice(), [Field::Bool(false), Field::U8(0), Field::U8(1)]));
+	}
+}
@@ -0,0 +1,482 @@
+/*
+ * Copyright (c) Kia Shakiba
+ *
+ * This source code is licensed under the GNU AGPLv3 license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::{
+	io,
+	fs,
+	fmt,
+	str::FromStr,
+	path::Path,
+	collections::BTreeMap,
+};
+
+use crate::error::ServerError;
+
+const SYSFS_NODE_PATH: &str = "/sys/devices/system/node";
+
+/// The number of CPUs a thread can be pinned to, which bounds every CPU ID
+/// in a CPU list.
+#[cfg(target_os = "linux")]
+pub const MAX_CPUS: usize = libc::CPU_SETSIZE as usize;
+
+#[cfg(not(target_os = "linux"))]
+pub const MAX_CPUS: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MemoryPolicy {
+	Bind,
+	Preferred,
+	Interleave,
+}
+
+/// The NUMA nodes of the machine and the CPUs of each node, as read from a
+/// sysfs node directory. Reading from a directory other than the real sysfs
+/// allows the placement logic to be exercised with a fake topology.
+pub struct Topology {
+	nodes: BTreeMap<u32, Vec<usize>>,
+}
+
+impl Topology {
+	pub fn detect() -> io::Result<Self> {
+		Topology::from_sysfs(SYSFS_NODE_PATH)
+	}
+
+	pub fn from_sysfs<P>(path: P) -> io::Result<Self>
+	where
+		P: AsRef<Path>,
+	{
+		let mut nodes = BTreeMap::new();
+
+		for entry in fs::read_dir(path)? {
+			let entry = entry?;
+			let file_name = entry.file_name();
+
+			let Some(node) = file_name
+				.to_str()
+				.and_then(|name| name.strip_prefix("node"))
+				.and_then(|node| node.parse::<u32>().ok()) else {
+				continue;
+			};
+
+			let cpu_list = fs::read_to_string(entry.path().join("cpulist"))?;
+
+			let cpus = parse_cpu_list(cpu_list.trim())
+				.ok_or(io::Error::from(io::ErrorKind::InvalidData))?;
+
+			nodes.insert(node, cpus);
+		}
+
+		Ok(Topology {
+			nodes,
+		})
+	}
+
+	/// Returns the CPUs of the supplied nodes, or `None` if any of the nodes
+	/// does not exist.
+	pub fn cpus_of(&self, nodes: &[u32]) -> Option<Vec<usize>> {
+		let mut cpus = Vec::new();
+
+		for node in nodes {
+			cpus.extend(self.nodes.get(node)?);
+		}
+
+		cpus.sort_unstable();
+		cpus.dedup();
+
+		Some(cpus)
+	}
+
+	pub fn has_node(&self, node: u32) -> bool {
+		self.nodes.contains_key(&node)
+	}
+
+	pub fn has_cpu(&self, cpu: usize) -> bool {
+		self.nodes
+			.values()
+			.any(|cpus| cpus.contains(&cpu))
+	}
+}
+
+impl FromStr for MemoryPolicy {
+	type Err = ServerError;
+
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		match value {
+			"bind" => Ok(MemoryPolicy::Bind),
+			"preferred" => Ok(MemoryPolicy::Preferred),
+			"interleave" => Ok(MemoryPolicy::Interleave),
+
+			_ => Err(ServerError::InvalidConfigParam("memory_policy")),
+		}
+	}
+}
+
+impl fmt::Display for MemoryPolicy {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			MemoryPolicy::Bind => write!(f, "bind"),
+			MemoryPolicy::Preferred => write!(f, "preferred"),
+			MemoryPolicy::Interleave => write!(f, "interleave"),
+		}
+	}
+}
+
+/// Resolves the CPUs to which the connection-handling worker threads are
+/// pinned, either from `worker_cpus` or from the CPUs of `worker_nodes`.
+pub fn worker_cpus(
+	cpus: Option<&[usize]>,
+	nodes: Option<&[u32]>,
+	topology: impl FnOnce() -> io::Result<Topology>,
+) -> Result<Option<Vec<usize>>, ServerError> {
+	if let Some(cpus) = cpus {
+		return Ok(Some(cpus.to_vec()));
+	}
+
+	let Some(nodes) = nodes else {
+		return Ok(None);
+	};
+
+	let topology = topology()
+		.map_err(|_| ServerError::InvalidPlacement("could not read the NUMA topology".into()))?;
+
+	match topology.cpus_of(nodes) {
+		Some(cpus) if !cpus.is_empty() => Ok(Some(cpus)),
+		_ => Err(ServerError::InvalidPlacement("worker_nodes contains an unknown node".into())),
+	}
+}
+
+/// Checks that every worker CPU exists in the topology, if the topology
+/// could be read.
+pub fn validate_cpus(cpus: Option<&[usize]>, topology: Option<&Topology>) -> Result<(), ServerError> {
+	let (Some(cpus), Some(topology)) = (cpus, topology) else {
+		return Ok(());
+	};
+
+	match cpus.iter().find(|cpu| !topology.has_cpu(**cpu)) {
+		Some(cpu) => Err(ServerError::InvalidPlacement(format!("cpu {cpu} does not exist"))),
+		None => Ok(()),
+	}
+}
+
+/// Checks that every node exists in the topology, if the topology could be
+/// read.
+pub fn validate_nodes(nodes: &[u32], topology: Option<&Topology>) -> Result<(), ServerError> {
+	let Some(topology) = topology else {
+		return Ok(());
+	};
+
+	match nodes.iter().find(|node| !topology.has_node(**node)) {
+		Some(node) => Err(ServerError::InvalidPlacement(format!("node {node} does not exist"))),
+		None => Ok(()),
+	}
+}
+
+/// Sets the memory policy of the calling thread, so the pages it faults in
+/// after this point are placed on the supplied nodes. The policy only
+/// applies to the calling thread, so the server binds the threads which
+/// allocate the cache's objects rather than the thread which builds it.
+pub fn bind_memory(
+	nodes: &[u32],
+	policy: MemoryPolicy,
+	topology: &Topology,
+) -> Result<(), ServerError> {
+	if let Some(node) = nodes.iter().find(|node| !topology.has_node(**node)) {
+		return Err(ServerError::InvalidPlacement(format!("memory node {node} does not exist")));
+	}
+
+	bind_current_thread(nodes, policy)
+		.map_err(|err| ServerError::InvalidPlacement(err.to_string()))
+}
+
+/// Parses a list of CPUs in the kernel's cpulist format (e.g. `0-3,8,10-11`).
+/// Every CPU must be below `MAX_CPUS`.
+pub fn parse_cpu_list(value: &str) -> Option<Vec<usize>> {
+	parse_id_list(value, MAX_CPUS)
+}
+
+/// Parses a list of IDs in the kernel's cpulist format, where every ID must
+/// be below `limit`.
+pub fn parse_id_list(value: &str, limit: usize) -> Option<Vec<usize>> {
+	let mut ids = Vec::new();
+
+	if value.is_empty() {
+		return Some(ids);
+	}
+
+	for range in value.split(',') {
+		let range = range.trim();
+
+		match range.split_once('-') {
+			Some((start, end)) => {
+				let start = start.parse::<usize>().ok()?;
+				let end = end.parse::<usize>().ok()?;
+
+				if start > end || end >= limit {
+					return None;
+				}
+
+				ids.extend(start..=end);
+			},
+
+			None => match range.parse().ok()? {
+				id if id < limit => ids.push(id),
+				_ => return None,
+			},
+		}
+	}
+
+	ids.sort_unstable();
+	ids.dedup();
+
+	Some(ids)
+}
+
+/// Formats a sorted list of CPUs in the kernel's cpulist format, collapsing
+/// consecutive CPUs into ranges.
+pub fn format_cpu_list(cpus: &[usize]) -> String {
+	let mut ranges: Vec<(usize, usize)> = Vec::new();
+
+	for cpu in cpus {
+		match ranges.last_mut() {
+			Some((_, end)) if *end + 1 == *cpu => *end = *cpu,
+			_ => ranges.push((*cpu, *cpu)),
+		}
+	}
+
+	ranges
+		.iter()
+		.map(|(start, end)| match start == end {
+			true => start.to_string(),
+			false => format!("{start}-{end}"),
+		})
+		.collect::<Vec<_>>()
+		.join(",")
+}
+
+#[cfg(target_os = "linux")]
+pub fn pin_current_thread(cpus: &[usize]) -> io::Result<()> {
+	// a CPU outside of the set would be written past the end of it
+	if cpus.iter().any(|cpu| *cpu >= MAX_CPUS) {
+		return Err(io::Error::from(io::ErrorKind::InvalidInput));
+	}
+
+	let result = unsafe {
+		let mut cpu_set = std::mem::zeroed::<libc::cpu_set_t>();
+		libc::CPU_ZERO(&mut cpu_set);
+
+		for cpu in cpus {
+			libc::CPU_SET(*cpu, &mut cpu_set);
+		}
+
+		libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &cpu_set)
+	};
+
+	match result {
+		0 => Ok(()),
+		_ => Err(io::Error::last_os_error()),
+	}
+}
+
+#[cfg(target_os = "linux")]
+pub fn bind_range(ptr: *const u8, len: usize, node: u32) -> io::Result<()> {
+	const MPOL_BIND: libc::c_long = 2;
+
+	let node_mask = node_mask(&[node]);
+
+	let result = unsafe {
+		libc::syscall(
+			libc::SYS_mbind,
+			ptr,
+			len,
+			MPOL_BIND,
+			node_mask.as_ptr(),
+			node_mask.len() * usize::BITS as usize + 1,
+			0,
+		)
+	};
+
+	match result {
+		0 => Ok(()),
+		_ => Err(io::Error::last_os_error()),
+	}
+}
+
+#[cfg(target_os = "linux")]
+pub fn bind_current_thread(nodes: &[u32], policy: MemoryPolicy) -> io::Result<()> {
+	let mode: libc::c_long = match policy {
+		MemoryPolicy::Preferred => 1,
+		MemoryPolicy::Bind => 2,
+		MemoryPolicy::Interleave => 3,
+	};
+
+	let node_mask = node_mask(nodes);
+
+	let result = unsafe {
+		libc::syscall(
+			libc::SYS_set_mempolicy,
+			mode,
+			node_mask.as_ptr(),
+			node_mask.len() * usize::BITS as usize + 1,
+		)
+	};
+
+	match result {
+		0 => Ok(()),
+		_ => Err(io::Error::last_os_error()),
+	}
+}
+
+#[cfg(target_os = "linux")]
+fn node_mask(nodes: &[u32]) -> Vec<usize> {
+	let bits_per_word = usize::BITS as usize;
+	let max_node = nodes.iter().copied().max().unwrap_or(0) as usize;
+
+	let mut node_mask = vec![0usize; max_node / bits_per_word + 1];
+
+	for node in nodes {
+		let node = *node as usize;
+		node_mask[node / bits_per_word] |= 1 << (node % bits_per_word);
+	}
+
+	node_mask
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_current_thread(_: &[usize]) -> io::Result<()> {
+	Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn bind_range(_: *const u8, _: usize, _: u32) -> io::Result<()> {
+	Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn bind_current_thread(_: &[u32], _: MemoryPolicy) -> io::Result<()> {
+	Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+#[cfg(test)]
+mod tests {
+	use std::path::PathBuf;
+	use super::*;
+
+	/// Writes a fake sysfs node directory with the supplied cpulist of every
+	/// node, along with the other entries sysfs holds next to the nodes.
+	fn fake_sysfs(name: &str, nodes: &[(u32, &str)]) -> PathBuf {
+		let path = std::env::temp_dir().join(format!("paper-numa-{}-{name}", std::process::id()));
+		let _ = fs::remove_dir_all(&path);
+
+		fs::create_dir_all(path.join("power")).unwrap();
+		fs::write(path.join("possible"), "0-1\n").unwrap();
+
+		for (node, cpu_list) in nodes {
+			let node_path = path.join(format!("node{node}"));
+
+			fs::create_dir_all(&node_path).unwrap();
+			fs::write(node_path.join("cpulist"), format!("{cpu_list}\n")).unwrap();
+		}
+
+		path
+	}
+
+	#[test]
+	fn it_reads_a_topology_from_sysfs() {
+		let path = fake_sysfs("read", &[(0, "0-3"), (1, "4-5,8")]);
+		let topology = Topology::from_sysfs(&path).unwrap();
+
+		assert!(topology.has_node(0) && topology.has_node(1));
+		assert!(!topology.has_node(2));
+
+		assert_eq!(topology.cpus_of(&[1]), Some(vec![4, 5, 8]));
+		assert_eq!(topology.cpus_of(&[1, 0]), Some(vec![0, 1, 2, 3, 4, 5, 8]));
+		assert_eq!(topology.cpus_of(&[2]), None);
+
+		assert!(topology.has_cpu(8));
+		assert!(!topology.has_cpu(6));
+
+		fs::remove_dir_all(path).unwrap();
+	}
+
+	#[test]
+	fn it_reads_a_node_without_cpus() {
+		let path = fake_sysfs("memory-only", &[(0, "0-1"), (1, "")]);
+		let topology = Topology::from_sysfs(&path).unwrap();
+
+		assert_eq!(topology.cpus_of(&[1]), Some(vec![]));
+
+		fs::remove_dir_all(path).unwrap();
+	}
+
+	#[test]
+	fn it_rejects_an_invalid_cpulist() {
+		let path = fake_sysfs("invalid", &[(0, "3-1")]);
+		assert!(Topology::from_sysfs(&path).is_err());
+
+		fs::remove_dir_all(path).unwrap();
+	}
+
+	#[test]
+	fn it_resolves_worker_cpus_from_nodes() {
+		let path = fake_sysfs("workers", &[(0, "0-1"), (1, "2-3")]);
+
+		let cpus = worker_cpus(None, Some(&[1]), || Topology::from_sysfs(&path)).unwrap();
+		assert_eq!(cpus, Some(vec![2, 3]));
+
+		let cpus = worker_cpus(Some(&[0]), Some(&[1]), || Topology::from_sysfs(&path)).unwrap();
+		assert_eq!(cpus, Some(vec![0]));
+
+		assert!(worker_cpus(None, Some(&[2]), || Topology::from_sysfs(&path)).is_err());
+
+		fs::remove_dir_all(path).unwrap();
+	}
+
+	#[test]
+	fn it_validates_a_placement_against_the_topology() {
+		let path = fake_sysfs("validate", &[(0, "0-1"), (1, "2-3")]);
+		let topology = Topology::from_sysfs(&path).unwrap();
+
+		assert!(validate_cpus(Some(&[0, 3]), Some(&topology)).is_ok());
+		assert!(validate_cpus(Some(&[4]), Some(&topology)).is_err());
+		assert!(validate_cpus(Some(&[4]), None).is_ok());
+
+		assert!(validate_nodes(&[1], Some(&topology)).is_ok());
+		assert!(validate_nodes(&[2], Some(&topology)).is_err());
+
+		fs::remove_dir_all(path).unwrap();
+	}
+
+	#[test]
+	fn it_parses_a_cpu_list() {
+		assert_eq!(parse_cpu_list(""), Some(vec![]));
+		assert_eq!(parse_cpu_list("0-2,8, 10-11"), Some(vec![0, 1, 2, 8, 10, 11]));
+		assert_eq!(parse_cpu_list("3,1,3"), Some(vec![1, 3]));
+
+		assert_eq!(parse_cpu_list("2-1"), None);
+		assert_eq!(parse_cpu_list("a"), None);
+	}
+
+	#[test]
+	fn it_rejects_cpus_outside_of_the_cpu_set() {
+		assert!(parse_cpu_list(&(MAX_CPUS - 1).to_string()).is_some());
+
+		assert_eq!(parse_cpu_list(&MAX_CPUS.to_string()), None);
+		assert_eq!(parse_cpu_list(&format!("0-{}", usize::MAX)), None);
+	}
+
+	#[test]
+	fn it_parses_ids_below_the_limit() {
+		assert_eq!(parse_id_list("16000-16002", 16384), Some(vec![16000, 16001, 16002]));
+		assert_eq!(parse_id_list("16384", 16384), None);
+	}
+
+	#[test]
+	fn it_formats_a_cpu_list() {
+		assert_eq!(format_cpu_list(&[0, 1, 2, 8, 10, 11]), "0-2,8,10-11");
+		assert_eq!(format_cpu_list(&[]), "");
+	}
+}
@@ -0,0 +1,146 @@
+/*
+ * Copyright (c) Kia Shakiba
+ *
+ * This source code is licensed under the GNU AGPLv3 license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use byteorder::{ByteOrder, LittleEndian};
+use paper_cache::CacheError;
+use paper_utils::stream::Buffer;
+
+use crate::{
+	error::ServerError,
+	expiry::now_millis,
+};
+
+const VERSION_SIZE: usize = 8;
+const EXPIRES_AT_SIZE: usize = 8;
+
+pub const HEADER_SIZE: usize = VERSION_SIZE + EXPIRES_AT_SIZE;
+
+/// Every value stored in the cache is prefixed with a header which holds
+/// the server-side metadata of the object: its version and its absolute
+/// expiry in unix milliseconds (zero if the object never expires).
+///
+/// The cache only sees the encoded object, so the header counts towards
+/// `max_size` and towards the cache's limit on the size of a single value
+/// (the largest value a client can set is `HEADER_SIZE` bytes smaller than
+/// that limit). The header is excluded from the sizes the server reports,
+/// i.e., `SIZE` and the `used_size` of `STATUS`.
+pub struct Object<'a> {
+	version: u64,
+	expires_at: Option<u64>,
+
+	value: &'a [u8],
+}
+
+impl<'a> Object<'a> {
+	pub fn new(
+		version: u64,
+		expires_at: Option<u64>,
+		value: &'a [u8],
+	) -> Self {
+		Object {
+			version,
+			expires_at,
+
+			value,
+		}
+	}
+
+	/// Decodes an object read from the cache. The cache only expires
+	/// objects with second granularity, so an object whose expiry has
+	/// already passed is treated as if it were not in the cache.
+	pub fn from_bytes(bytes: &'a [u8]) -> Result<Self, ServerError> {
+		if bytes.len() < HEADER_SIZE {
+			return Err(ServerError::InvalidObject);
+		}
+
+		let version = LittleEndian::read_u64(&bytes[..VERSION_SIZE]);
+
+		let expires_at = match LittleEndian::read_u64(&bytes[VERSION_SIZE..HEADER_SIZE]) {
+			0 => None,
+			expires_at => Some(expires_at),
+		};
+
+		if expires_at.is_some_and(|expires_at| expires_at <= now_millis()) {
+			return Err(ServerError::CacheError(CacheError::KeyNotFound));
+		}
+
+		Ok(Object {
+			version,
+			expires_at,
+
+			value: &bytes[HEADER_SIZE..],
+		})
+	}
+
+	pub fn version(&self) -> u64 {
+		self.version
+	}
+
+	pub fn expires_at(&self) -> Option<u64> {
+		self.expires_at
+	}
+
+	pub fn value(&self) -> &'a [u8] {
+		self.value
+	}
+
+	pub fn to_buffer(&self) -> Buffer {
+		let mut bytes = vec![0; HEADER_SIZE + self.value.len()];
+
+		LittleEndian::write_u64(&mut bytes[..VERSION_SIZE], self.version);
+		LittleEndian::write_u64(&mut bytes[VERSION_SIZE..HEADER_SIZE], self.expires_at.unwrap_or(0));
+		bytes[HEADER_SIZE..].copy_from_slice(self.value);
+
+		Buffer::from(bytes)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn it_round_trips_an_object() {
+		let expires_at = now_millis() + 60_000;
+		let buffer = Object::new(7, Some(expires_at), b"value").to_buffer();
+
+		assert_eq!(buffer.len(), HEADER_SIZE + 5);
+
+		let object = Object::from_bytes(&buffer).unwrap();
+
+		assert_eq!(object.version(), 7);
+		assert_eq!(object.expires_at(), Some(expires_at));
+		assert_eq!(object.value(), b"value");
+	}
+
+	#[test]
+	fn it_round_trips_an_object_without_expiry() {
+		let buffer = Object::new(1, None, b"").to_buffer();
+		let object = Object::from_bytes(&buffer).unwrap();
+
+		assert_eq!(object.expires_at(), None);
+		assert!(object.value().is_empty());
+	}
+
+	#[test]
+	fn it_treats_an_expired_object_as_missing() {
+		let buffer = Object::new(1, Some(now_millis() - 1), b"value").to_buffer();
+
+		assert_eq!(
+			Object::from_bytes(&buffer).err(),
+			Some(ServerError::CacheError(CacheError::KeyNotFound)),
+		);
+	}
+
+	#[test]
+	fn it_rejects_a_truncated_header() {
+		assert_eq!(
+			Object::from_bytes(&[0; HEADER_SIZE - 1]).err(),
+			Some(ServerError::InvalidObject),
+		);
+	}
+}
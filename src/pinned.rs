@@ -0,0 +1,115 @@
+/*
+ * Copyright (c) Kia Shakiba
+ *
+ * This source code is licensed under the GNU AGPLv3 license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::{
+	collections::HashMap,
+	sync::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use paper_utils::stream::Buffer;
+
+use crate::error::ServerError;
+
+/// Holds the objects which are pinned outside of the cache, so they are
+/// never evicted regardless of the cache's policy. The pinned objects have
+/// their own size budget so that they cannot starve the cache.
+pub struct PinnedObjects {
+	inner: RwLock<Pinned>,
+	max_size: u64,
+}
+
+#[derive(Default)]
+struct Pinned {
+	objects: HashMap<Buffer, Buffer>,
+	used_size: u64,
+}
+
+impl PinnedObjects {
+	pub fn new(max_size: u64) -> Self {
+		PinnedObjects {
+			inner: RwLock::default(),
+			max_size,
+		}
+	}
+
+	pub fn used_size(&self) -> u64 {
+		self.read().used_size
+	}
+
+	pub fn max_size(&self) -> u64 {
+		self.max_size
+	}
+
+	pub fn contains(&self, key: &[u8]) -> bool {
+		self.read().objects.contains_key(key)
+	}
+
+	/// Returns a copy of the encoded object if the key is pinned.
+	pub fn get(&self, key: &[u8]) -> Option<Buffer> {
+		self.read()
+			.objects
+			.get(key)
+			.cloned()
+	}
+
+	/// Pins the encoded object, replacing the key's current pinned object.
+	pub fn insert(&self, key: Buffer, bytes: Buffer) -> Result<(), ServerError> {
+		let mut inner = self.write();
+
+		let current_size = inner.objects
+			.get(&key)
+			.map(|current| object_size(&key, current))
+			.unwrap_or(0);
+
+		let size = object_size(&key, &bytes);
+
+		if inner.used_size - current_size + size > self.max_size {
+			return Err(ServerError::PinnedSizeExceeded);
+		}
+
+		inner.used_size = inner.used_size - current_size + size;
+		inner.objects.insert(key, bytes);
+
+		Ok(())
+	}
+
+	pub fn remove(&self, key: &[u8]) -> Option<Buffer> {
+		if !self.contains(key) {
+			return None;
+		}
+
+		let mut inner = self.write();
+		let (key, bytes) = inner.objects.remove_entry(key)?;
+
+		inner.used_size -= object_size(&key, &bytes);
+
+		Some(bytes)
+	}
+
+	pub fn clear(&self) {
+		let mut inner = self.write();
+
+		inner.objects.clear();
+		inner.used_size = 0;
+	}
+
+	fn read(&self) -> RwLockReadGuard<'_, Pinned> {
+		self.inner
+			.read()
+			.unwrap_or_else(|err| err.into_inner())
+	}
+
+	fn write(&self) -> RwLockWriteGuard<'_, Pinned> {
+		self.inner
+			.write()
+			.unwrap_or_else(|err| err.into_inner())
+	}
+}
+
+fn object_size(key: &[u8], bytes: &[u8]) -> u64 {
+	(key.len() + bytes.len()) as u64
+}
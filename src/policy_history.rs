@@ -0,0 +1,251 @@
+/*
+ * Copyright (c) Kia Shakiba
+ *
+ * This source code is licensed under the GNU AGPLv3 license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::{
+	sync::{Mutex, MutexGuard},
+	collections::VecDeque,
+};
+
+use log::info;
+use paper_cache::PaperPolicy;
+
+use crate::{
+	error::ServerError,
+	store::Cache,
+	expiry,
+};
+
+const MAX_HISTORY_LENGTH: usize = 1000;
+
+/// Records every change of the cache's eviction policy, whether it was
+/// issued by a client or made by the cache in auto-policy mode. The cache
+/// does not report its own switches, so they are detected by periodically
+/// observing its status.
+pub struct PolicyHistory {
+	state: Mutex<HistoryState>,
+}
+
+struct HistoryState {
+	policy: PaperPolicy,
+	switches: VecDeque<PolicySwitch>,
+}
+
+#[derive(Clone)]
+pub struct PolicySwitch {
+	pub timestamp: u64,
+
+	pub old_policy: PaperPolicy,
+	pub new_policy: PaperPolicy,
+
+	pub miss_ratio: f64,
+	pub source: SwitchSource,
+}
+
+#[derive(Clone)]
+pub enum SwitchSource {
+	Auto,
+
+	/// A switch issued by the client at the contained address.
+	Manual(String),
+}
+
+impl PolicyHistory {
+	pub fn new(cache: &Cache) -> Result<Self, ServerError> {
+		let status = cache.status()?;
+
+		let state = HistoryState {
+			policy: status.policy(),
+			switches: VecDeque::new(),
+		};
+
+		Ok(PolicyHistory {
+			state: Mutex::new(state),
+		})
+	}
+
+	/// Switches the cache's policy on behalf of the client at `address`.
+	pub fn switch(
+		&self,
+		cache: &Cache,
+		policy: PaperPolicy,
+		address: &str,
+	) -> Result<(), ServerError> {
+		let mut state = self.lock();
+
+		// an automatic switch made since the last observation is recorded
+		// first so the manual switch is recorded from the correct policy
+		state.observe(cache)?;
+
+		cache.policy(policy)?;
+
+		let status = cache.status()?;
+		let source = SwitchSource::Manual(address.into());
+
+		state.record(policy, status.miss_ratio(), source);
+
+		// in auto-policy mode, the recorded policy is auto, while later
+		// observations are compared against the policy the cache picked
+		state.policy = status.policy();
+
+		Ok(())
+	}
+
+	/// Records a switch if the cache changed its policy since the last
+	/// observation.
+	pub fn observe(&self, cache: &Cache) -> Result<(), ServerError> {
+		self.lock().observe(cache)
+	}
+
+	pub fn switches(&self) -> Vec<PolicySwitch> {
+		self.lock()
+			.switches
+			.iter()
+			.cloned()
+			.collect()
+	}
+
+	fn lock(&self) -> MutexGuard<'_, HistoryState> {
+		self.state
+			.lock()
+			.unwrap_or_else(|err| err.into_inner())
+	}
+}
+
+impl HistoryState {
+	fn observe(&mut self, cache: &Cache) -> Result<(), ServerError> {
+		let status = cache.status()?;
+
+		if status.policy() != self.policy {
+			self.record(status.policy(), status.miss_ratio(), SwitchSource::Auto);
+		}
+
+		Ok(())
+	}
+
+	fn record(
+		&mut self,
+		new_policy: PaperPolicy,
+		miss_ratio: f64,
+		source: SwitchSource,
+	) {
+		let switch = PolicySwitch {
+			timestamp: expiry::now_millis(),
+
+			old_policy: self.policy,
+			new_policy,
+
+			miss_ratio,
+			source,
+		};
+
+		match &switch.source {
+			SwitchSource::Auto => info!(
+				"Policy switched automatically from {} to {} (miss ratio {:.4})",
+				switch.old_policy,
+				switch.new_policy,
+				switch.miss_ratio,
+			),
+
+			SwitchSource::Manual(address) => info!(
+				"Policy switched from {} to {} by {address} (miss ratio {:.4})",
+				switch.old_policy,
+				switch.new_policy,
+				switch.miss_ratio,
+			),
+		}
+
+		if self.switches.len() == MAX_HISTORY_LENGTH {
+			self.switches.pop_front();
+		}
+
+		self.policy = new_policy;
+		self.switches.push_back(switch);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn cache(policy: PaperPolicy) -> Cache {
+		Cache::new(1 << 20, &[PaperPolicy::Lru, PaperPolicy::Lfu], policy).unwrap()
+	}
+
+	fn policies(switch: &PolicySwitch) -> (PaperPolicy, PaperPolicy) {
+		(switch.old_policy, switch.new_policy)
+	}
+
+	#[test]
+	fn it_records_a_manual_switch() {
+		let cache = cache(PaperPolicy::Lru);
+		let history = PolicyHistory::new(&cache).unwrap();
+
+		history.switch(&cache, PaperPolicy::Lfu, "127.0.0.1:1234").unwrap();
+
+		let switches = history.switches();
+
+		assert_eq!(switches.len(), 1);
+		assert_eq!(policies(&switches[0]), (PaperPolicy::Lru, PaperPolicy::Lfu));
+		assert!(matches!(&switches[0].source, SwitchSource::Manual(address) if address == "127.0.0.1:1234"));
+	}
+
+	#[test]
+	fn it_records_an_automatic_switch_once() {
+		let cache = cache(PaperPolicy::Lru);
+		let history = PolicyHistory::new(&cache).unwrap();
+
+		history.observe(&cache).unwrap();
+		assert!(history.switches().is_empty());
+
+		// the cache switches its own policy without notifying the server
+		cache.policy(PaperPolicy::Lfu).unwrap();
+
+		history.observe(&cache).unwrap();
+		history.observe(&cache).unwrap();
+
+		let switches = history.switches();
+
+		assert_eq!(switches.len(), 1);
+		assert_eq!(policies(&switches[0]), (PaperPolicy::Lru, PaperPolicy::Lfu));
+		assert!(matches!(switches[0].source, SwitchSource::Auto));
+	}
+
+	#[test]
+	fn it_records_an_automatic_switch_before_a_manual_one() {
+		let cache = cache(PaperPolicy::Lru);
+		let history = PolicyHistory::new(&cache).unwrap();
+
+		cache.policy(PaperPolicy::Lfu).unwrap();
+		history.switch(&cache, PaperPolicy::Lru, "client").unwrap();
+
+		let switches = history.switches();
+
+		assert_eq!(switches.len(), 2);
+		assert_eq!(policies(&switches[0]), (PaperPolicy::Lru, PaperPolicy::Lfu));
+		assert_eq!(policies(&switches[1]), (PaperPolicy::Lfu, PaperPolicy::Lru));
+	}
+
+	#[test]
+	fn it_keeps_the_latest_switches() {
+		let cache = cache(PaperPolicy::Lru);
+		let history = PolicyHistory::new(&cache).unwrap();
+
+		for index in 0..MAX_HISTORY_LENGTH + 1 {
+			let policy = match index % 2 {
+				0 => PaperPolicy::Lfu,
+				_ => PaperPolicy::Lru,
+			};
+
+			history.switch(&cache, policy, &index.to_string()).unwrap();
+		}
+
+		let switches = history.switches();
+
+		assert_eq!(switches.len(), MAX_HISTORY_LENGTH);
+		assert!(matches!(&switches[0].source, SwitchSource::Manual(address) if address == "1"));
+	}
+}
@@ -0,0 +1,755 @@
+/*
+ * Copyright (c) Kia Shakiba
+ *
+ * This source code is licensed under the GNU AGPLv3 license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::{
+	io::Write,
+	collections::BTreeMap,
+	time::{Duration, Instant},
+	net::{TcpListener, Shutdown},
+	sync::{
+		Arc,
+		Mutex,
+		MutexGuard,
+		atomic::{AtomicUsize, Ordering},
+	},
+};
+
+use log::{info, warn, error};
+use kwik::thread_pool::ThreadPool;
+
+use paper_utils::{
+	stream::{Buffer, StreamError},
+	sheet::{Sheet, SheetBuilder},
+};
+
+use crate::{
+	error::{ServerError, ErrorCode},
+	command::Command,
+	connection::Connection,
+	config::Config,
+	cluster::Cluster,
+	node_client::{self, NodeClient, Field},
+};
+
+type SheetResult = Result<Sheet, ServerError>;
+
+// how long a backend which failed is ejected before it is tried again
+const EJECT_DURATION: Duration = Duration::from_secs(5);
+
+/// Accepts the single-node protocol and forwards every command to the
+/// backend which owns its key, so that clients can use a sharded
+/// deployment unchanged. An MGET is split into one MGET per backend, and
+/// commands which apply to the whole keyspace are fanned out to every
+/// backend, while commands which inspect a single node (such as STATUS)
+/// must be sent to the nodes directly.
+///
+/// A backend which redirects a key to another node is taken as the owner
+/// of the key's slot from then on, so the proxy follows migrations.
+pub struct Proxy {
+	listener: TcpListener,
+	backends: Arc<Backends>,
+
+	pool: ThreadPool,
+
+	max_connections: usize,
+	num_connections: Arc<AtomicUsize>,
+	auth_token: Option<u64>,
+}
+
+struct Backends {
+	cluster: Cluster,
+	backends: Vec<Backend>,
+}
+
+/// A pool of connections to a single backend. A backend whose connection
+/// fails (including on a fresh connection, since a pooled connection may
+/// have been closed by the backend while it was idle) is ejected for a
+/// while, during which its commands fail fast rather than waiting on the
+/// backend.
+struct Backend {
+	address: String,
+	auth_token: Option<String>,
+
+	idle: Mutex<Vec<NodeClient>>,
+	max_idle: usize,
+
+	ejected_until: Mutex<Option<Instant>>,
+}
+
+impl Proxy {
+	pub fn new(config: &Config) -> Result<Self, ServerError> {
+		if config.cluster_nodes().is_empty() {
+			return Err(ServerError::InvalidConfigParam("cluster_nodes"));
+		}
+
+		let addr = format!("{}:{}", config.host(), config.port());
+
+		let Ok(listener) = TcpListener::bind(addr) else {
+			return Err(ServerError::InvalidAddress);
+		};
+
+		let cluster = Cluster::new(config.cluster_nodes(), None)?;
+
+		let backends = cluster
+			.nodes()
+			.iter()
+			.map(|node| Backend::new(
+				node.address(),
+				config.cluster_auth_token().map(String::from),
+				config.max_connections(),
+			))
+			.collect();
+
+		let proxy = Proxy {
+			listener,
+			backends: Arc::new(Backends {
+				cluster,
+				backends,
+			}),
+
+			pool: ThreadPool::new(config.max_connections()),
+
+			max_connections: config.max_connections(),
+			num_connections: Arc::new(AtomicUsize::new(0)),
+			auth_token: config.auth_token(),
+		};
+
+		Ok(proxy)
+	}
+
+	pub fn listen(&mut self) -> Result<(), ServerError> {
+		for stream in self.listener.incoming() {
+			match stream {
+				Ok(mut stream) => {
+					if self.num_connections.load(Ordering::Relaxed) == self.max_connections {
+						warn!("Maximum number of connections exceeded");
+
+						let sheet = ServerError::MaxConnectionsExceeded.to_sheet();
+						let _ = stream.write_all(sheet.serialize());
+
+						let _ = stream.shutdown(Shutdown::Both);
+						return Err(ServerError::MaxConnectionsExceeded);
+					}
+
+					let address = stream
+						.peer_addr()
+						.map(|address| address.to_string())
+						.unwrap_or("-1".into());
+
+					info!("Connected: {address}");
+
+					let sheet = SheetBuilder::new()
+						.write_bool(true)
+						.into_sheet();
+
+					stream
+						.write_all(sheet.serialize())
+						.map_err(|_| ServerError::InvalidResponse)?;
+
+					let connection = Connection::new(stream, address.clone(), self.auth_token);
+					let backends = self.backends.clone();
+					let num_connections = Arc::clone(&self.num_connections);
+
+					self.pool.execute(move || {
+						num_connections.fetch_add(1, Ordering::Relaxed);
+						Proxy::handle_connection(connection, backends);
+
+						info!("Disconnected: {address}");
+						num_connections.fetch_sub(1, Ordering::Relaxed);
+					});
+				},
+
+				Err(_) => return Err(ServerError::InvalidConnection),
+			}
+		}
+
+		Ok(())
+	}
+
+	fn handle_connection(mut connection: Connection, backends: Arc<Backends>) {
+		loop {
+			let command = match connection.get_command() {
+				Ok(command) => command,
+				Err(ServerError::Disconnected) => return,
+
+				Err(err) => {
+					error!("{err}");
+					continue;
+				},
+			};
+
+			let sheet_result = match (connection.is_authorized(), command) {
+				(_, Command::Ping) => handle_ping(),
+				(_, Command::Auth(token)) => handle_auth(&mut connection, &token),
+
+				(true, command @ (Command::Version | Command::ClusterNodes)) => backends.forward_any(&command),
+
+				(true, command @ (Command::Wipe | Command::Resize(_) | Command::Policy(_))) => backends.forward_all(&command),
+
+				(true, command @ (Command::DelPrefix(_) | Command::Invalidate(_))) => backends.forward_all_count(&command),
+
+				(true, Command::MGet(keys)) => backends.forward_mget(&keys),
+
+				(true, Command::Scan(..) | Command::Status | Command::Mrc | Command::PolicyHistory)
+					| (true, Command::TraceStart(..) | Command::TraceStop)
+					| (true, Command::ClusterMigrate(..) | Command::ClusterImport(..) | Command::ClusterCancelImport(..) | Command::ClusterSetSlots(..))
+					=> Err(ServerError::InvalidCommand("command is not supported by the proxy".into())),
+
+				(true, command) => backends.forward_key(&command),
+
+				_ => Err(ServerError::Unauthorized),
+			};
+
+			let sheet = sheet_result.unwrap_or_else(|err| err.to_sheet());
+
+			if (connection.send_response(sheet.serialize())).is_err() {
+				error!("Could not send response to command");
+			}
+		}
+	}
+}
+
+impl Backends {
+	/// Forwards a command which has a key to the backend which owns it. If
+	/// the backend redirects the key, the command is retried once on the
+	/// backend it was redirected to.
+	fn forward_key(&self, command: &Command) -> SheetResult {
+		let Some(key) = command.key() else {
+			return Err(ServerError::InvalidCommand("command has no key".into()));
+		};
+
+		let mut fields = self.backends[self.cluster.owner(key)].forward(command)?;
+
+		if let Some(owner) = self.follow_redirect(&fields) {
+			fields = self.backends[owner].forward(command)?;
+		}
+
+		Ok(node_client::into_sheet(fields))
+	}
+
+	/// Splits an MGET into one MGET per backend which owns any of its keys,
+	/// and responds with the values in the order of the keys. The keys of a
+	/// batch which is redirected are split again by their new owners and
+	/// retried once.
+	fn forward_mget(&self, keys: &[Buffer]) -> SheetResult {
+		let mut values = vec![None; keys.len()];
+		let mut pending = (0..keys.len()).collect::<Vec<_>>();
+
+		for attempt in 0..2 {
+			let mut redirected = Vec::new();
+
+			for (owner, indexes) in self.split_by_owner(keys, &pending) {
+				let batch = Command::MGet(indexes
+					.iter()
+					.map(|index| keys[*index].clone())
+					.collect());
+
+				let fields = self.backends[owner].forward(&batch)?;
+
+				if node_client::is_ok(&fields) {
+					let batch_values = mget_values(fields)
+						.filter(|batch_values| batch_values.len() == indexes.len())
+						.ok_or(ServerError::InvalidResponse)?;
+
+					for (index, value) in indexes.into_iter().zip(batch_values) {
+						values[index] = value;
+					}
+
+					continue;
+				}
+
+				match attempt == 0 && self.follow_redirect(&fields).is_some() {
+					true => redirected.extend(indexes),
+					false => return Ok(node_client::into_sheet(fields)),
+				}
+			}
+
+			pending = redirected;
+
+			if pending.is_empty() {
+				break;
+			}
+		}
+
+		let sheet_builder = SheetBuilder::new()
+			.write_bool(true)
+			.write_u32(values.len() as u32);
+
+		let sheet = values
+			.into_iter()
+			.fold(sheet_builder, |builder, value| match value {
+				Some(value) => builder
+					.write_bool(true)
+					.write_buf(&value),
+
+				None => builder.write_bool(false),
+			})
+			.into_sheet();
+
+		Ok(sheet)
+	}
+
+	/// Groups the indexes of the keys by the backend which owns each key.
+	fn split_by_owner(&self, keys: &[Buffer], indexes: &[usize]) -> BTreeMap<usize, Vec<usize>> {
+		let mut batches = BTreeMap::<usize, Vec<usize>>::new();
+
+		for index in indexes {
+			batches
+				.entry(self.cluster.owner(&keys[*index]))
+				.or_default()
+				.push(*index);
+		}
+
+		batches
+	}
+
+	/// If the response redirects a slot to a known backend, takes that
+	/// backend as the slot's owner and returns it.
+	fn follow_redirect(&self, fields: &[Field]) -> Option<usize> {
+		let [Field::Bool(false), Field::U8(ErrorCode::MOVED), Field::U32(slot), Field::Buf(address)] = fields else {
+			return None;
+		};
+
+		let owner = self.backends
+			.iter()
+			.position(|backend| backend.address.as_bytes() == address.as_ref())?;
+
+		let slot = *slot as u16;
+		self.cluster.set_owner(slot..=slot, owner);
+
+		Some(owner)
+	}
+
+	/// Forwards a command to the first available backend.
+	fn forward_any(&self, command: &Command) -> SheetResult {
+		let mut last_err = ServerError::InvalidConnection;
+
+		for backend in &self.backends {
+			match backend.forward(command) {
+				Ok(fields) => return Ok(node_client::into_sheet(fields)),
+				Err(err) => last_err = err,
+			}
+		}
+
+		Err(last_err)
+	}
+
+	/// Forwards a command to every backend, returning the first failed
+	/// response if any backend fails.
+	fn forward_all(&self, command: &Command) -> SheetResult {
+		let mut responses = Vec::with_capacity(self.backends.len());
+
+		for backend in &self.backends {
+			responses.push(backend.forward(command)?);
+		}
+
+		let response = responses
+			.into_iter()
+			.find(|fields| !node_client::is_ok(fields))
+			.unwrap_or(vec![Field::Bool(true)]);
+
+		Ok(node_client::into_sheet(response))
+	}
+
+	/// Forwards a command which responds with a count to every backend, and
+	/// responds with the sum of the counts.
+	fn forward_all_count(&self, command: &Command) -> SheetResult {
+		let mut count = 0;
+
+		for backend in &self.backends {
+			let fields = backend.forward(command)?;
+
+			match fields.as_slice() {
+				[Field::Bool(true), Field::U64(backend_count)] => count += backend_count,
+				_ => return Ok(node_client::into_sheet(fields)),
+			}
+		}
+
+		let sheet = SheetBuilder::new()
+			.write_bool(true)
+			.write_u64(count)
+			.into_sheet();
+
+		Ok(sheet)
+	}
+}
+
+impl Backend {
+	fn new(address: String, auth_token: Option<String>, max_idle: usize) -> Self {
+		Backend {
+			address,
+			auth_token,
+
+			idle: Mutex::default(),
+			max_idle,
+
+			ejected_until: Mutex::default(),
+		}
+	}
+
+	fn forward(&self, command: &Command) -> Result<Vec<Field>, ServerError> {
+		if self.is_ejected() {
+			return Err(ServerError::BackendUnavailable(self.address.clone()));
+		}
+
+		let idle_client = self.lock_idle().pop();
+
+		let result = match idle_client {
+			Some(client) => self
+				.send(client, command)
+				.or_else(|_| self.send_fresh(command)),
+
+			None => self.send_fresh(command),
+		};
+
+		match result {
+			Ok(fields) => Ok(fields),
+
+			Err(err) => {
+				warn!("Ejecting backend {} ({err})", self.address);
+
+				*self.lock_ejected_until() = Some(Instant::now() + EJECT_DURATION);
+				self.lock_idle().clear();
+
+				Err(ServerError::BackendUnavailable(self.address.clone()))
+			},
+		}
+	}
+
+	fn is_ejected(&self) -> bool {
+		let mut ejected_until = self.lock_ejected_until();
+
+		match *ejected_until {
+			Some(instant) if instant > Instant::now() => true,
+
+			Some(_) => {
+				*ejected_until = None;
+				false
+			},
+
+			None => false,
+		}
+	}
+
+	/// Sends the command on a new connection.
+	fn send_fresh(&self, command: &Command) -> Result<Vec<Field>, StreamError> {
+		let client = NodeClient::connect(&self.address, self.auth_token.as_deref())?;
+		self.send(client, command)
+	}
+
+	/// Sends the command and returns the connection to the pool once its
+	/// response has been read.
+	fn send(&self, mut client: NodeClient, command: &Command) -> Result<Vec<Field>, StreamError> {
+		let fields = client.send(command)?;
+		self.return_client(client);
+
+		Ok(fields)
+	}
+
+	fn return_client(&self, client: NodeClient) {
+		let mut idle = self.lock_idle();
+
+		if idle.len() < self.max_idle {
+			idle.push(client);
+		}
+	}
+
+	fn lock_idle(&self) -> MutexGuard<'_, Vec<NodeClient>> {
+		self.idle
+			.lock()
+			.unwrap_or_else(|err| err.into_inner())
+	}
+
+	fn lock_ejected_until(&self) -> MutexGuard<'_, Option<Instant>> {
+		self.ejected_until
+			.lock()
+			.unwrap_or_else(|err| err.into_inner())
+	}
+}
+
+/// Returns the values of a successful MGET response.
+fn mget_values(fields: Vec<Field>) -> Option<Vec<Option<Buffer>>> {
+	// skips the response's status and number of values
+	let mut fields = fields.into_iter().skip(2);
+	let mut values = Vec::new();
+
+	while let Some(field) = fields.next() {
+		let value = match field {
+			Field::Bool(true) => match fields.next() {
+				Some(Field::Buf(value)) => Some(value),
+				_ => return None,
+			},
+
+			Field::Bool(false) => None,
+			_ => return None,
+		};
+
+		values.push(value);
+	}
+
+	Some(values)
+}
+
+fn handle_ping() -> SheetResult {
+	let sheet = SheetBuilder::new()
+		.write_bool(true)
+		.write_buf(b"pong")
+		.into_sheet();
+
+	Ok(sheet)
+}
+
+fn handle_auth(connection: &mut Connection, token: &Buffer) -> SheetResult {
+	let is_authorized = String::from_utf8(token.to_vec())
+		.is_ok_and(|token| connection.authorize(&token));
+
+	if !is_authorized {
+		return Err(ServerError::Unauthorized);
+	}
+
+	let sheet = SheetBuilder::new()
+		.write_bool(true)
+		.into_sheet();
+
+	Ok(sheet)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{
+		net::TcpStream,
+		thread,
+		sync::atomic::AtomicUsize,
+	};
+
+	use crate::cluster::{self, ClusterNodeConfig};
+	use super::*;
+
+	type Handler = Arc<dyn Fn(&Command) -> Sheet + Send + Sync>;
+
+	/// A backend which responds to every command with the handler, and
+	/// closes each connection after its first response if `is_closing`.
+	struct FakeBackend {
+		address: String,
+		num_commands: Arc<AtomicUsize>,
+	}
+
+	impl FakeBackend {
+		fn spawn(is_closing: bool, handler: impl Fn(&Command) -> Sheet + Send + Sync + 'static) -> Self {
+			let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+			let address = listener.local_addr().unwrap().to_string();
+
+			let handler: Handler = Arc::new(handler);
+			let num_commands = Arc::new(AtomicUsize::new(0));
+			let thread_num_commands = num_commands.clone();
+
+			thread::spawn(move || {
+				for stream in listener.incoming() {
+					let stream = stream.unwrap();
+					let handler = handler.clone();
+					let num_commands = thread_num_commands.clone();
+
+					thread::spawn(move || serve(stream, is_closing, handler, num_commands));
+				}
+			});
+
+			FakeBackend {
+				address,
+				num_commands,
+			}
+		}
+
+		fn num_commands(&self) -> usize {
+			self.num_commands.load(Ordering::Relaxed)
+		}
+
+		fn node(&self, id: &str, slots: &str) -> ClusterNodeConfig {
+			let (host, port) = self.address.rsplit_once(':').unwrap();
+			format!("{id}:{host}:{port}:{slots}").parse().unwrap()
+		}
+	}
+
+	fn serve(mut stream: TcpStream, is_closing: bool, handler: Handler, num_commands: Arc<AtomicUsize>) {
+		let handshake = SheetBuilder::new()
+			.write_bool(true)
+			.into_sheet();
+
+		stream.write_all(handshake.serialize()).unwrap();
+
+		while let Ok(command) = Command::from_stream(&mut stream) {
+			num_commands.fetch_add(1, Ordering::Relaxed);
+
+			if stream.write_all(handler(&command).serialize()).is_err() || is_closing {
+				return;
+			}
+		}
+	}
+
+	fn backends(nodes: &[ClusterNodeConfig]) -> Backends {
+		let cluster = Cluster::new(nodes, None).unwrap();
+
+		let backends = nodes
+			.iter()
+			.map(|node| Backend::new(node.address(), None, 4))
+			.collect();
+
+		Backends {
+			cluster,
+			backends,
+		}
+	}
+
+	/// Responds to an MGET with each key prefixed by the backend's name.
+	fn mget_handler(name: &'static str) -> impl Fn(&Command) -> Sheet + Send + Sync {
+		move |command| {
+			let Command::MGet(keys) = command else {
+				panic!("expected an MGET");
+			};
+
+			keys
+				.iter()
+				.fold(SheetBuilder::new().write_bool(true).write_u32(keys.len() as u32), |builder, key| {
+					builder
+						.write_bool(true)
+						.write_buf(format!("{name}:{}", String::from_utf8_lossy(key)).as_bytes())
+				})
+				.into_sheet()
+		}
+	}
+
+	fn value_sheet(value: &str) -> Vec<u8> {
+		SheetBuilder::new()
+			.write_bool(true)
+			.write_buf(value.as_bytes())
+			.into_sheet()
+			.serialize()
+			.to_vec()
+	}
+
+	/// Returns a key whose slot is in the range.
+	fn key_in(slots: std::ops::Range<u16>) -> Buffer {
+		(0..)
+			.map(|index| Buffer::from(format!("key{index}").as_bytes()))
+			.find(|key| slots.contains(&cluster::key_slot(key)))
+			.unwrap()
+	}
+
+	#[test]
+	fn it_splits_an_mget_across_backends() {
+		let a = FakeBackend::spawn(false, mget_handler("a"));
+		let b = FakeBackend::spawn(false, mget_handler("b"));
+
+		let backends = backends(&[a.node("a", "0-8191"), b.node("b", "8192-16383")]);
+
+		let keys = [key_in(8192..16384), key_in(0..8192), key_in(8192..16384)];
+		let sheet = backends.forward_mget(&keys).unwrap();
+
+		let expected = keys
+			.iter()
+			.fold(SheetBuilder::new().write_bool(true).write_u32(3), |builder, key| {
+				let name = match cluster::key_slot(key) < 8192 {
+					true => "a",
+					false => "b",
+				};
+
+				builder
+					.write_bool(true)
+					.write_buf(format!("{name}:{}", String::from_utf8_lossy(key)).as_bytes())
+			})
+			.into_sheet();
+
+		assert_eq!(sheet.serialize(), expected.serialize());
+		assert_eq!(a.num_commands(), 1);
+		assert_eq!(b.num_commands(), 1);
+	}
+
+	#[test]
+	fn it_follows_a_redirect_to_the_new_owner() {
+		let b = FakeBackend::spawn(false, |_| SheetBuilder::new().write_bool(true).write_buf(b"b").into_sheet());
+
+		let b_address = b.address.clone();
+
+		let a = FakeBackend::spawn(false, move |command| {
+			let key = command.key().unwrap();
+
+			ServerError::Moved(cluster::key_slot(key), b_address.clone()).to_sheet()
+		});
+
+		let backends = backends(&[a.node("a", "0-8191"), b.node("b", "8192-16383")]);
+		let key = key_in(0..8192);
+
+		for _ in 0..2 {
+			let sheet = backends.forward_key(&Command::Get(key.clone())).unwrap();
+			assert_eq!(sheet.serialize(), value_sheet("b"));
+		}
+
+		// the second GET is sent to the new owner directly
+		assert_eq!(a.num_commands(), 1);
+		assert_eq!(b.num_commands(), 2);
+		assert_eq!(backends.cluster.owner(&key), 1);
+	}
+
+	#[test]
+	fn it_redirects_the_keys_of_an_mget_batch() {
+		let b = FakeBackend::spawn(false, mget_handler("b"));
+		let b_address = b.address.clone();
+
+		let a = FakeBackend::spawn(false, move |command| {
+			let Command::MGet(keys) = command else {
+				panic!("expected an MGET");
+			};
+
+			ServerError::Moved(cluster::key_slot(&keys[0]), b_address.clone()).to_sheet()
+		});
+
+		let backends = backends(&[a.node("a", "0-8191"), b.node("b", "8192-16383")]);
+		let key = key_in(0..8192);
+
+		let sheet = backends.forward_mget(std::slice::from_ref(&key)).unwrap();
+
+		let expected = SheetBuilder::new()
+			.write_bool(true)
+			.write_u32(1)
+			.write_bool(true)
+			.write_buf(format!("b:{}", String::from_utf8_lossy(&key)).as_bytes())
+			.into_sheet();
+
+		assert_eq!(sheet.serialize(), expected.serialize());
+	}
+
+	#[test]
+	fn it_retries_a_closed_connection_on_a_fresh_connection() {
+		let a = FakeBackend::spawn(true, |_| SheetBuilder::new().write_bool(true).write_buf(b"a").into_sheet());
+		let backend = Backend::new(a.address.clone(), None, 4);
+
+		for _ in 0..3 {
+			let fields = backend.forward(&Command::Get(Buffer::from(b"key".as_slice()))).unwrap();
+			assert_eq!(node_client::into_sheet(fields).serialize(), value_sheet("a"));
+		}
+
+		assert!(!backend.is_ejected());
+		assert_eq!(a.num_commands(), 3);
+	}
+
+	#[test]
+	fn it_ejects_an_unreachable_backend() {
+		let address = TcpListener::bind("127.0.0.1:0")
+			.unwrap()
+			.local_addr()
+			.unwrap()
+			.to_string();
+
+		let backend = Backend::new(address.clone(), None, 4);
+
+		assert!(matches!(
+			backend.forward(&Command::Ping),
+			Err(ServerError::BackendUnavailable(unavailable)) if unavailable == address,
+		));
+
+		assert!(backend.is_ejected());
+	}
+}
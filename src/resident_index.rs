@@ -0,0 +1,138 @@
+/*
+ * Copyright (c) Kia Shakiba
+ *
+ * This source code is licensed under the GNU AGPLv3 license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::{
+	collections::HashMap,
+	hash::{DefaultHasher, Hash, Hasher},
+	sync::{Arc, Mutex, MutexGuard},
+};
+
+use paper_utils::stream::Buffer;
+
+const NUM_SHARDS: usize = 64;
+
+type Shard = HashMap<Buffer, Arc<Buffer>>;
+
+/// Tracks the objects held by the caches which sit above the far tiers, so
+/// that the objects the caches evict can be demoted. The caches evict and
+/// expire objects without notifying the server, so an object is found to
+/// be dropped once its cache no longer holds its key, which is checked
+/// through the cache's own API rather than inferred from the object. The
+/// index keeps a reference to every object so that a dropped object can
+/// still be demoted. paper-cache returns the allocation it holds, so the
+/// reference costs no memory while the cache holds the object, but the
+/// index stays correct if the cache returns a copy instead. Until it is
+/// demoted, the dropped object is the key's current object.
+pub struct ResidentIndex {
+	shards: Box<[Mutex<Shard>]>,
+}
+
+impl ResidentIndex {
+	pub fn insert(&self, key: Buffer, object: Arc<Buffer>) {
+		self.shard(&key).insert(key, object);
+	}
+
+	/// Returns the tracked object, whether or not its cache still holds it.
+	pub fn get(&self, key: &[u8]) -> Option<Arc<Buffer>> {
+		self.shard(key).get(key).cloned()
+	}
+
+	pub fn remove(&self, key: &[u8]) {
+		self.shard(key).remove(key);
+	}
+
+	pub fn clear(&self) {
+		for shard in &self.shards {
+			lock(shard).clear();
+		}
+	}
+
+	/// Returns the keys which are not held by their caches, according to
+	/// `is_held`.
+	pub fn dropped(&self, is_held: impl Fn(&Buffer) -> bool) -> Vec<Buffer> {
+		self.shards
+			.iter()
+			.flat_map(|shard| {
+				lock(shard)
+					.keys()
+					.filter(|key| !is_held(key))
+					.cloned()
+					.collect::<Vec<_>>()
+			})
+			.collect()
+	}
+
+	/// Stops tracking the key and returns its object.
+	pub fn take(&self, key: &[u8]) -> Option<Arc<Buffer>> {
+		self.shard(key).remove(key)
+	}
+
+	fn shard(&self, key: &[u8]) -> MutexGuard<'_, Shard> {
+		let mut s = DefaultHasher::new();
+		key.hash(&mut s);
+
+		lock(&self.shards[s.finish() as usize % self.shards.len()])
+	}
+}
+
+impl Default for ResidentIndex {
+	fn default() -> Self {
+		let shards = (0..NUM_SHARDS)
+			.map(|_| Mutex::default())
+			.collect();
+
+		ResidentIndex {
+			shards,
+		}
+	}
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+	mutex
+		.lock()
+		.unwrap_or_else(|err| err.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn buffer(value: &str) -> Buffer {
+		Buffer::from(value.as_bytes())
+	}
+
+	#[test]
+	fn it_finds_objects_dropped_by_their_cache() {
+		let index = ResidentIndex::default();
+
+		index.insert(buffer("held"), Arc::new(buffer("1")));
+		index.insert(buffer("dropped"), Arc::new(buffer("2")));
+
+		assert_eq!(index.dropped(|key| key == &buffer("held")), [buffer("dropped")]);
+		assert_eq!(index.take(b"dropped"), Some(Arc::new(buffer("2"))));
+
+		assert!(index.get(b"dropped").is_none());
+		assert!(index.get(b"held").is_some());
+	}
+
+	#[test]
+	fn it_does_not_depend_on_the_references_to_an_object() {
+		let index = ResidentIndex::default();
+
+		// an object whose allocation is not shared with its cache, or which
+		// is still referenced elsewhere, is found by its key alone
+		let object = Arc::new(buffer("1"));
+		index.insert(buffer("a"), object.clone());
+		index.insert(buffer("b"), Arc::new(buffer("2")));
+
+		assert!(index.dropped(|_| true).is_empty());
+		assert_eq!(index.dropped(|key| key == &buffer("b")), [buffer("a")]);
+
+		assert_eq!(index.take(b"a"), Some(object));
+		assert_eq!(index.take(b"a"), None);
+	}
+}
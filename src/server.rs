@@ -8,16 +8,22 @@
 use std::{
 	sync::{
 		Arc,
-		atomic::{AtomicUsize, Ordering},
+		Mutex,
+		MutexGuard,
+		atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
 	},
 	io::Write,
+	thread::{self, JoinHandle},
+	time::Duration,
 	str::FromStr,
-	net::{TcpListener, TcpStream, Shutdown},
+	collections::HashMap,
+	path::Path,
+	net::{TcpListener, TcpStream, Shutdown, SocketAddr, IpAddr, Ipv4Addr, Ipv6Addr},
 };
 
 use log::{info, warn, error};
 use kwik::thread_pool::ThreadPool;
-use paper_cache::{PaperCache, PaperPolicy, CacheError};
+use paper_cache::{PaperPolicy, CacheError};
 
 use paper_utils::{
 	stream::Buffer,
@@ -29,49 +35,282 @@ use crate::{
 	command::Command,
 	connection::Connection,
 	config::Config,
+	store::{Store, Cache},
+	object,
+	tier::Tiers,
+	pinned::PinnedObjects,
+	admission::Admission,
+	namespace::Namespaces,
+	expiry::{self, Expiry},
+	tier::{self, TierStatus},
+	numa::{self, MemoryPolicy, Topology},
+	memory_limit::{self, RelativeMaxSize},
+	mrc::ShadowCaches,
+	policy_history::{PolicyHistory, SwitchSource},
+	cluster::{self, Cluster, Route},
+	migration::{self, Migrator},
+	trace::{Tracer, TraceOp},
 };
 
-pub type Cache = PaperCache<Buffer, Buffer>;
 type SheetResult = Result<Sheet, ServerError>;
 
+const DEFAULT_SCAN_COUNT: u32 = 10;
+
+// how often the cache's policy is checked for switches made in auto-policy
+// mode, which the cache does not otherwise report
+const POLICY_OBSERVE_INTERVAL: Duration = Duration::from_secs(1);
+
+// how often a shutdown checks whether every connection has closed
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// how often a reload of a relative max size is checked for
+const RELOAD_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// how often the objects evicted from the cache are demoted into the far
+// tiers, which bounds how long an evicted object is still held in DRAM
+const DEMOTION_INTERVAL: Duration = Duration::from_millis(100);
+
+// how often, and how many of, the indexed keys are checked for objects the
+// cache evicted or expired, so that the index is pruned on a server which
+// receives few writes
+const INDEX_SWEEP_INTERVAL: Duration = Duration::from_millis(100);
+const INDEX_SWEEP_BATCH_SIZE: usize = 1000;
+
+/// Builds a server from a config, which is the default config unless one is
+/// supplied. The server creates its own cache and binds its own listener
+/// unless they are supplied, so that it can be embedded with an existing
+/// cache or on an ephemeral port.
+#[derive(Default)]
+pub struct ServerBuilder {
+	config: Config,
+	cache: Option<Cache>,
+	listener: Option<TcpListener>,
+	should_handle_reload_signal: bool,
+}
+
 pub struct Server {
 	listener: TcpListener,
-	cache: Arc<Cache>,
+	store: Arc<Store>,
+	tracer: Arc<Tracer>,
+	shadow_caches: Option<Arc<ShadowCaches>>,
+	policy_history: Arc<PolicyHistory>,
+	cluster: Option<Arc<Cluster>>,
+	migrator: Option<Arc<Migrator>>,
 
 	pool: ThreadPool,
 
 	max_connections: usize,
 	num_connections: Arc<AtomicUsize>,
 	auth_token: Option<u64>,
+
+	worker_cpus: Option<Arc<[usize]>>,
+	memory_nodes: Option<Arc<[u32]>>,
+	memory_policy: MemoryPolicy,
+
+	lifecycle: Arc<Lifecycle>,
+}
+
+/// A server which runs on a background thread. The server is shut down when
+/// the handle is dropped.
+pub struct ServerHandle {
+	local_addr: SocketAddr,
+	lifecycle: Arc<Lifecycle>,
+
+	thread: Option<JoinHandle<()>>,
+}
+
+/// The state which a server shares with its handle and its background
+/// threads, so that they can be stopped and its connections can be closed.
+#[derive(Default)]
+struct Lifecycle {
+	is_shutdown: AtomicBool,
+
+	streams: Mutex<HashMap<u64, TcpStream>>,
+	next_stream_id: AtomicU64,
+}
+
+impl ServerBuilder {
+	pub fn new() -> Self {
+		ServerBuilder::default()
+	}
+
+	pub fn config(mut self, config: Config) -> Self {
+		self.config = config;
+		self
+	}
+
+	/// Serves the supplied cache instead of creating one from the config.
+	pub fn cache(mut self, cache: Cache) -> Self {
+		self.cache = Some(cache);
+		self
+	}
+
+	/// Accepts connections on the supplied listener instead of binding the
+	/// config's host and port.
+	pub fn listener(mut self, listener: TcpListener) -> Self {
+		self.listener = Some(listener);
+		self
+	}
+
+	/// Resolves a relative max_size again whenever the process receives a
+	/// SIGHUP. The signal handler is global to the process, so it is only
+	/// installed when the embedding application opts in.
+	pub fn handle_reload_signal(mut self) -> Self {
+		self.should_handle_reload_signal = true;
+		self
+	}
+
+	pub fn build(self) -> Result<Server, ServerError> {
+		let config = self.config;
+
+		let cache = match self.cache {
+			Some(cache) => cache,
+			None => new_cache(&config)?,
+		};
+
+		let store = Store::new(
+			cache,
+			Namespaces::new(config.namespaces())?,
+			Tiers::new(config.tiers())?,
+			PinnedObjects::new(config.max_pinned_size()),
+			Admission::new(config.admission()),
+		);
+
+		let listener = match self.listener {
+			Some(listener) => listener,
+
+			None => TcpListener::bind(format!("{}:{}", config.host(), config.port()))
+				.map_err(|_| ServerError::InvalidAddress)?,
+		};
+
+		Server::new(&config, store, listener, self.should_handle_reload_signal)
+	}
 }
 
 impl Server {
-	pub fn new(
+	fn new(
 		config: &Config,
-		cache: Cache,
+		store: Store,
+		listener: TcpListener,
+		should_handle_reload_signal: bool,
 	) -> Result<Self, ServerError> {
-		let addr = format!("{}:{}", config.host(), config.port());
+		let worker_cpus = numa::worker_cpus(config.worker_cpus(), config.worker_nodes(), Topology::detect)?;
 
-		let Ok(listener) = TcpListener::bind(addr) else {
-			return Err(ServerError::InvalidAddress);
+		let shadow_caches = match config.mrc_sample_rate() {
+			Some(sample_rate) => Some(ShadowCaches::new(
+				sample_rate,
+				config.policies(),
+				config.max_size(),
+			)?),
+
+			None => None,
 		};
 
+		let cluster = match (config.cluster_node(), config.cluster_nodes().is_empty()) {
+			(_, true) => None,
+			(Some(node_id), false) => Some(Cluster::new(config.cluster_nodes(), Some(node_id))?),
+
+			// the nodes may only be configured without this server's ID
+			// when the server runs as a proxy
+			(None, false) => return Err(ServerError::InvalidConfigParam("cluster_node")),
+		};
+
+		let store = Arc::new(store);
+		let policy_history = Arc::new(PolicyHistory::new(store.cache())?);
+		let lifecycle = Arc::new(Lifecycle::default());
+
+		observe_policy(store.clone(), policy_history.clone(), lifecycle.clone());
+
+		if store.has_tiers() {
+			demote_evicted(store.clone(), lifecycle.clone());
+		}
+
+		sweep_index(store.clone(), lifecycle.clone());
+
+		let shadow_caches = shadow_caches.map(Arc::new);
+
+		if let Some(max_size) = config.relative_max_size() {
+			info!("Resolved max_size to {} bytes", config.max_size());
+
+			if should_handle_reload_signal {
+				memory_limit::handle_reload_signal();
+				watch_max_size(max_size, store.clone(), shadow_caches.clone(), lifecycle.clone());
+			}
+		}
+
+		let cluster = cluster.map(Arc::new);
+
+		let migrator = cluster.as_ref().map(|cluster| Arc::new(Migrator::new(
+			store.clone(),
+			cluster.clone(),
+			config.cluster_auth_token().map(String::from),
+		)));
+
 		let server = Server {
 			listener,
-			cache: Arc::new(cache),
+			store,
+			tracer: Arc::new(Tracer::new(config.trace_dir().map(Path::to_path_buf))),
+			shadow_caches,
+			policy_history,
+			cluster,
+			migrator,
 
 			pool: ThreadPool::new(config.max_connections()),
 
 			max_connections: config.max_connections(),
 			num_connections: Arc::new(AtomicUsize::new(0)),
 			auth_token: config.auth_token(),
+
+			worker_cpus: worker_cpus.map(Arc::from),
+			memory_nodes: (!config.memory_nodes().is_empty()).then(|| Arc::from(config.memory_nodes())),
+			memory_policy: config.memory_policy(),
+
+			lifecycle,
 		};
 
 		Ok(server)
 	}
 
+	/// Returns the version of the cache.
+	pub fn version(&self) -> String {
+		self.store.cache().version()
+	}
+
+	pub fn local_addr(&self) -> Result<SocketAddr, ServerError> {
+		self.listener
+			.local_addr()
+			.map_err(|_| ServerError::InvalidAddress)
+	}
+
+	/// Accepts connections until the server is shut down.
+	pub fn run(&mut self) {
+		while !self.lifecycle.is_shutdown() {
+			let _ = self.listen();
+		}
+	}
+
+	/// Runs the server on a background thread.
+	pub fn spawn(mut self) -> Result<ServerHandle, ServerError> {
+		let local_addr = self.local_addr()?;
+		let lifecycle = self.lifecycle.clone();
+
+		let thread = thread::spawn(move || self.run());
+
+		Ok(ServerHandle {
+			local_addr,
+			lifecycle,
+
+			thread: Some(thread),
+		})
+	}
+
 	pub fn listen(&mut self) -> Result<(), ServerError> {
 		for stream in self.listener.incoming() {
+			// a shutdown wakes the listener with a connection of its own
+			if self.lifecycle.is_shutdown() {
+				return Ok(());
+			}
+
 			match stream {
 				Ok(mut stream) => {
 					if self.num_connections.load(Ordering::Relaxed) == self.max_connections {
@@ -92,16 +331,51 @@ impl Server {
 
 					success_handshake(&mut stream)?;
 
-					let connection = Connection::new(stream, self.auth_token);
-					let cache = self.cache.clone();
+					let stream_id = self.lifecycle.register(&stream);
+
+					let connection = Connection::new(stream, address.clone(), self.auth_token);
+					let lifecycle = self.lifecycle.clone();
+					let store = self.store.clone();
+					let tracer = self.tracer.clone();
+					let shadow_caches = self.shadow_caches.clone();
+					let policy_history = self.policy_history.clone();
+					let cluster = self.cluster.clone();
+					let migrator = self.migrator.clone();
 					let num_connections = Arc::clone(&self.num_connections);
+					let worker_cpus = self.worker_cpus.clone();
+					let memory_nodes = self.memory_nodes.clone();
+					let memory_policy = self.memory_policy;
 
 					self.pool.execute(move || {
+						if let Some(cpus) = worker_cpus
+							&& let Err(err) = numa::pin_current_thread(&cpus)
+						{
+							warn!("Could not pin worker thread: {err}");
+						}
+
+						// the objects which the worker writes are allocated
+						// by the worker's thread
+						if let Some(nodes) = memory_nodes
+							&& let Err(err) = numa::bind_current_thread(&nodes, memory_policy)
+						{
+							warn!("Could not bind worker thread memory: {err}");
+						}
+
 						num_connections.fetch_add(1, Ordering::Relaxed);
-						Server::handle_connection(connection, cache);
+						Server::handle_connection(
+							connection,
+							store,
+							tracer,
+							shadow_caches,
+							policy_history,
+							cluster,
+							migrator,
+						);
 
 						info!("Disconnected: {address}");
 						num_connections.fetch_sub(1, Ordering::Relaxed);
+
+						lifecycle.deregister(stream_id);
 					});
 				},
 
@@ -112,7 +386,15 @@ impl Server {
 		Ok(())
 	}
 
-	fn handle_connection(mut connection: Connection, cache: Arc<Cache>) {
+	fn handle_connection(
+		mut connection: Connection,
+		store: Arc<Store>,
+		tracer: Arc<Tracer>,
+		shadow_caches: Option<Arc<ShadowCaches>>,
+		policy_history: Arc<PolicyHistory>,
+		cluster: Option<Arc<Cluster>>,
+		migrator: Option<Arc<Migrator>>,
+	) {
 		loop {
 			let command = match connection.get_command() {
 				Ok(command) => command,
@@ -124,31 +406,98 @@ impl Server {
 				},
 			};
 
+			// a key which is owned by another node is redirected before the
+			// command is traced or handled
+			if connection.is_authorized()
+				&& let Some(cluster) = &cluster
+				&& let Err(err) = redirect(cluster, &store, &command)
+			{
+				if connection.send_response(err.to_sheet().serialize()).is_err() {
+					error!("Could not send response to command");
+				}
+
+				continue;
+			}
+
+			let traced_accesses = match connection.is_authorized() && tracer.is_active() {
+				true => sample_accesses(&command, |key| tracer.sample(key)),
+				false => Vec::new(),
+			};
+
+			let shadow_accesses = match (connection.is_authorized(), &shadow_caches) {
+				(true, Some(shadow_caches)) => sample_accesses(&command, |key| shadow_caches.sample(key)),
+				_ => Vec::new(),
+			};
+
 			let sheet_result = match (connection.is_authorized(), command) {
 				(_, Command::Ping) => handle_ping(),
-				(_, Command::Version) => handle_version(&cache),
+				(_, Command::Version) => handle_version(&store),
 
 				(_, Command::Auth(token)) => handle_auth(&mut connection, &token),
 
-				(true, Command::Get(key)) => handle_get(&cache, key),
-				(true, Command::Set(key, value, ttl)) => handle_set(&cache, key, value, ttl),
-				(true, Command::Del(key)) => handle_del(&cache, key),
+				(true, Command::Get(key)) => handle_get(&store, key),
+				(true, Command::Set(key, value, expiry, tags)) => handle_set(&store, key, value, expiry, tags),
+				(true, Command::Del(key)) => handle_del(&store, key),
+
+				(true, Command::MGet(keys)) => handle_mget(&store, keys),
+
+				(true, Command::Gets(key)) => handle_gets(&store, key),
+				(true, Command::Cas(key, value, version, expiry)) => handle_cas(&store, key, value, version, expiry),
+
+				(true, Command::SetNx(key, value, expiry)) => handle_set_nx(&store, key, value, expiry),
+				(true, Command::SetXx(key, value, expiry)) => handle_set_xx(&store, key, value, expiry),
+				(true, Command::GetSet(key, value, expiry)) => handle_get_set(&store, key, value, expiry),
+
+				(true, Command::Has(key)) => handle_has(&store, key),
+				(true, Command::Peek(key)) => handle_peek(&store, key),
+				(true, Command::Ttl(key, expiry)) => handle_ttl(&store, key, expiry),
+				(true, Command::Pttl(key)) => handle_pttl(&store, key),
+				(true, Command::Size(key)) => handle_size(&store, key),
+
+				(true, Command::Scan(cursor, prefix, count)) => handle_scan(&store, cursor, prefix, count),
+				(true, Command::DelPrefix(prefix)) => handle_del_prefix(&store, prefix),
 
-				(true, Command::Has(key)) => handle_has(&cache, key),
-				(true, Command::Peek(key)) => handle_peek(&cache, key),
-				(true, Command::Ttl(key, ttl)) => handle_ttl(&cache, key, ttl),
-				(true, Command::Size(key)) => handle_size(&cache, key),
+				(true, Command::Invalidate(tag)) => handle_invalidate(&store, tag),
 
-				(true, Command::Wipe) => handle_wipe(&cache),
+				(true, Command::Pin(key)) => handle_pin(&store, key),
+				(true, Command::Unpin(key)) => handle_unpin(&store, key),
 
-				(true, Command::Resize(size)) => handle_resize(&cache, size),
-				(true, Command::Policy(policy_str)) => handle_policy(&cache, policy_str),
+				(true, Command::Wipe) => handle_wipe(&store, shadow_caches.as_deref()),
 
-				(true, Command::Status) => handle_status(&cache),
+				(true, Command::Resize(size)) => handle_resize(&store, shadow_caches.as_deref(), size),
+				(true, Command::Policy(policy_str)) => handle_policy(&store, &policy_history, &connection, policy_str),
+				(true, Command::PolicyHistory) => handle_policy_history(&policy_history),
+
+				(true, Command::Status) => handle_status(&store),
+
+				(true, Command::TraceStart(name, sample_rate)) => handle_trace_start(&tracer, &name, sample_rate),
+				(true, Command::TraceStop) => handle_trace_stop(&tracer),
+
+				(true, Command::Mrc) => handle_mrc(shadow_caches.as_deref()),
+
+				(true, Command::ClusterNodes) => handle_cluster_nodes(cluster.as_deref()),
+				(true, Command::ClusterMigrate(start, end, node_id)) => handle_cluster_migrate(migrator.as_ref(), start, end, &node_id),
+				(true, Command::ClusterImport(start, end, node_id)) => handle_cluster_import(cluster.as_deref(), start, end, &node_id),
+				(true, Command::ClusterCancelImport(start, end, node_id)) => handle_cluster_cancel_import(&store, cluster.as_deref(), start, end, &node_id),
+				(true, Command::ClusterSetSlots(start, end, node_id)) => handle_cluster_set_slots(cluster.as_deref(), start, end, &node_id),
+
+				(true, Command::Restore(key, value, expiry, tags)) => handle_restore(&store, key, value, expiry, tags),
 
 				_ => Err(ServerError::Unauthorized),
 			};
 
+			for access in traced_accesses {
+				let value_size = access_value_size(&store, &access);
+				tracer.record(access.key_hash, value_size, access.op);
+			}
+
+			if let Some(shadow_caches) = &shadow_caches {
+				for access in shadow_accesses {
+					let value_size = access_value_size(&store, &access);
+					shadow_caches.record(access.key_hash, value_size, access.op);
+				}
+			}
+
 			let sheet = sheet_result.unwrap_or_else(|err| err.to_sheet());
 
 			if (connection.send_response(sheet.serialize())).is_err() {
@@ -158,6 +507,272 @@ impl Server {
 	}
 }
 
+/// Returns the redirect for a command whose key is not served by this node.
+/// While the key's slot migrates away from this node, a read is served if
+/// this node still holds the object, while a write removes the object and
+/// is redirected to the target, which holds the newest object from then on.
+/// An MGET is redirected if any of its keys is.
+fn redirect(cluster: &Cluster, store: &Store, command: &Command) -> Result<(), ServerError> {
+	if let Command::MGet(keys) = command {
+		return keys
+			.iter()
+			.try_for_each(|key| redirect_key(cluster, store, key, true));
+	}
+
+	match command.key() {
+		Some(key) => redirect_key(cluster, store, key, command.is_read()),
+		None => Ok(()),
+	}
+}
+
+fn redirect_key(
+	cluster: &Cluster,
+	store: &Store,
+	key: &Buffer,
+	is_read: bool,
+) -> Result<(), ServerError> {
+	match cluster.route(key) {
+		Route::Local => Ok(()),
+		Route::Moved(slot, address) => Err(ServerError::Moved(slot, address)),
+
+		Route::Migrating(slot, address) => {
+			if is_read && store.has(key)? {
+				return Ok(());
+			}
+
+			match store.del(key) {
+				Ok(_) | Err(ServerError::CacheError(CacheError::KeyNotFound)) => {},
+				Err(err) => return Err(err),
+			}
+
+			Err(ServerError::Moved(slot, address))
+		},
+	}
+}
+
+struct SampledAccess {
+	key: Buffer,
+	key_hash: u64,
+	op: TraceOp,
+	value_size: u32,
+}
+
+/// Returns the accesses to record if the command is recorded, keeping the
+/// accesses whose keys are sampled by the supplied sampler, which returns
+/// the key's hash. An MGET is recorded as a GET of each of its keys.
+fn sample_accesses(
+	command: &Command,
+	sample: impl Fn(&[u8]) -> Option<u64>,
+) -> Vec<SampledAccess> {
+	let accesses = match command {
+		Command::Get(key) => vec![(key, TraceOp::Get, 0)],
+		Command::MGet(keys) => keys.iter().map(|key| (key, TraceOp::Get, 0)).collect(),
+		Command::Set(key, value, _, _) => vec![(key, TraceOp::Set, value.len() as u32)],
+		Command::Del(key) => vec![(key, TraceOp::Del, 0)],
+		Command::Has(key) => vec![(key, TraceOp::Has, 0)],
+		Command::Peek(key) => vec![(key, TraceOp::Peek, 0)],
+
+		_ => return Vec::new(),
+	};
+
+	accesses
+		.into_iter()
+		.filter_map(|(key, op, value_size)| Some(SampledAccess {
+			key: key.clone(),
+			key_hash: sample(key)?,
+			op,
+			value_size,
+		}))
+		.collect()
+}
+
+fn access_value_size(store: &Store, access: &SampledAccess) -> u32 {
+	match access.op {
+		// the size of a read object is only known once it has been read, and
+		// is recorded as zero if the object was not found
+		TraceOp::Get | TraceOp::Peek => store
+			.inspect(&access.key, |object| object.value().len() as u32)
+			.unwrap_or(0),
+
+		_ => access.value_size,
+	}
+}
+
+impl ServerHandle {
+	pub fn local_addr(&self) -> SocketAddr {
+		self.local_addr
+	}
+
+	/// Stops accepting connections, closes every open connection and waits
+	/// for their commands to finish.
+	pub fn shutdown(mut self) {
+		self.stop();
+	}
+
+	fn stop(&mut self) {
+		let Some(thread) = self.thread.take() else {
+			return;
+		};
+
+		self.lifecycle.is_shutdown.store(true, Ordering::Relaxed);
+
+		// the listener is blocked until it accepts a connection
+		let _ = TcpStream::connect(wake_addr(self.local_addr));
+		let _ = thread.join();
+
+		self.lifecycle.close_streams();
+
+		while !self.lifecycle.lock_streams().is_empty() {
+			thread::sleep(SHUTDOWN_POLL_INTERVAL);
+		}
+	}
+}
+
+impl Drop for ServerHandle {
+	fn drop(&mut self) {
+		self.stop();
+	}
+}
+
+impl Lifecycle {
+	fn is_shutdown(&self) -> bool {
+		self.is_shutdown.load(Ordering::Relaxed)
+	}
+
+	/// Tracks a connection's stream so that it can be closed on shutdown.
+	fn register(&self, stream: &TcpStream) -> u64 {
+		let stream_id = self.next_stream_id.fetch_add(1, Ordering::Relaxed);
+
+		if let Ok(stream) = stream.try_clone() {
+			self.lock_streams().insert(stream_id, stream);
+		}
+
+		stream_id
+	}
+
+	fn deregister(&self, stream_id: u64) {
+		self.lock_streams().remove(&stream_id);
+	}
+
+	/// Closes every tracked stream, which disconnects its connection once
+	/// the connection's current command is handled.
+	fn close_streams(&self) {
+		for stream in self.lock_streams().values() {
+			let _ = stream.shutdown(Shutdown::Both);
+		}
+	}
+
+	fn lock_streams(&self) -> MutexGuard<'_, HashMap<u64, TcpStream>> {
+		self.streams
+			.lock()
+			.unwrap_or_else(|err| err.into_inner())
+	}
+}
+
+/// Returns the address with which a listener bound to `addr` can be
+/// reached, since a listener bound to an unspecified address cannot be
+/// connected to directly.
+fn wake_addr(addr: SocketAddr) -> SocketAddr {
+	match addr.ip() {
+		IpAddr::V4(ip) if ip.is_unspecified() => SocketAddr::new(Ipv4Addr::LOCALHOST.into(), addr.port()),
+		IpAddr::V6(ip) if ip.is_unspecified() => SocketAddr::new(Ipv6Addr::LOCALHOST.into(), addr.port()),
+		_ => addr,
+	}
+}
+
+/// Periodically records the policy switches made by the cache in auto-policy
+/// mode on a background thread.
+fn observe_policy(
+	store: Arc<Store>,
+	policy_history: Arc<PolicyHistory>,
+	lifecycle: Arc<Lifecycle>,
+) {
+	thread::spawn(move || {
+		while !lifecycle.is_shutdown() {
+			thread::sleep(POLICY_OBSERVE_INTERVAL);
+
+			if let Err(err) = policy_history.observe(store.cache()) {
+				warn!("Could not observe policy: {err}");
+			}
+		}
+	});
+}
+
+/// Creates the cache from the config. If memory nodes are configured, the
+/// cache is created on a thread which is bound to them, since the memory
+/// policy only applies to the thread which sets it and must not be left on
+/// the thread which builds the server.
+fn new_cache(config: &Config) -> Result<Cache, ServerError> {
+	let create = || Cache::new(config.max_size(), config.policies(), config.policy());
+
+	if config.memory_nodes().is_empty() {
+		return Ok(create()?);
+	}
+
+	let topology = Topology::detect()
+		.map_err(|_| ServerError::InvalidPlacement("could not read the NUMA topology".into()))?;
+
+	thread::scope(|scope| {
+		let thread = scope.spawn(|| {
+			numa::bind_memory(config.memory_nodes(), config.memory_policy(), &topology)?;
+			Ok(create()?)
+		});
+
+		thread
+			.join()
+			.unwrap_or_else(|err| std::panic::resume_unwind(err))
+	})
+}
+
+/// Prunes the keys of the objects the cache evicted or expired from the
+/// store's indexes on a background thread, a bounded batch at a time.
+fn sweep_index(store: Arc<Store>, lifecycle: Arc<Lifecycle>) {
+	thread::spawn(move || {
+		while !lifecycle.is_shutdown() {
+			thread::sleep(INDEX_SWEEP_INTERVAL);
+			store.sweep(INDEX_SWEEP_BATCH_SIZE);
+		}
+	});
+}
+
+fn demote_evicted(store: Arc<Store>, lifecycle: Arc<Lifecycle>) {
+	thread::spawn(move || {
+		while !lifecycle.is_shutdown() {
+			thread::sleep(DEMOTION_INTERVAL);
+			store.demote_evicted();
+		}
+	});
+}
+
+/// Resolves a relative max size again whenever a reload is requested with a
+/// SIGHUP (e.g., after the memory limit of the server's cgroup is changed),
+/// and resizes the cache to the resolved size.
+fn watch_max_size(
+	max_size: RelativeMaxSize,
+	store: Arc<Store>,
+	shadow_caches: Option<Arc<ShadowCaches>>,
+	lifecycle: Arc<Lifecycle>,
+) {
+	thread::spawn(move || {
+		while !lifecycle.is_shutdown() {
+			thread::sleep(RELOAD_POLL_INTERVAL);
+
+			if !memory_limit::take_reload_request() {
+				continue;
+			}
+
+			let result = max_size
+				.resolve()
+				.and_then(|size| handle_resize(&store, shadow_caches.as_deref(), size).map(|_| size));
+
+			match result {
+				Ok(size) => info!("Resized the cache to {size} bytes"),
+				Err(err) => warn!("Could not reload max_size: {err}"),
+			}
+		}
+	});
+}
+
 fn success_handshake(stream: &mut TcpStream) -> Result<(), ServerError> {
 	let sheet = SheetBuilder::new()
 		.write_bool(true)
@@ -185,10 +800,10 @@ fn handle_ping() -> SheetResult {
 	Ok(sheet)
 }
 
-fn handle_version(cache: &Arc<Cache>) -> SheetResult {
+fn handle_version(store: &Arc<Store>) -> SheetResult {
 	let sheet = SheetBuilder::new()
 		.write_bool(true)
-		.write_str(cache.version())
+		.write_str(store.cache().version())
 		.into_sheet();
 
 	Ok(sheet)
@@ -209,141 +824,577 @@ fn handle_auth(connection: &mut Connection, token: &Buffer) -> SheetResult {
 	Ok(sheet)
 }
 
-fn handle_get(cache: &Arc<Cache>, key: Buffer) -> SheetResult {
-	cache
-		.get(&key)
-		.map(|object|
-			SheetBuilder::new()
+fn handle_get(store: &Arc<Store>, key: Buffer) -> SheetResult {
+	store.get(&key, |object|
+		SheetBuilder::new()
+			.write_bool(true)
+			.write_buf(object.value())
+			.into_sheet()
+	)
+}
+
+fn handle_mget(store: &Arc<Store>, keys: Vec<Buffer>) -> SheetResult {
+	let mut sheet_builder = SheetBuilder::new()
+		.write_bool(true)
+		.write_u32(keys.len() as u32);
+
+	for key in keys {
+		sheet_builder = match store.get(&key, |object| Buffer::from(object.value())) {
+			Ok(value) => sheet_builder
 				.write_bool(true)
-				.write_buf(&object)
-				.into_sheet(),
-		)
-		.map_err(ServerError::CacheError)
+				.write_buf(&value),
+
+			Err(ServerError::CacheError(CacheError::KeyNotFound)) => sheet_builder.write_bool(false),
+			Err(err) => return Err(err),
+		};
+	}
+
+	Ok(sheet_builder.into_sheet())
 }
 
 fn handle_set(
-	cache: &Arc<Cache>,
+	store: &Arc<Store>,
 	key: Buffer,
 	value: Buffer,
-	ttl: Option<u32>,
+	expiry: Expiry,
+	tags: Vec<Buffer>,
 ) -> SheetResult {
-	cache
-		.set(key, value, ttl)
+	store
+		.set(key, &value, expiry, &tags)
 		.map(|_|
 			SheetBuilder::new()
 				.write_bool(true)
 				.into_sheet()
 		)
-		.map_err(ServerError::CacheError)
 }
 
-fn handle_del(cache: &Arc<Cache>, key: Buffer) -> SheetResult {
-	cache
+fn handle_del(store: &Arc<Store>, key: Buffer) -> SheetResult {
+	store
 		.del(&key)
 		.map(|_|
 			SheetBuilder::new()
 				.write_bool(true)
 				.into_sheet()
 		)
-		.map_err(ServerError::CacheError)
 }
 
-fn handle_has(cache: &Arc<Cache>, key: Buffer) -> SheetResult {
+fn handle_gets(store: &Arc<Store>, key: Buffer) -> SheetResult {
+	store.get(&key, |object|
+		SheetBuilder::new()
+			.write_bool(true)
+			.write_buf(object.value())
+			.write_u64(object.version())
+			.into_sheet()
+	)
+}
+
+fn handle_cas(
+	store: &Arc<Store>,
+	key: Buffer,
+	value: Buffer,
+	version: u64,
+	expiry: Expiry,
+) -> SheetResult {
+	store
+		.cas(key, &value, version, expiry)
+		.map(|_|
+			SheetBuilder::new()
+				.write_bool(true)
+				.into_sheet()
+		)
+}
+
+fn handle_set_nx(
+	store: &Arc<Store>,
+	key: Buffer,
+	value: Buffer,
+	expiry: Expiry,
+) -> SheetResult {
+	store
+		.set_nx(key, &value, expiry)
+		.map(|is_set|
+			SheetBuilder::new()
+				.write_bool(true)
+				.write_bool(is_set)
+				.into_sheet()
+		)
+}
+
+fn handle_set_xx(
+	store: &Arc<Store>,
+	key: Buffer,
+	value: Buffer,
+	expiry: Expiry,
+) -> SheetResult {
+	store
+		.set_xx(key, &value, expiry)
+		.map(|is_set|
+			SheetBuilder::new()
+				.write_bool(true)
+				.write_bool(is_set)
+				.into_sheet()
+		)
+}
+
+fn handle_get_set(
+	store: &Arc<Store>,
+	key: Buffer,
+	value: Buffer,
+	expiry: Expiry,
+) -> SheetResult {
+	let old_value = store.get_set(key, &value, expiry)?;
+
+	let sheet_builder = SheetBuilder::new()
+		.write_bool(true)
+		.write_bool(old_value.is_some());
+
+	let sheet = match old_value {
+		Some(old_value) => sheet_builder.write_buf(&old_value).into_sheet(),
+		None => sheet_builder.into_sheet(),
+	};
+
+	Ok(sheet)
+}
+
+fn handle_has(store: &Arc<Store>, key: Buffer) -> SheetResult {
 	let sheet = SheetBuilder::new()
 		.write_bool(true)
-		.write_bool(cache.has(&key))
+		.write_bool(store.has(&key)?)
 		.into_sheet();
 
 	Ok(sheet)
 }
 
-fn handle_peek(cache: &Arc<Cache>, key: Buffer) -> SheetResult {
-	cache
-		.peek(&key)
-		.map(|object|
+fn handle_peek(store: &Arc<Store>, key: Buffer) -> SheetResult {
+	store.peek(&key, |object|
+		SheetBuilder::new()
+			.write_bool(true)
+			.write_buf(object.value())
+			.into_sheet()
+	)
+}
+
+fn handle_ttl(store: &Arc<Store>, key: Buffer, expiry: Expiry) -> SheetResult {
+	store
+		.expire(key, expiry)
+		.map(|_|
 			SheetBuilder::new()
 				.write_bool(true)
-				.write_buf(&object)
 				.into_sheet()
 		)
-		.map_err(ServerError::CacheError)
 }
 
-fn handle_ttl(cache: &Arc<Cache>, key: Buffer, ttl: Option<u32>) -> SheetResult {
-	cache
-		.ttl(&key, ttl)
-		.map(|_|
+fn handle_pttl(store: &Arc<Store>, key: Buffer) -> SheetResult {
+	let remaining = store.inspect(&key, |object|
+		object
+			.expires_at()
+			.map(|expires_at| expires_at.saturating_sub(expiry::now_millis()))
+	)?;
+
+	let sheet_builder = SheetBuilder::new()
+		.write_bool(true)
+		.write_bool(remaining.is_some());
+
+	let sheet = match remaining {
+		Some(remaining) => sheet_builder.write_u64(remaining).into_sheet(),
+		None => sheet_builder.into_sheet(),
+	};
+
+	Ok(sheet)
+}
+
+fn handle_size(store: &Arc<Store>, key: Buffer) -> SheetResult {
+	store.inspect(&key, |object|
+		SheetBuilder::new()
+			.write_bool(true)
+			.write_u32(object.value().len() as u32)
+			.into_sheet()
+	)
+}
+
+fn handle_scan(
+	store: &Arc<Store>,
+	cursor: Buffer,
+	prefix: Buffer,
+	count: u32,
+) -> SheetResult {
+	let count = match count {
+		0 => DEFAULT_SCAN_COUNT,
+		count => count,
+	};
+
+	let (keys, next_cursor) = store.scan(&cursor, &prefix, count as usize)?;
+
+	let mut sheet_builder = SheetBuilder::new()
+		.write_bool(true)
+		.write_bool(next_cursor.is_none())
+		.write_buf(next_cursor.as_deref().unwrap_or_default())
+		.write_u32(keys.len() as u32);
+
+	for key in &keys {
+		sheet_builder = sheet_builder.write_buf(key);
+	}
+
+	Ok(sheet_builder.into_sheet())
+}
+
+fn handle_del_prefix(store: &Arc<Store>, prefix: Buffer) -> SheetResult {
+	store
+		.del_prefix(&prefix)
+		.map(|num_deleted|
 			SheetBuilder::new()
 				.write_bool(true)
+				.write_u64(num_deleted)
 				.into_sheet()
 		)
-		.map_err(ServerError::CacheError)
 }
 
-fn handle_size(cache: &Arc<Cache>, key: Buffer) -> SheetResult {
-	cache
-		.size(&key)
-		.map(|size|
+fn handle_invalidate(store: &Arc<Store>, tag: Buffer) -> SheetResult {
+	store
+		.invalidate(&tag)
+		.map(|num_deleted|
 			SheetBuilder::new()
 				.write_bool(true)
-				.write_u32(size)
+				.write_u64(num_deleted)
 				.into_sheet()
 		)
-		.map_err(ServerError::CacheError)
 }
 
-fn handle_wipe(cache: &Arc<Cache>) -> SheetResult {
-	cache
-		.wipe()
+fn handle_pin(store: &Arc<Store>, key: Buffer) -> SheetResult {
+	store
+		.pin(&key)
 		.map(|_|
 			SheetBuilder::new()
 				.write_bool(true)
 				.into_sheet()
 		)
-		.map_err(ServerError::CacheError)
 }
 
-fn handle_resize(cache: &Arc<Cache>, size: u64) -> SheetResult {
-	cache
-		.resize(size)
+fn handle_unpin(store: &Arc<Store>, key: Buffer) -> SheetResult {
+	store
+		.unpin(&key)
 		.map(|_|
 			SheetBuilder::new()
 				.write_bool(true)
 				.into_sheet()
 		)
-		.map_err(ServerError::CacheError)
 }
 
-fn handle_policy(cache: &Arc<Cache>, policy_str: String) -> SheetResult {
+fn handle_wipe(store: &Arc<Store>, shadow_caches: Option<&ShadowCaches>) -> SheetResult {
+	store.wipe()?;
+
+	if let Some(shadow_caches) = shadow_caches {
+		shadow_caches.wipe();
+	}
+
+	let sheet = SheetBuilder::new()
+		.write_bool(true)
+		.into_sheet();
+
+	Ok(sheet)
+}
+
+/// Resizes the default cache. Each namespace keeps the size quota it was
+/// configured with, which is also reserved out of a relative max_size, so
+/// resizing the default cache cannot take memory from the namespaces.
+fn handle_resize(
+	store: &Arc<Store>,
+	shadow_caches: Option<&ShadowCaches>,
+	size: u64,
+) -> SheetResult {
+	store
+		.cache()
+		.resize(size)
+		.map_err(ServerError::CacheError)?;
+
+	if let Some(shadow_caches) = shadow_caches {
+		shadow_caches.resize(size)?;
+	}
+
+	let sheet = SheetBuilder::new()
+		.write_bool(true)
+		.into_sheet();
+
+	Ok(sheet)
+}
+
+/// Switches the policy of the default cache. A namespace is configured with
+/// the policy which suits its own workload, which is the reason it is
+/// separate from the default cache, so its policy is never switched.
+fn handle_policy(
+	store: &Arc<Store>,
+	policy_history: &PolicyHistory,
+	connection: &Connection,
+	policy_str: String,
+) -> SheetResult {
 	let Ok(policy) = PaperPolicy::from_str(&policy_str) else {
 		return Err(ServerError::CacheError(
 			CacheError::InvalidPolicy
 		));
 	};
 
-	cache
-		.policy(policy)
+	policy_history
+		.switch(store.cache(), policy, connection.address())
 		.map(|_|
 			SheetBuilder::new()
 				.write_bool(true)
 				.into_sheet()
 		)
-		.map_err(ServerError::CacheError)
 }
 
-fn handle_status(cache: &Arc<Cache>) -> SheetResult {
-	let status = cache.status().map_err(ServerError::CacheError)?;
+fn handle_policy_history(policy_history: &PolicyHistory) -> SheetResult {
+	let switches = policy_history.switches();
+
+	let mut sheet_builder = SheetBuilder::new()
+		.write_bool(true)
+		.write_u32(switches.len() as u32);
+
+	for switch in switches {
+		let (is_auto, issuer) = match switch.source {
+			SwitchSource::Auto => (true, String::new()),
+			SwitchSource::Manual(address) => (false, address),
+		};
+
+		sheet_builder = sheet_builder
+			.write_u64(switch.timestamp)
+			.write_str(switch.old_policy.to_string())
+			.write_str(switch.new_policy.to_string())
+			.write_f64(switch.miss_ratio)
+			.write_bool(is_auto)
+			.write_str(issuer);
+	}
+
+	Ok(sheet_builder.into_sheet())
+}
+
+fn handle_trace_start(tracer: &Tracer, name: &str, sample_rate: f64) -> SheetResult {
+	if !tracer.is_enabled() {
+		return Err(ServerError::TraceDisabled);
+	}
+
+	let path = tracer
+		.start(name, sample_rate)
+		.map_err(|err| ServerError::InvalidTrace(format!("{name}: {err}")))?;
+
+	info!("Started trace: {}", path.display());
+
+	let sheet = SheetBuilder::new()
+		.write_bool(true)
+		.into_sheet();
+
+	Ok(sheet)
+}
+
+fn handle_trace_stop(tracer: &Tracer) -> SheetResult {
+	tracer
+		.stop()
+		.map_err(|err| ServerError::InvalidTrace(err.to_string()))?;
+
+	info!("Stopped trace");
+
+	let sheet = SheetBuilder::new()
+		.write_bool(true)
+		.into_sheet();
+
+	Ok(sheet)
+}
+
+fn handle_mrc(shadow_caches: Option<&ShadowCaches>) -> SheetResult {
+	let Some(shadow_caches) = shadow_caches else {
+		return Err(ServerError::MrcDisabled);
+	};
+
+	let curves = shadow_caches.curves();
+
+	let mut sheet_builder = SheetBuilder::new()
+		.write_bool(true)
+		.write_f64(shadow_caches.sample_rate())
+		.write_u32(curves.len() as u32);
+
+	for point in &curves {
+		sheet_builder = sheet_builder
+			.write_str(point.policy.to_string())
+			.write_u64(point.size)
+			.write_u64(point.gets)
+			.write_u64(point.misses)
+			.write_f64(point.miss_ratio());
+	}
+
+	Ok(sheet_builder.into_sheet())
+}
+
+fn handle_cluster_nodes(cluster: Option<&Cluster>) -> SheetResult {
+	let Some(cluster) = cluster else {
+		return Err(ServerError::ClusterDisabled);
+	};
+
+	let mut sheet_builder = SheetBuilder::new()
+		.write_bool(true)
+		.write_u32(cluster.nodes().len() as u32);
+
+	for (index, node) in cluster.nodes().iter().enumerate() {
+		let slots = cluster.node_slots(index);
+
+		sheet_builder = sheet_builder
+			.write_str(node.id().to_owned())
+			.write_str(node.host().to_owned())
+			.write_u32(node.port())
+			.write_bool(cluster.is_self(node))
+			.write_u32(slots.len() as u32);
+
+		for range in &slots {
+			sheet_builder = sheet_builder
+				.write_u32(*range.start() as u32)
+				.write_u32(*range.end() as u32);
+		}
+	}
+
+	Ok(sheet_builder.into_sheet())
+}
+
+fn handle_cluster_migrate(
+	migrator: Option<&Arc<Migrator>>,
+	start: u32,
+	end: u32,
+	node_id: &str,
+) -> SheetResult {
+	let Some(migrator) = migrator else {
+		return Err(ServerError::ClusterDisabled);
+	};
+
+	let range = cluster::slot_range(start, end).ok_or(ServerError::InvalidSlotRange)?;
+	migrator.start(range, node_id)?;
+
+	let sheet = SheetBuilder::new()
+		.write_bool(true)
+		.into_sheet();
+
+	Ok(sheet)
+}
+
+fn handle_cluster_import(
+	cluster: Option<&Cluster>,
+	start: u32,
+	end: u32,
+	node_id: &str,
+) -> SheetResult {
+	let Some(cluster) = cluster else {
+		return Err(ServerError::ClusterDisabled);
+	};
+
+	let range = cluster::slot_range(start, end).ok_or(ServerError::InvalidSlotRange)?;
+
+	if cluster.node_index(node_id).is_none() {
+		return Err(ServerError::UnknownNode(node_id.into()));
+	}
+
+	cluster.set_importing(range, true);
+
+	let sheet = SheetBuilder::new()
+		.write_bool(true)
+		.into_sheet();
+
+	Ok(sheet)
+}
+
+/// Stops importing the slots and removes the objects which were already
+/// imported. The import cannot be cancelled once this node owns the slots.
+fn handle_cluster_cancel_import(
+	store: &Arc<Store>,
+	cluster: Option<&Cluster>,
+	start: u32,
+	end: u32,
+	node_id: &str,
+) -> SheetResult {
+	let Some(cluster) = cluster else {
+		return Err(ServerError::ClusterDisabled);
+	};
+
+	let range = cluster::slot_range(start, end).ok_or(ServerError::InvalidSlotRange)?;
+
+	if cluster.node_index(node_id).is_none() {
+		return Err(ServerError::UnknownNode(node_id.into()));
+	}
+
+	if !cluster.can_cancel_import(&range) {
+		return Err(ServerError::InvalidSlotRange);
+	}
+
+	cluster.set_importing(range.clone(), false);
+	let num_removed = migration::remove_slots(store, &range)?;
+
+	info!("Cancelled import of slots {range:?} from {node_id} ({num_removed} objects removed)");
+
+	let sheet = SheetBuilder::new()
+		.write_bool(true)
+		.into_sheet();
+
+	Ok(sheet)
+}
+
+fn handle_cluster_set_slots(
+	cluster: Option<&Cluster>,
+	start: u32,
+	end: u32,
+	node_id: &str,
+) -> SheetResult {
+	let Some(cluster) = cluster else {
+		return Err(ServerError::ClusterDisabled);
+	};
+
+	let range = cluster::slot_range(start, end).ok_or(ServerError::InvalidSlotRange)?;
+
+	let Some(owner) = cluster.node_index(node_id) else {
+		return Err(ServerError::UnknownNode(node_id.into()));
+	};
+
+	cluster.set_owner(range, owner);
+
+	let sheet = SheetBuilder::new()
+		.write_bool(true)
+		.into_sheet();
+
+	Ok(sheet)
+}
+
+fn handle_restore(
+	store: &Arc<Store>,
+	key: Buffer,
+	value: Buffer,
+	expiry: Expiry,
+	tags: Vec<Buffer>,
+) -> SheetResult {
+	let is_restored = store.restore(key, &value, expiry, &tags)?;
+
+	let sheet = SheetBuilder::new()
+		.write_bool(true)
+		.write_bool(is_restored)
+		.into_sheet();
+
+	Ok(sheet)
+}
+
+/// Returns the stats of the default cache, followed by those of the far
+/// tiers, the admission policy, the pinned objects and every namespace.
+fn handle_status(store: &Arc<Store>) -> SheetResult {
+	let status = store.cache().status().map_err(ServerError::CacheError)?;
+
+	// the object headers are server-side metadata, so they are not reported
+	// as part of the used size
+	let used_size = status
+		.used_size()
+		.saturating_sub(status.num_objects() * object::HEADER_SIZE as u64);
 
 	let mut sheet_builder = SheetBuilder::new()
 		.write_bool(true)
 		.write_u32(status.pid())
 		.write_u64(status.max_size())
-		.write_u64(status.used_size())
+		.write_u64(used_size)
 		.write_u64(status.num_objects())
 		.write_u64(status.rss())
 		.write_u64(status.hwm())
 		.write_u64(status.total_gets())
-		.write_u64(status.total_sets())
+		.write_u64(status.total_sets().saturating_sub(store.num_expiry_sets()))
 		.write_u64(status.total_dels())
 		.write_f64(status.miss_ratio())
 		.write_u32(status.policies().len() as u32);
@@ -352,11 +1403,78 @@ fn handle_status(cache: &Arc<Cache>) -> SheetResult {
 		sheet_builder = sheet_builder.write_str(policy.to_string());
 	}
 
-	let sheet = sheet_builder
+	sheet_builder = sheet_builder
 		.write_str(status.policy().to_string())
 		.write_bool(status.is_auto_policy())
-		.write_u64(status.uptime())
-		.into_sheet();
+		.write_u64(status.uptime());
 
-	Ok(sheet)
+	// the tier stats are only listed when far tiers are configured, so the
+	// status of a single-tier cache lists no tiers
+	if !store.has_tiers() {
+		sheet_builder = sheet_builder.write_u32(0);
+	} else {
+		let dram_stats = store.dram_stats();
+
+		let dram_status = TierStatus {
+			name: tier::DRAM_TIER_NAME.into(),
+
+			used_size,
+			max_size: status.max_size(),
+			num_objects: status.num_objects(),
+
+			hits: dram_stats.hits(),
+			promotions: dram_stats.promotions(),
+			demotions: dram_stats.demotions(),
+
+			avg_access_nanos: dram_stats.avg_access_nanos(),
+		};
+
+		let far_tier_status = store.far_tier_status();
+
+		sheet_builder = sheet_builder.write_u32(far_tier_status.len() as u32 + 1);
+
+		for tier_status in std::iter::once(dram_status).chain(far_tier_status) {
+			sheet_builder = sheet_builder
+				.write_str(tier_status.name)
+				.write_u64(tier_status.used_size)
+				.write_u64(tier_status.max_size)
+				.write_u64(tier_status.num_objects)
+				.write_u64(tier_status.hits)
+				.write_u64(tier_status.promotions)
+				.write_u64(tier_status.demotions)
+				.write_u64(tier_status.avg_access_nanos);
+		}
+	}
+
+	// the admission stats follow a flag which is only set when an admission
+	// policy is configured
+	sheet_builder = sheet_builder.write_bool(store.admission().is_enabled());
+
+	if store.admission().is_enabled() {
+		sheet_builder = sheet_builder
+			.write_u64(store.admission().admitted())
+			.write_u64(store.admission().rejected());
+	}
+
+	// the pinned objects are held outside of the cache, so their size is
+	// reported against their own budget
+	sheet_builder = sheet_builder
+		.write_u64(store.pinned().used_size())
+		.write_u64(store.pinned().max_size());
+
+	let namespace_status = store.namespaces().status().map_err(ServerError::CacheError)?;
+
+	sheet_builder = sheet_builder.write_u32(namespace_status.len() as u32);
+
+	for namespace_status in namespace_status {
+		sheet_builder = sheet_builder
+			.write_str(namespace_status.name)
+			.write_u64(namespace_status.used_size)
+			.write_u64(namespace_status.max_size)
+			.write_u64(namespace_status.num_objects)
+			.write_f64(namespace_status.miss_ratio)
+			.write_str(namespace_status.policy.to_string());
+	}
+
+	Ok(sheet_builder.into_sheet())
 }
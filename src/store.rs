@@ -0,0 +1,1441 @@
+/*
+ * Copyright (c) Kia Shakiba
+ *
+ * This source code is licensed under the GNU AGPLv3 license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::{
+	time::Instant,
+	hash::{DefaultHasher, Hash, Hasher},
+	sync::{
+		Mutex,
+		MutexGuard,
+		TryLockError,
+		atomic::{AtomicU64, Ordering},
+	},
+};
+
+use paper_cache::{PaperCache, CacheError};
+use paper_utils::stream::Buffer;
+
+use crate::{
+	error::ServerError,
+	object::Object,
+	expiry::{self, Expiry},
+	key_index::KeyIndex,
+	tag_index::TagIndex,
+	resident_index::ResidentIndex,
+	tier::{Tiers, TierStats, TierStatus},
+	pinned::PinnedObjects,
+	admission::Admission,
+	namespace::Namespaces,
+};
+
+pub type Cache = PaperCache<Buffer, Buffer>;
+
+const NUM_LOCKS: usize = 1024;
+
+// the number of index entries checked for staleness on every write, which
+// keeps the index from growing with keys the cache has since evicted
+const SWEEP_BATCH_SIZE: usize = 2;
+
+const DEL_PREFIX_BATCH_SIZE: usize = 1000;
+
+/// Wraps the cache with the state needed to version objects and to make
+/// read-modify-write commands atomic. Every command which writes to a key
+/// must hold that key's lock for the duration of the write. Keys which
+/// belong to a namespace are held by the namespace's cache rather than the
+/// default cache. If far tiers are configured, the objects the caches hold
+/// are tracked so that they can be demoted once they are evicted.
+pub struct Store {
+	cache: Cache,
+	namespaces: Namespaces,
+	tiers: Tiers,
+	residents: ResidentIndex,
+	dram_stats: TierStats,
+	pinned: PinnedObjects,
+	admission: Admission,
+
+	locks: Box<[Mutex<()>]>,
+	next_version: AtomicU64,
+	num_expiry_sets: AtomicU64,
+
+	keys: KeyIndex,
+	tags: TagIndex,
+}
+
+impl Store {
+	pub fn new(
+		cache: Cache,
+		namespaces: Namespaces,
+		tiers: Tiers,
+		pinned: PinnedObjects,
+		admission: Admission,
+	) -> Self {
+		let locks = (0..NUM_LOCKS)
+			.map(|_| Mutex::new(()))
+			.collect();
+
+		Store {
+			cache,
+			namespaces,
+			tiers,
+			residents: ResidentIndex::default(),
+			dram_stats: TierStats::default(),
+			pinned,
+			admission,
+
+			locks,
+			next_version: AtomicU64::new(1),
+			num_expiry_sets: AtomicU64::default(),
+
+			keys: KeyIndex::default(),
+			tags: TagIndex::default(),
+		}
+	}
+
+	pub fn cache(&self) -> &Cache {
+		&self.cache
+	}
+
+	pub fn has_tiers(&self) -> bool {
+		!self.tiers.is_empty()
+	}
+
+	/// Access counters of the cache. Unlike a far tier, its promotions
+	/// count the objects moved into the cache and its demotions count the
+	/// evicted objects moved out of it into the far tiers.
+	pub fn dram_stats(&self) -> &TierStats {
+		&self.dram_stats
+	}
+
+	pub fn far_tier_status(&self) -> Vec<TierStatus> {
+		self.tiers.status()
+	}
+
+	pub fn admission(&self) -> &Admission {
+		&self.admission
+	}
+
+	pub fn namespaces(&self) -> &Namespaces {
+		&self.namespaces
+	}
+
+	pub fn pinned(&self) -> &PinnedObjects {
+		&self.pinned
+	}
+
+	/// Returns the number of sets made on the default cache to update an
+	/// object's expiry (see `Store::expire`), which are not writes from the
+	/// client's point of view.
+	pub fn num_expiry_sets(&self) -> u64 {
+		self.num_expiry_sets.load(Ordering::Relaxed)
+	}
+
+	/// Reads the object as an access to it. If the object is only held by
+	/// a far tier, it is promoted back into the cache.
+	pub fn get<T>(
+		&self,
+		key: &Buffer,
+		f: impl FnOnce(&Object) -> T,
+	) -> Result<T, ServerError> {
+		self.admission.record(key);
+
+		if let Some(bytes) = self.pinned.get(key) {
+			return Ok(f(&Object::from_bytes(&bytes)?));
+		}
+
+		let start = Instant::now();
+		let result = self.cache_for(key).get(key);
+
+		self.dram_stats.record_access(start.elapsed(), result.is_ok());
+
+		match result {
+			Ok(object) => Ok(f(&Object::from_bytes(&object[..])?)),
+			Err(CacheError::KeyNotFound) if !self.tiers.is_empty() => self.promote(key, f),
+			Err(err) => Err(err.into()),
+		}
+	}
+
+	/// Reads the object without it counting as an access and without
+	/// promoting it out of the far tiers. The read is recorded in the tier
+	/// stats, so it is only used for a client's PEEK.
+	pub fn peek<T>(
+		&self,
+		key: &Buffer,
+		f: impl FnOnce(&Object) -> T,
+	) -> Result<T, ServerError> {
+		self.read(key, f, true)
+	}
+
+	/// Reads the object the same as `peek`, without recording the read in
+	/// the tier stats, for the checks which the server makes itself.
+	pub fn inspect<T>(
+		&self,
+		key: &Buffer,
+		f: impl FnOnce(&Object) -> T,
+	) -> Result<T, ServerError> {
+		self.read(key, f, false)
+	}
+
+	pub fn has(&self, key: &Buffer) -> Result<bool, ServerError> {
+		match self.inspect(key, |_| ()) {
+			Ok(_) => Ok(true),
+			Err(ServerError::CacheError(CacheError::KeyNotFound)) => Ok(false),
+			Err(err) => Err(err),
+		}
+	}
+
+	/// Sets the object, replacing any tags the key previously carried with
+	/// the supplied tags. Returns `NotAdmitted` if the admission policy
+	/// rejects the object.
+	pub fn set(
+		&self,
+		key: Buffer,
+		value: &[u8],
+		expiry: Expiry,
+		tags: &[Buffer],
+	) -> Result<(), ServerError> {
+		let _guard = self.lock(&key);
+
+		match self.write(key, value, expiry, tags)? {
+			true => Ok(()),
+			false => Err(ServerError::NotAdmitted),
+		}
+	}
+
+	/// Sets the object only if its current version matches the supplied
+	/// version.
+	pub fn cas(
+		&self,
+		key: Buffer,
+		value: &[u8],
+		version: u64,
+		expiry: Expiry,
+	) -> Result<(), ServerError> {
+		let _guard = self.lock(&key);
+
+		if self.inspect(&key, |current| current.version())? != version {
+			return Err(ServerError::VersionMismatch);
+		}
+
+		// the key exists, so the object is always admitted
+		self.write(key, value, expiry, &[]).map(|_| ())
+	}
+
+	/// Sets the object only if the key is not already in the cache. Returns
+	/// whether the object was set, which is false if the admission policy
+	/// rejects the object.
+	pub fn set_nx(
+		&self,
+		key: Buffer,
+		value: &[u8],
+		expiry: Expiry,
+	) -> Result<bool, ServerError> {
+		let _guard = self.lock(&key);
+
+		if self.has(&key)? {
+			return Ok(false);
+		}
+
+		self.write(key, value, expiry, &[])
+	}
+
+	/// Sets the object only if the key is already in the cache. Returns
+	/// whether the object was set.
+	pub fn set_xx(
+		&self,
+		key: Buffer,
+		value: &[u8],
+		expiry: Expiry,
+	) -> Result<bool, ServerError> {
+		let _guard = self.lock(&key);
+
+		if !self.has(&key)? {
+			return Ok(false);
+		}
+
+		self.write(key, value, expiry, &[])
+	}
+
+	/// Sets the object and returns the value it replaced, if any. Returns
+	/// `NotAdmitted` if the admission policy rejects the object.
+	pub fn get_set(
+		&self,
+		key: Buffer,
+		value: &[u8],
+		expiry: Expiry,
+	) -> Result<Option<Buffer>, ServerError> {
+		let _guard = self.lock(&key);
+
+		let old_value = match self.inspect(&key, |current| Buffer::from(current.value())) {
+			Ok(old_value) => Some(old_value),
+			Err(ServerError::CacheError(CacheError::KeyNotFound)) => None,
+			Err(err) => return Err(err),
+		};
+
+		match self.write(key, value, expiry, &[])? {
+			true => Ok(old_value),
+			false => Err(ServerError::NotAdmitted),
+		}
+	}
+
+	/// Updates the expiry of an object without changing its value or its
+	/// version. The expiry is held in the object's header, which the cache
+	/// cannot update in place, so the object is set again. That set counts
+	/// as an access to the object in the cache's eviction policy (e.g., it
+	/// moves the object to the front of an LRU queue). It is excluded from
+	/// the total sets reported by `STATUS`.
+	pub fn expire(&self, key: Buffer, expiry: Expiry) -> Result<(), ServerError> {
+		let _guard = self.lock(&key);
+
+		let (version, value) = self.inspect(&key, |current| {
+			(current.version(), Buffer::from(current.value()))
+		})?;
+
+		let expires_at = expiry.expires_at(expiry::now_millis());
+		let object = Object::new(version, expires_at, &value);
+
+		let is_default_cache_set = !self.pinned.contains(&key)
+			&& self.namespaces.find(&key).is_none()
+			&& expires_at.is_none_or(|expires_at| expires_at > expiry::now_millis());
+
+		self.put(key, &object)?;
+
+		if is_default_cache_set {
+			self.num_expiry_sets.fetch_add(1, Ordering::Relaxed);
+		}
+
+		Ok(())
+	}
+
+	pub fn del(&self, key: &Buffer) -> Result<(), ServerError> {
+		let _guard = self.lock(key);
+
+		match self.remove(key)? {
+			true => Ok(()),
+			false => Err(ServerError::CacheError(CacheError::KeyNotFound)),
+		}
+	}
+
+	/// Sets an object which was migrated from another node, unless the key
+	/// is already set (in which case the key was written on this node during
+	/// the migration, and the written object is newer). The object bypasses
+	/// admission since it was already admitted by the source node. Returns
+	/// whether the object was set.
+	pub fn restore(
+		&self,
+		key: Buffer,
+		value: &[u8],
+		expiry: Expiry,
+		tags: &[Buffer],
+	) -> Result<bool, ServerError> {
+		let _guard = self.lock(&key);
+
+		if self.has(&key)? {
+			return Ok(false);
+		}
+
+		self.insert(key, value, expiry, tags)?;
+		Ok(true)
+	}
+
+	/// Hands the object and its tags to `f`, which copies it to another node.
+	/// The key's lock is held throughout, so the object cannot be written
+	/// while it is being copied. The object is kept until the migration of
+	/// its slot completes. Returns whether the key was set.
+	pub fn export_key(
+		&self,
+		key: &Buffer,
+		f: impl FnOnce(&Object, &[Buffer]) -> Result<(), ServerError>,
+	) -> Result<bool, ServerError> {
+		let _guard = self.lock(key);
+
+		let bytes = match self.pinned.get(key) {
+			Some(bytes) => bytes,
+
+			None => match self.cache_for(key).peek(key) {
+				Ok(object) => Buffer::from(&object[..]),
+
+				Err(CacheError::KeyNotFound) => match self.far_get(key, false) {
+					Some((_, bytes)) => bytes,
+					None => return Ok(false),
+				},
+
+				Err(err) => return Err(err.into()),
+			},
+		};
+
+		f(&Object::from_bytes(&bytes)?, &self.tags.tags(key))?;
+		Ok(true)
+	}
+
+	/// Moves the object out of the cache and its far tiers so that it is
+	/// never evicted.
+	pub fn pin(&self, key: &Buffer) -> Result<(), ServerError> {
+		let _guard = self.lock(key);
+
+		if self.pinned.contains(key) {
+			return Ok(());
+		}
+
+		let bytes = match self.cache_for(key).peek(key) {
+			Ok(object) => Buffer::from(&object[..]),
+
+			Err(CacheError::KeyNotFound) => self
+				.far_get(key, false)
+				.map(|(_, bytes)| bytes)
+				.ok_or(ServerError::CacheError(CacheError::KeyNotFound))?,
+
+			Err(err) => return Err(err.into()),
+		};
+
+		// an object which has expired but was not yet removed by the cache
+		// cannot be pinned
+		Object::from_bytes(&bytes)?;
+
+		self.pinned.insert(key.clone(), bytes)?;
+
+		self.residents.remove(key);
+		self.tiers.remove(key);
+		let _ = self.cache_for(key).del(key);
+
+		Ok(())
+	}
+
+	/// Moves a pinned object back into the cache, where it is subject to
+	/// eviction again.
+	pub fn unpin(&self, key: &Buffer) -> Result<(), ServerError> {
+		let _guard = self.lock(key);
+
+		let bytes = self.pinned
+			.remove(key)
+			.ok_or(ServerError::CacheError(CacheError::KeyNotFound))?;
+
+		let object = Object::from_bytes(&bytes)?;
+		self.put(key.clone(), &object)
+	}
+
+	pub fn wipe(&self) -> Result<(), ServerError> {
+		self.cache.wipe()?;
+		self.namespaces.wipe()?;
+		self.residents.clear();
+		self.tiers.clear();
+		self.pinned.clear();
+
+		self.keys.clear();
+		self.tags.clear();
+
+		Ok(())
+	}
+
+	/// Deletes every object which carries the tag and returns the number of
+	/// deleted objects. The locks of all the tagged keys are held while the
+	/// objects are deleted, so the invalidation is atomic with respect to
+	/// other writes.
+	pub fn invalidate(&self, tag: &[u8]) -> Result<u64, ServerError> {
+		loop {
+			let mut lock_indexes = self.tags
+				.keys(tag)
+				.iter()
+				.map(|key| self.lock_index(key))
+				.collect::<Vec<_>>();
+
+			// locks are always acquired in ascending order so that two
+			// invalidations cannot deadlock
+			lock_indexes.sort_unstable();
+			lock_indexes.dedup();
+
+			let _guards = lock_indexes
+				.iter()
+				.map(|index| self.locks[*index].lock().unwrap_or_else(|err| err.into_inner()))
+				.collect::<Vec<_>>();
+
+			// the tag may have been attached to other keys before the locks
+			// were acquired, in which case the locks must be acquired again
+			let keys = self.tags.keys(tag);
+
+			let is_locked = keys
+				.iter()
+				.all(|key| lock_indexes.binary_search(&self.lock_index(key)).is_ok());
+
+			if !is_locked {
+				continue;
+			}
+
+			let mut num_deleted = 0;
+
+			for key in &keys {
+				if self.remove(key)? {
+					num_deleted += 1;
+				}
+			}
+
+			return Ok(num_deleted);
+		}
+	}
+
+	/// Returns up to `count` keys in the cache which start with `prefix`,
+	/// continuing from `cursor`, along with the cursor from which to continue
+	/// the scan (or `None` if the scan is complete). Fewer than `count` keys
+	/// may be returned if some indexed keys are no longer in the cache.
+	pub fn scan(
+		&self,
+		cursor: &[u8],
+		prefix: &[u8],
+		count: usize,
+	) -> Result<(Vec<Buffer>, Option<Buffer>), ServerError> {
+		let (batch, is_done) = self.keys.range(cursor, prefix, count);
+
+		let next_cursor = match is_done {
+			true => None,
+			false => batch.last().cloned(),
+		};
+
+		let mut keys = Vec::with_capacity(batch.len());
+
+		for key in batch {
+			if self.has(&key)? {
+				keys.push(key);
+			} else {
+				self.prune(&key, true);
+			}
+		}
+
+		Ok((keys, next_cursor))
+	}
+
+	/// Deletes every key which starts with `prefix` and returns the number of
+	/// deleted keys. Keys are deleted in batches, so keys which are set with
+	/// the prefix while the command is running may or may not be deleted.
+	pub fn del_prefix(&self, prefix: &[u8]) -> Result<u64, ServerError> {
+		let mut num_deleted = 0;
+		let mut cursor = Buffer::default();
+
+		loop {
+			let (batch, is_done) = self.keys.range(&cursor, prefix, DEL_PREFIX_BATCH_SIZE);
+
+			for key in &batch {
+				match self.del(key) {
+					Ok(_) => num_deleted += 1,
+					Err(ServerError::CacheError(CacheError::KeyNotFound)) => {},
+					Err(err) => return Err(err),
+				}
+			}
+
+			match (is_done, batch.last()) {
+				(false, Some(last)) => cursor = last.clone(),
+				_ => break,
+			}
+		}
+
+		Ok(num_deleted)
+	}
+
+	/// Demotes the objects which the caches evicted into the far tiers. The
+	/// caches do not report evictions, so the server calls this
+	/// periodically, and an object is evicted once its cache no longer holds
+	/// its key. An object which was expired by its cache is dropped instead,
+	/// along with any stale object the far tiers hold for its key.
+	pub fn demote_evicted(&self) {
+		let is_held = |key: &Buffer| self.cache_for(key).has(key);
+
+		for key in self.residents.dropped(is_held) {
+			// a key which is being written is demoted on a later call
+			let Some(_guard) = self.try_lock(&key) else {
+				continue;
+			};
+
+			// the key may have been set again before its lock was acquired
+			if is_held(&key) {
+				continue;
+			}
+
+			let Some(bytes) = self.residents.take(&key) else {
+				continue;
+			};
+
+			match Object::from_bytes(&bytes) {
+				Ok(_) => {
+					self.tiers.demote(&key, &bytes);
+					self.dram_stats.record_demotions(1);
+				},
+
+				Err(_) => { self.tiers.remove(&key); },
+			}
+		}
+	}
+
+	/// Prunes the next `count` keys of the index whose objects are no longer
+	/// held, continuing from where the last sweep stopped. Every write
+	/// sweeps a few keys, and the server sweeps a larger batch periodically
+	/// so that the index is also pruned when there are few writes.
+	pub fn sweep(&self, count: usize) {
+		for key in self.keys.next_sweep(count) {
+			if !self.has(&key).unwrap_or(true) {
+				self.prune(&key, false);
+			}
+		}
+	}
+
+	/// Writes the object if it is admitted, and returns whether it was.
+	fn write(
+		&self,
+		key: Buffer,
+		value: &[u8],
+		expiry: Expiry,
+		tags: &[Buffer],
+	) -> Result<bool, ServerError> {
+		// only new objects are subject to admission, so an object which is
+		// already in the cache is always updated
+		if self.admission.is_enabled() {
+			match self.has(&key)? {
+				true => self.admission.record(&key),
+				false if !self.admission.admit(&key) => return Ok(false),
+				false => {},
+			}
+		}
+
+		self.insert(key, value, expiry, tags)?;
+		Ok(true)
+	}
+
+	/// Writes the object with a new version, without checking admission.
+	fn insert(
+		&self,
+		key: Buffer,
+		value: &[u8],
+		expiry: Expiry,
+		tags: &[Buffer],
+	) -> Result<(), ServerError> {
+		let version = self.next_version.fetch_add(1, Ordering::Relaxed);
+		let expires_at = expiry.expires_at(expiry::now_millis());
+
+		let object = Object::new(version, expires_at, value);
+
+		self.tags.set(&key, tags);
+		self.put(key, &object)
+	}
+
+	fn put(&self, key: Buffer, object: &Object) -> Result<(), ServerError> {
+		let now = expiry::now_millis();
+
+		let ttl = match object.expires_at() {
+			Some(expires_at) if expires_at <= now => {
+				// an expiry in the past removes the object
+				return self.remove(&key).map(|_| ());
+			},
+
+			Some(expires_at) => Some(expiry::ttl_seconds(expires_at - now)),
+			None => None,
+		};
+
+		let buffer = object.to_buffer();
+
+		// a pinned object is updated in place and keeps its pin
+		if self.pinned.contains(&key) {
+			self.pinned.insert(key.clone(), buffer)?;
+			self.keys.insert(&key);
+
+			return Ok(());
+		}
+
+		self.keys.insert(&key);
+
+		match self.tiers.is_empty() {
+			true => self.cache_for(&key).set(key, buffer, ttl)?,
+
+			false => {
+				self.cache_for(&key).set(key.clone(), buffer, ttl)?;
+				self.track(&key);
+			},
+		}
+
+		self.sweep(SWEEP_BATCH_SIZE);
+
+		Ok(())
+	}
+
+	/// Reads the object from the pinned objects, the cache or the far tiers,
+	/// recording the read in the tier stats if `should_record` is set.
+	fn read<T>(
+		&self,
+		key: &Buffer,
+		f: impl FnOnce(&Object) -> T,
+		should_record: bool,
+	) -> Result<T, ServerError> {
+		if let Some(bytes) = self.pinned.get(key) {
+			return Ok(f(&Object::from_bytes(&bytes)?));
+		}
+
+		let start = Instant::now();
+		let result = self.cache_for(key).peek(key);
+
+		if should_record {
+			self.dram_stats.record_access(start.elapsed(), result.is_ok());
+		}
+
+		match result {
+			Ok(object) => Ok(f(&Object::from_bytes(&object[..])?)),
+
+			Err(CacheError::KeyNotFound) => {
+				let (_, bytes) = self
+					.far_get(key, should_record)
+					.ok_or(ServerError::CacheError(CacheError::KeyNotFound))?;
+
+				Ok(f(&Object::from_bytes(&bytes)?))
+			},
+
+			Err(err) => Err(err.into()),
+		}
+	}
+
+	/// Moves an object which the cache no longer holds back into the cache.
+	fn promote<T>(
+		&self,
+		key: &Buffer,
+		f: impl FnOnce(&Object) -> T,
+	) -> Result<T, ServerError> {
+		let _guard = self.lock(key);
+
+		// the object may have been promoted or set while waiting on the lock
+		if let Ok(object) = self.cache_for(key).peek(key) {
+			return Ok(f(&Object::from_bytes(&object[..])?));
+		}
+
+		let (tier_index, bytes) = self
+			.far_get(key, true)
+			.ok_or(ServerError::CacheError(CacheError::KeyNotFound))?;
+
+		let object = Object::from_bytes(&bytes)?;
+		let now = expiry::now_millis();
+
+		let ttl = object
+			.expires_at()
+			.map(|expires_at| expiry::ttl_seconds(expires_at.saturating_sub(now)));
+
+		let result = f(&object);
+
+		self.cache_for(key).set(key.clone(), bytes, ttl)?;
+		self.track(key);
+
+		if let Some(tier_index) = tier_index {
+			self.tiers.promote(tier_index, key);
+		}
+
+		self.dram_stats.record_promotion();
+
+		Ok(result)
+	}
+
+	/// Returns the object of a key which the cache does not hold, along with
+	/// the index of the far tier which holds it. An object which the cache
+	/// evicted but which was not demoted yet is still held in DRAM, and is
+	/// newer than any object the far tiers hold for its key. The far tiers
+	/// record the read in their stats if `should_record` is set.
+	fn far_get(&self, key: &[u8], should_record: bool) -> Option<(Option<usize>, Buffer)> {
+		if let Some(object) = self.residents.get(key) {
+			return Some((None, Buffer::from(&object[..])));
+		}
+
+		self.tiers
+			.get(key, should_record)
+			.map(|(tier_index, bytes)| (Some(tier_index), Buffer::from(bytes)))
+	}
+
+	/// Tracks the object which was just set in the cache above the far
+	/// tiers, so that it is demoted once the cache evicts it.
+	fn track(&self, key: &Buffer) {
+		match self.cache_for(key).peek(key) {
+			Ok(object) => self.residents.insert(key.clone(), object),
+
+			// the object was not kept by the cache, so any object the far
+			// tiers hold for the key is stale
+			Err(_) => {
+				self.residents.remove(key);
+				self.tiers.remove(key);
+			},
+		}
+	}
+
+	/// Removes the object and its index entries without acquiring the key's
+	/// lock. Returns whether the object was pinned or in the cache or a far
+	/// tier.
+	fn remove(&self, key: &Buffer) -> Result<bool, ServerError> {
+		self.keys.remove(key);
+		self.tags.remove(key);
+		self.residents.remove(key);
+
+		let is_pinned = self.pinned.remove(key).is_some();
+		let is_in_tiers = self.tiers.remove(key);
+
+		match self.cache_for(key).del(key) {
+			Ok(_) => Ok(true),
+			Err(CacheError::KeyNotFound) => Ok(is_pinned || is_in_tiers),
+			Err(err) => Err(err.into()),
+		}
+	}
+
+	/// Removes a key from the index if it is no longer in the cache. When
+	/// called during a write, the key's lock may already be held by the
+	/// current thread, so the key is skipped rather than waiting on it.
+	fn prune(&self, key: &Buffer, should_block: bool) {
+		let _guard = match should_block {
+			true => self.lock(key),
+
+			false => match self.try_lock(key) {
+				Some(guard) => guard,
+				None => return,
+			},
+		};
+
+		if !self.has(key).unwrap_or(true) {
+			self.keys.remove(key);
+			self.tags.remove(key);
+
+			// an expired pinned object is only removed here
+			self.pinned.remove(key);
+		}
+	}
+
+	fn cache_for(&self, key: &[u8]) -> &Cache {
+		self.namespaces
+			.find(key)
+			.unwrap_or(&self.cache)
+	}
+
+	fn lock(&self, key: &[u8]) -> MutexGuard<'_, ()> {
+		self.locks[self.lock_index(key)]
+			.lock()
+			.unwrap_or_else(|err| err.into_inner())
+	}
+
+	fn try_lock(&self, key: &[u8]) -> Option<MutexGuard<'_, ()>> {
+		match self.locks[self.lock_index(key)].try_lock() {
+			Ok(guard) => Some(guard),
+			Err(TryLockError::Poisoned(err)) => Some(err.into_inner()),
+			Err(TryLockError::WouldBlock) => None,
+		}
+	}
+
+	fn lock_index(&self, key: &[u8]) -> usize {
+		let mut s = DefaultHasher::new();
+		key.hash(&mut s);
+
+		s.finish() as usize % self.locks.len()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{fs, path::PathBuf, sync::Arc, thread};
+	use paper_cache::PaperPolicy;
+	use crate::{admission::AdmissionPolicy, namespace::NamespaceConfig, object, tier::TierConfig};
+	use super::*;
+
+	const VALUE_SIZE: usize = 100;
+
+	/// A store whose cache only holds a few objects, above a file-backed far
+	/// tier which holds all of them.
+	fn tiered_store(name: &str) -> (Store, PathBuf) {
+		let path = std::env::temp_dir().join(format!("paper-tier-{}-{name}", std::process::id()));
+
+		let tier = format!("far:1MiB,file={}", path.display())
+			.parse::<TierConfig>()
+			.unwrap();
+
+		let store = Store::new(
+			Cache::new(10 * VALUE_SIZE as u64, &[PaperPolicy::Lru], PaperPolicy::Lru).unwrap(),
+			Namespaces::default(),
+			Tiers::new(&[tier]).unwrap(),
+			PinnedObjects::new(1 << 20),
+			Admission::new(AdmissionPolicy::None),
+		);
+
+		(store, path)
+	}
+
+	fn store() -> Store {
+		store_with_admission(AdmissionPolicy::None)
+	}
+
+	/// A store whose cache only holds a few objects, without far tiers.
+	fn small_store(max_pinned_size: u64) -> Store {
+		Store::new(
+			Cache::new(10 * VALUE_SIZE as u64, &[PaperPolicy::Lru], PaperPolicy::Lru).unwrap(),
+			Namespaces::default(),
+			Tiers::default(),
+			PinnedObjects::new(max_pinned_size),
+			Admission::new(AdmissionPolicy::None),
+		)
+	}
+
+	fn store_with_admission(policy: AdmissionPolicy) -> Store {
+		Store::new(
+			Cache::new(1 << 20, &[PaperPolicy::Lru], PaperPolicy::Lru).unwrap(),
+			Namespaces::default(),
+			Tiers::default(),
+			PinnedObjects::new(1 << 20),
+			Admission::new(policy),
+		)
+	}
+
+	/// A store with a namespace which only holds a few objects.
+	fn namespaced_store() -> Store {
+		let namespace = "sessions:1KiB,prefix=session:,policy=lru"
+			.parse::<NamespaceConfig>()
+			.unwrap();
+
+		Store::new(
+			Cache::new(1 << 20, &[PaperPolicy::Lru], PaperPolicy::Lru).unwrap(),
+			Namespaces::new(&[namespace]).unwrap(),
+			Tiers::default(),
+			PinnedObjects::new(1 << 20),
+			Admission::new(AdmissionPolicy::None),
+		)
+	}
+
+	fn key(key: &str) -> Buffer {
+		Buffer::from(key.as_bytes())
+	}
+
+	fn value(store: &Store, key_name: &str) -> Option<Vec<u8>> {
+		store.get(&key(key_name), |object| object.value().to_vec()).ok()
+	}
+
+	fn version(store: &Store, key_name: &str) -> u64 {
+		store.get(&key(key_name), |object| object.version()).unwrap()
+	}
+
+	#[test]
+	fn it_evicts_a_namespace_without_affecting_the_cache() {
+		let store = namespaced_store();
+		let large_value = [0; 400];
+
+		store.set(key("page:1"), &large_value, Expiry::Never, &[]).unwrap();
+
+		for index in 0..5 {
+			store.set(key(&format!("session:{index}")), &large_value, Expiry::Never, &[]).unwrap();
+		}
+
+		assert!(store.namespaces.find(b"session:4").unwrap().has(&key("session:4")));
+		assert!(!store.cache().has(&key("session:4")));
+
+		// the namespace's cache only holds its latest objects
+		assert_eq!(value(&store, "session:0"), None);
+		assert!(value(&store, "session:4").is_some());
+
+		assert!(value(&store, "page:1").is_some());
+	}
+
+	#[test]
+	fn it_sweeps_the_keys_of_evicted_objects() {
+		let store = small_store(1 << 20);
+		let value = [0; VALUE_SIZE];
+
+		for index in 0..100 {
+			store.set(key(&index.to_string()), &value, Expiry::Never, &[key("tag")]).unwrap();
+		}
+
+		let num_held = (0..100)
+			.filter(|index| store.has(&key(&index.to_string())).unwrap())
+			.count();
+
+		assert!(num_held < 10);
+
+		// a single sweep reaches every key, without any further writes
+		store.sweep(1000);
+
+		assert_eq!(store.keys.range(&[], &[], usize::MAX).0.len(), num_held);
+		assert_eq!(store.tags.keys(b"tag").len(), num_held);
+	}
+
+	#[test]
+	fn it_versions_every_write() {
+		let store = store();
+
+		store.set(key("a"), b"1", Expiry::Never, &[]).unwrap();
+		let first_version = version(&store, "a");
+
+		store.set(key("a"), b"2", Expiry::Never, &[]).unwrap();
+		assert!(version(&store, "a") > first_version);
+	}
+
+	#[test]
+	fn it_applies_a_cas_with_the_current_version() {
+		let store = store();
+
+		store.set(key("a"), b"1", Expiry::Never, &[]).unwrap();
+		let current_version = version(&store, "a");
+
+		store.cas(key("a"), b"2", current_version, Expiry::Never).unwrap();
+
+		assert_eq!(value(&store, "a"), Some(b"2".to_vec()));
+		assert!(version(&store, "a") > current_version);
+	}
+
+	#[test]
+	fn it_rejects_a_cas_with_a_stale_version() {
+		let store = store();
+
+		store.set(key("a"), b"1", Expiry::Never, &[]).unwrap();
+		let stale_version = version(&store, "a");
+		store.set(key("a"), b"2", Expiry::Never, &[]).unwrap();
+
+		assert_eq!(
+			store.cas(key("a"), b"3", stale_version, Expiry::Never),
+			Err(ServerError::VersionMismatch),
+		);
+
+		assert_eq!(value(&store, "a"), Some(b"2".to_vec()));
+	}
+
+	#[test]
+	fn it_rejects_a_cas_on_a_missing_key() {
+		assert_eq!(
+			store().cas(key("a"), b"1", 1, Expiry::Never),
+			Err(ServerError::CacheError(CacheError::KeyNotFound)),
+		);
+	}
+
+	#[test]
+	fn it_applies_exactly_one_of_concurrent_cas_writes() {
+		let store = Arc::new(store());
+
+		store.set(key("a"), b"0", Expiry::Never, &[]).unwrap();
+		let current_version = version(&store, "a");
+
+		let handles = (0..8)
+			.map(|index| {
+				let store = store.clone();
+
+				thread::spawn(move || {
+					store
+						.cas(key("a"), index.to_string().as_bytes(), current_version, Expiry::Never)
+						.is_ok()
+				})
+			})
+			.collect::<Vec<_>>();
+
+		let num_applied = handles
+			.into_iter()
+			.map(|handle| handle.join().unwrap())
+			.filter(|is_applied| *is_applied)
+			.count();
+
+		assert_eq!(num_applied, 1);
+	}
+
+	#[test]
+	fn it_sets_nx_only_if_the_key_is_missing() {
+		let store = store();
+
+		assert_eq!(store.set_nx(key("a"), b"1", Expiry::Never), Ok(true));
+		assert_eq!(store.set_nx(key("a"), b"2", Expiry::Never), Ok(false));
+
+		assert_eq!(value(&store, "a"), Some(b"1".to_vec()));
+	}
+
+	#[test]
+	fn it_sets_xx_only_if_the_key_exists() {
+		let store = store();
+
+		assert_eq!(store.set_xx(key("a"), b"1", Expiry::Never), Ok(false));
+		assert_eq!(value(&store, "a"), None);
+
+		store.set(key("a"), b"1", Expiry::Never, &[]).unwrap();
+
+		assert_eq!(store.set_xx(key("a"), b"2", Expiry::Never), Ok(true));
+		assert_eq!(value(&store, "a"), Some(b"2".to_vec()));
+	}
+
+	#[test]
+	fn it_returns_the_replaced_value_on_get_set() {
+		let store = store();
+
+		assert_eq!(store.get_set(key("a"), b"1", Expiry::Never), Ok(None));
+		assert_eq!(store.get_set(key("a"), b"2", Expiry::Never), Ok(Some(Buffer::from(&b"1"[..]))));
+
+		assert_eq!(value(&store, "a"), Some(b"2".to_vec()));
+	}
+
+	#[test]
+	fn it_grants_a_concurrent_set_nx_to_exactly_one_writer() {
+		let store = Arc::new(store());
+
+		let handles = (0..8)
+			.map(|index| {
+				let store = store.clone();
+
+				thread::spawn(move || {
+					store
+						.set_nx(key("lock"), index.to_string().as_bytes(), Expiry::Never)
+						.unwrap()
+				})
+			})
+			.collect::<Vec<_>>();
+
+		let num_acquired = handles
+			.into_iter()
+			.map(|handle| handle.join().unwrap())
+			.filter(|is_set| *is_set)
+			.count();
+
+		assert_eq!(num_acquired, 1);
+	}
+
+	#[test]
+	fn it_scans_every_key_with_a_prefix_exactly_once() {
+		let store = store();
+
+		for index in 0..25 {
+			store.set(key(&format!("user:{index:02}")), b"1", Expiry::Never, &[]).unwrap();
+		}
+
+		store.set(key("session:1"), b"1", Expiry::Never, &[]).unwrap();
+
+		let mut scanned = Vec::new();
+		let mut cursor = Buffer::default();
+
+		loop {
+			let (keys, next_cursor) = store.scan(&cursor, b"user:", 10).unwrap();
+			scanned.extend(keys);
+
+			match next_cursor {
+				Some(next_cursor) => cursor = next_cursor,
+				None => break,
+			}
+		}
+
+		let expected = (0..25)
+			.map(|index| key(&format!("user:{index:02}")))
+			.collect::<Vec<_>>();
+
+		assert_eq!(scanned, expected);
+	}
+
+	#[test]
+	fn it_skips_deleted_keys_in_a_scan() {
+		let store = store();
+
+		store.set(key("a"), b"1", Expiry::Never, &[]).unwrap();
+		store.set(key("b"), b"1", Expiry::Never, &[]).unwrap();
+		store.del(&key("a")).unwrap();
+
+		assert_eq!(store.scan(b"", b"", 10), Ok((vec![key("b")], None)));
+	}
+
+	#[test]
+	fn it_deletes_by_prefix() {
+		let store = store();
+
+		for index in 0..2500 {
+			store.set(key(&format!("user:{index}")), b"1", Expiry::Never, &[]).unwrap();
+		}
+
+		store.set(key("session:1"), b"1", Expiry::Never, &[]).unwrap();
+
+		assert_eq!(store.del_prefix(b"user:"), Ok(2500));
+		assert_eq!(value(&store, "user:1"), None);
+		assert_eq!(value(&store, "session:1"), Some(b"1".to_vec()));
+	}
+
+	#[test]
+	fn it_invalidates_every_object_with_a_tag() {
+		let store = store();
+
+		store.set(key("a"), b"1", Expiry::Never, &[key("x")]).unwrap();
+		store.set(key("b"), b"1", Expiry::Never, &[key("x"), key("y")]).unwrap();
+		store.set(key("c"), b"1", Expiry::Never, &[key("y")]).unwrap();
+
+		assert_eq!(store.invalidate(b"x"), Ok(2));
+
+		assert_eq!(value(&store, "a"), None);
+		assert_eq!(value(&store, "b"), None);
+		assert_eq!(value(&store, "c"), Some(b"1".to_vec()));
+	}
+
+	#[test]
+	fn it_drops_tags_when_a_key_is_rewritten() {
+		let store = store();
+
+		store.set(key("a"), b"1", Expiry::Never, &[key("x")]).unwrap();
+		store.set(key("a"), b"2", Expiry::Never, &[]).unwrap();
+
+		assert_eq!(store.invalidate(b"x"), Ok(0));
+		assert_eq!(value(&store, "a"), Some(b"2".to_vec()));
+	}
+
+	#[test]
+	fn it_demotes_evicted_objects_and_promotes_them_on_access() {
+		let (store, path) = tiered_store("promote");
+
+		for index in 0..100u8 {
+			store.set(key(&format!("key:{index}")), &[index; VALUE_SIZE], Expiry::Never, &[]).unwrap();
+		}
+
+		store.demote_evicted();
+
+		let status = &store.far_tier_status()[0];
+
+		assert!(status.demotions > 0);
+		assert_eq!(status.num_objects, status.demotions);
+		assert_eq!(status.promotions, 0);
+
+		for index in 0..100u8 {
+			assert_eq!(value(&store, &format!("key:{index}")), Some(vec![index; VALUE_SIZE]));
+		}
+
+		let status = &store.far_tier_status()[0];
+
+		assert!(status.promotions > 0);
+		assert!(status.num_objects < status.demotions);
+
+		// the cache counts the same objects moving the other way
+		assert!(store.dram_stats().promotions() >= status.promotions);
+		assert_eq!(store.dram_stats().demotions(), status.demotions);
+
+		fs::remove_file(path).unwrap();
+	}
+
+	#[test]
+	fn it_only_records_client_reads_in_the_tier_stats() {
+		let (store, path) = tiered_store("stats");
+
+		for index in 0..100u8 {
+			store.set(key(&format!("key:{index}")), &[index; VALUE_SIZE], Expiry::Never, &[]).unwrap();
+		}
+
+		store.demote_evicted();
+
+		let far_hits = store.far_tier_status()[0].hits;
+		let dram_hits = store.dram_stats().hits();
+
+		for index in 0..100u8 {
+			let key = key(&format!("key:{index}"));
+
+			assert!(store.has(&key).unwrap());
+			assert!(store.inspect(&key, |_| ()).is_ok());
+			assert!(!store.set_nx(key, b"new", Expiry::Never).unwrap());
+		}
+
+		store.sweep(1000);
+
+		assert_eq!(store.far_tier_status()[0].hits, far_hits);
+		assert_eq!(store.dram_stats().hits(), dram_hits);
+
+		assert!(store.peek(&key("key:0"), |_| ()).is_ok());
+		assert_eq!(store.far_tier_status()[0].hits, far_hits + 1);
+
+		fs::remove_file(path).unwrap();
+	}
+
+	#[test]
+	fn it_only_counts_evicted_objects_as_demotions() {
+		let (store, path) = tiered_store("demotions");
+
+		for index in 0..5u8 {
+			store.set(key(&format!("key:{index}")), &[index; VALUE_SIZE], Expiry::Never, &[]).unwrap();
+			store.set(key(&format!("key:{index}")), &[index; VALUE_SIZE], Expiry::Never, &[]).unwrap();
+		}
+
+		store.demote_evicted();
+		assert_eq!(store.far_tier_status()[0].demotions, 0);
+
+		for index in 5..100u8 {
+			store.set(key(&format!("key:{index}")), &[index; VALUE_SIZE], Expiry::Never, &[]).unwrap();
+		}
+
+		store.demote_evicted();
+
+		let status = &store.far_tier_status()[0];
+		assert!(status.demotions > 0 && status.demotions < 100);
+
+		fs::remove_file(path).unwrap();
+	}
+
+	#[test]
+	fn it_does_not_serve_a_deleted_object_from_a_far_tier() {
+		let (store, path) = tiered_store("delete");
+
+		for index in 0..100u8 {
+			store.set(key(&format!("key:{index}")), &[index; VALUE_SIZE], Expiry::Never, &[]).unwrap();
+		}
+
+		store.demote_evicted();
+
+		store.del(&key("key:0")).unwrap();
+		store.set(key("key:1"), b"new", Expiry::Never, &[]).unwrap();
+
+		assert_eq!(value(&store, "key:0"), None);
+		assert_eq!(value(&store, "key:1"), Some(b"new".to_vec()));
+
+		fs::remove_file(path).unwrap();
+	}
+
+	#[test]
+	fn it_updates_an_expiry_without_changing_the_version() {
+		let store = store();
+
+		store.set(key("a"), b"1", Expiry::Never, &[]).unwrap();
+		let current_version = version(&store, "a");
+
+		store.expire(key("a"), Expiry::Millis(60_000)).unwrap();
+
+		let expires_at = store.peek(&key("a"), |object| object.expires_at()).unwrap();
+
+		assert!(expires_at.is_some_and(|expires_at| expires_at > expiry::now_millis()));
+		assert_eq!(version(&store, "a"), current_version);
+		assert_eq!(store.num_expiry_sets(), 1);
+	}
+
+	#[test]
+	fn it_removes_an_object_with_a_past_expiry() {
+		let store = store();
+
+		store.set(key("a"), b"1", Expiry::Never, &[]).unwrap();
+		store.expire(key("a"), Expiry::UnixMillis(1)).unwrap();
+
+		assert_eq!(value(&store, "a"), None);
+		assert_eq!(store.num_expiry_sets(), 0);
+	}
+
+	#[test]
+	fn it_hides_an_object_once_its_millisecond_expiry_passes() {
+		let store = store();
+
+		store.set(key("a"), b"1", Expiry::Millis(20), &[]).unwrap();
+		assert_eq!(value(&store, "a"), Some(b"1".to_vec()));
+
+		thread::sleep(std::time::Duration::from_millis(40));
+		assert_eq!(value(&store, "a"), None);
+	}
+
+	#[test]
+	fn it_keeps_a_pinned_object_under_eviction_pressure() {
+		let store = small_store(1 << 20);
+
+		store.set(key("pinned"), &[1; VALUE_SIZE], Expiry::Never, &[]).unwrap();
+		store.pin(&key("pinned")).unwrap();
+
+		for index in 0..100 {
+			store.set(key(&index.to_string()), &[0; VALUE_SIZE], Expiry::Never, &[]).unwrap();
+		}
+
+		assert_eq!(value(&store, "0"), None);
+		assert_eq!(value(&store, "pinned"), Some(vec![1; VALUE_SIZE]));
+
+		// a pinned object is updated in place
+		store.set(key("pinned"), &[2; VALUE_SIZE], Expiry::Never, &[]).unwrap();
+		assert!(store.pinned().contains(&key("pinned")));
+		assert_eq!(value(&store, "pinned"), Some(vec![2; VALUE_SIZE]));
+	}
+
+	#[test]
+	fn it_rejects_a_pin_beyond_the_max_pinned_size() {
+		let pinned_size = ("pinned:0".len() + object::HEADER_SIZE + VALUE_SIZE) as u64;
+		let store = small_store(2 * pinned_size);
+
+		for index in 0..3 {
+			store.set(key(&format!("pinned:{index}")), &[0; VALUE_SIZE], Expiry::Never, &[]).unwrap();
+		}
+
+		store.pin(&key("pinned:0")).unwrap();
+		store.pin(&key("pinned:1")).unwrap();
+
+		assert_eq!(store.pin(&key("pinned:2")), Err(ServerError::PinnedSizeExceeded));
+		assert_eq!(store.pinned().used_size(), 2 * pinned_size);
+
+		// the object which could not be pinned is left in the cache
+		assert!(store.cache().has(&key("pinned:2")));
+
+		store.unpin(&key("pinned:0")).unwrap();
+		store.pin(&key("pinned:2")).unwrap();
+	}
+
+	#[test]
+	fn it_evicts_an_unpinned_object() {
+		let store = small_store(1 << 20);
+
+		store.set(key("pinned"), &[1; VALUE_SIZE], Expiry::Never, &[]).unwrap();
+		store.pin(&key("pinned")).unwrap();
+		store.unpin(&key("pinned")).unwrap();
+
+		assert_eq!(store.pinned().used_size(), 0);
+		assert!(store.cache().has(&key("pinned")));
+
+		assert_eq!(
+			store.unpin(&key("pinned")),
+			Err(ServerError::CacheError(CacheError::KeyNotFound)),
+		);
+
+		for index in 0..100 {
+			store.set(key(&index.to_string()), &[0; VALUE_SIZE], Expiry::Never, &[]).unwrap();
+		}
+
+		assert_eq!(value(&store, "pinned"), None);
+	}
+
+	#[test]
+	fn it_expires_a_pinned_object() {
+		let store = small_store(1 << 20);
+
+		store.set(key("pinned"), b"1", Expiry::Millis(20), &[]).unwrap();
+		store.pin(&key("pinned")).unwrap();
+
+		thread::sleep(std::time::Duration::from_millis(40));
+		assert_eq!(value(&store, "pinned"), None);
+
+		// the expired object keeps its share of the budget until it is swept
+		assert!(store.pinned().used_size() > 0);
+
+		store.sweep(1000);
+		assert_eq!(store.pinned().used_size(), 0);
+	}
+
+	#[test]
+	fn it_reports_a_set_which_is_not_admitted() {
+		let store = store_with_admission(AdmissionPolicy::TinyLfu);
+
+		assert_eq!(store.set(key("a"), b"1", Expiry::Never, &[]), Err(ServerError::NotAdmitted));
+		assert_eq!(store.get_set(key("a"), b"1", Expiry::Never), Err(ServerError::NotAdmitted));
+		assert_eq!(value(&store, "a"), None);
+
+		// the rejected writes and the read are accesses to the key, so
+		// its next write is admitted
+		assert_eq!(store.get_set(key("a"), b"1", Expiry::Never), Ok(None));
+		assert_eq!(value(&store, "a"), Some(b"1".to_vec()));
+
+		assert_eq!(store.admission().rejected(), 2);
+		assert_eq!(store.admission().admitted(), 1);
+	}
+
+	#[test]
+	fn it_reports_a_setnx_which_is_not_admitted() {
+		let store = store_with_admission(AdmissionPolicy::TinyLfu);
+
+		assert_eq!(store.set_nx(key("a"), b"1", Expiry::Never), Ok(false));
+		assert_eq!(value(&store, "a"), None);
+
+		assert_eq!(store.set_nx(key("a"), b"1", Expiry::Never), Ok(true));
+		assert_eq!(value(&store, "a"), Some(b"1".to_vec()));
+	}
+
+	#[test]
+	fn it_always_updates_an_admitted_object() {
+		let store = store_with_admission(AdmissionPolicy::TinyLfu);
+
+		for _ in 0..3 {
+			let _ = store.set(key("a"), b"1", Expiry::Never, &[]);
+		}
+
+		let rejected = store.admission().rejected();
+
+		store.set(key("a"), b"2", Expiry::Never, &[]).unwrap();
+		assert_eq!(store.set_xx(key("a"), b"3", Expiry::Never), Ok(true));
+
+		assert_eq!(value(&store, "a"), Some(b"3".to_vec()));
+		assert_eq!(store.admission().rejected(), rejected);
+	}
+}
@@ -0,0 +1,167 @@
+/*
+ * Copyright (c) Kia Shakiba
+ *
+ * This source code is licensed under the GNU AGPLv3 license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::{
+	collections::{HashMap, HashSet},
+	sync::RwLock,
+};
+
+use paper_utils::stream::Buffer;
+
+/// Tracks which keys carry which tags. An object's tags are replaced on
+/// every write to its key, so a key is only ever a member of the tags it
+/// was last written with.
+#[derive(Default)]
+pub struct TagIndex {
+	inner: RwLock<Tags>,
+}
+
+#[derive(Default)]
+struct Tags {
+	keys_by_tag: HashMap<Buffer, HashSet<Buffer>>,
+	tags_by_key: HashMap<Buffer, Vec<Buffer>>,
+}
+
+impl TagIndex {
+	pub fn set(&self, key: &Buffer, tags: &[Buffer]) {
+		if tags.is_empty() && !self.contains(key) {
+			return;
+		}
+
+		let mut inner = self.inner
+			.write()
+			.unwrap_or_else(|err| err.into_inner());
+
+		inner.remove(key);
+
+		if tags.is_empty() {
+			return;
+		}
+
+		for tag in tags {
+			inner.keys_by_tag
+				.entry(tag.clone())
+				.or_default()
+				.insert(key.clone());
+		}
+
+		inner.tags_by_key.insert(key.clone(), tags.to_vec());
+	}
+
+	pub fn remove(&self, key: &[u8]) {
+		if !self.contains(key) {
+			return;
+		}
+
+		self.inner
+			.write()
+			.unwrap_or_else(|err| err.into_inner())
+			.remove(key);
+	}
+
+	pub fn clear(&self) {
+		let mut inner = self.inner
+			.write()
+			.unwrap_or_else(|err| err.into_inner());
+
+		inner.keys_by_tag.clear();
+		inner.tags_by_key.clear();
+	}
+
+	/// Returns the tags which the key currently carries.
+	pub fn tags(&self, key: &[u8]) -> Vec<Buffer> {
+		self.inner
+			.read()
+			.unwrap_or_else(|err| err.into_inner())
+			.tags_by_key
+			.get(key)
+			.cloned()
+			.unwrap_or_default()
+	}
+
+	/// Returns the keys which currently carry the tag.
+	pub fn keys(&self, tag: &[u8]) -> Vec<Buffer> {
+		self.inner
+			.read()
+			.unwrap_or_else(|err| err.into_inner())
+			.keys_by_tag
+			.get(tag)
+			.map(|keys| keys.iter().cloned().collect())
+			.unwrap_or_default()
+	}
+
+	fn contains(&self, key: &[u8]) -> bool {
+		self.inner
+			.read()
+			.unwrap_or_else(|err| err.into_inner())
+			.tags_by_key
+			.contains_key(key)
+	}
+}
+
+impl Tags {
+	fn remove(&mut self, key: &[u8]) {
+		let Some(tags) = self.tags_by_key.remove(key) else {
+			return;
+		};
+
+		for tag in tags {
+			let Some(keys) = self.keys_by_tag.get_mut(&tag) else {
+				continue;
+			};
+
+			keys.remove(key);
+
+			if keys.is_empty() {
+				self.keys_by_tag.remove(&tag);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn buffer(value: &str) -> Buffer {
+		Buffer::from(value.as_bytes())
+	}
+
+	#[test]
+	fn it_replaces_a_key_s_tags() {
+		let index = TagIndex::default();
+
+		index.set(&buffer("a"), &[buffer("x"), buffer("y")]);
+		index.set(&buffer("a"), &[buffer("z")]);
+
+		assert_eq!(index.tags(b"a"), [buffer("z")]);
+		assert!(index.keys(b"x").is_empty());
+		assert_eq!(index.keys(b"z"), [buffer("a")]);
+	}
+
+	#[test]
+	fn it_removes_a_key_from_its_tags() {
+		let index = TagIndex::default();
+
+		index.set(&buffer("a"), &[buffer("x")]);
+		index.set(&buffer("b"), &[buffer("x")]);
+		index.remove(b"a");
+
+		assert_eq!(index.keys(b"x"), [buffer("b")]);
+		assert!(index.tags(b"a").is_empty());
+	}
+
+	#[test]
+	fn it_clears_tags_on_an_untagged_write() {
+		let index = TagIndex::default();
+
+		index.set(&buffer("a"), &[buffer("x")]);
+		index.set(&buffer("a"), &[]);
+
+		assert!(index.keys(b"x").is_empty());
+	}
+}
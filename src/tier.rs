@@ -0,0 +1,421 @@
+/*
+ * Copyright (c) Kia Shakiba
+ *
+ * This source code is licensed under the GNU AGPLv3 license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::{
+	fmt,
+	str::FromStr,
+	path::PathBuf,
+	time::{Duration, Instant},
+	sync::atomic::{AtomicU64, Ordering},
+};
+
+use parse_size::parse_size;
+use paper_utils::stream::Buffer;
+
+use crate::{
+	error::ServerError,
+	far_tier::FarTier,
+};
+
+pub const DRAM_TIER_NAME: &str = "dram";
+
+/// A memory tier as configured with `tiers[]=<name>:<size>[,<option>=<value>]`.
+/// The `dram` tier is held by the cache itself, while every other tier is a
+/// far tier backed by the configured memory.
+#[derive(Debug, Clone)]
+pub struct TierConfig {
+	name: String,
+	size: u64,
+	backing: TierBacking,
+}
+
+#[derive(Debug, Clone)]
+pub enum TierBacking {
+	Dram,
+
+	Anonymous,
+	NumaNode(u32),
+	Device(PathBuf),
+	File(PathBuf),
+}
+
+/// The far tiers which sit below the cache, ordered from fastest to
+/// slowest. The far tiers are exclusive: an object is only demoted into the
+/// first far tier once the cache evicts it, and is moved back into the
+/// cache when it is accessed again. Objects evicted from a far tier are
+/// demoted into the next far tier, if there is one.
+#[derive(Default)]
+pub struct Tiers {
+	far_tiers: Vec<FarTier>,
+}
+
+/// Access counters of a single tier. Promotions count the objects moved
+/// out of the tier into the cache, and demotions count the objects which
+/// were evicted from the tier above and moved into the tier.
+#[derive(Default)]
+pub struct TierStats {
+	hits: AtomicU64,
+	promotions: AtomicU64,
+	demotions: AtomicU64,
+
+	accesses: AtomicU64,
+	access_nanos: AtomicU64,
+}
+
+pub struct TierStatus {
+	pub name: String,
+
+	pub used_size: u64,
+	pub max_size: u64,
+	pub num_objects: u64,
+
+	pub hits: u64,
+	pub promotions: u64,
+	pub demotions: u64,
+
+	pub avg_access_nanos: u64,
+}
+
+impl TierConfig {
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+
+	pub fn size(&self) -> u64 {
+		self.size
+	}
+
+	pub fn backing(&self) -> &TierBacking {
+		&self.backing
+	}
+
+	pub fn is_dram(&self) -> bool {
+		matches!(self.backing, TierBacking::Dram)
+	}
+}
+
+impl FromStr for TierConfig {
+	type Err = ServerError;
+
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		let invalid = || ServerError::InvalidConfigTier(value.into());
+
+		let (name, params) = value.split_once(':').ok_or_else(invalid)?;
+		let mut params = params.split(',');
+
+		let name = name.trim();
+
+		if name.is_empty() {
+			return Err(invalid());
+		}
+
+		let size = match params.next().map(|size| parse_size(size.trim())) {
+			Some(Ok(size)) if size > 0 => size,
+			_ => return Err(invalid()),
+		};
+
+		let mut backing = match name {
+			DRAM_TIER_NAME => TierBacking::Dram,
+			_ => TierBacking::Anonymous,
+		};
+
+		for param in params {
+			let (key, param_value) = param.split_once('=').ok_or_else(invalid)?;
+
+			if matches!(backing, TierBacking::Dram) {
+				// the dram tier is allocated by the cache and takes no options
+				return Err(invalid());
+			}
+
+			backing = match (key.trim(), param_value.trim()) {
+				("numa_node", node) => TierBacking::NumaNode(node.parse().map_err(|_| invalid())?),
+				("path", path) if !path.is_empty() => TierBacking::Device(path.into()),
+				("file", path) if !path.is_empty() => TierBacking::File(path.into()),
+
+				_ => return Err(invalid()),
+			};
+		}
+
+		Ok(TierConfig {
+			name: name.to_owned(),
+			size,
+			backing,
+		})
+	}
+}
+
+impl fmt::Display for TierConfig {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}:{}", self.name, self.size)?;
+
+		match &self.backing {
+			TierBacking::Dram | TierBacking::Anonymous => Ok(()),
+			TierBacking::NumaNode(node) => write!(f, ",numa_node={node}"),
+			TierBacking::Device(path) => write!(f, ",path={}", path.display()),
+			TierBacking::File(path) => write!(f, ",file={}", path.display()),
+		}
+	}
+}
+
+impl Tiers {
+	pub fn new(configs: &[TierConfig]) -> Result<Self, ServerError> {
+		let far_tiers = configs
+			.iter()
+			.filter(|config| !config.is_dram())
+			.map(FarTier::new)
+			.collect::<Result<Vec<_>, _>>()?;
+
+		Ok(Tiers {
+			far_tiers,
+		})
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.far_tiers.is_empty()
+	}
+
+	/// Returns the object from the fastest far tier which holds the key,
+	/// along with the index of that tier. The access is recorded in the
+	/// stats of each tier it reaches if `should_record` is set.
+	pub fn get(&self, key: &[u8], should_record: bool) -> Option<(usize, Vec<u8>)> {
+		for (index, tier) in self.far_tiers.iter().enumerate() {
+			let start = Instant::now();
+			let value = tier.get(key);
+
+			if should_record {
+				tier.stats().record_access(start.elapsed(), value.is_some());
+			}
+
+			if let Some(value) = value {
+				return Some((index, value));
+			}
+		}
+
+		None
+	}
+
+	/// Removes the object which was promoted out of the tier.
+	pub fn promote(&self, index: usize, key: &[u8]) {
+		if let Some(tier) = self.far_tiers.get(index) {
+			tier.lock().remove(key);
+			tier.stats().record_promotion();
+		}
+	}
+
+	pub fn status(&self) -> Vec<TierStatus> {
+		self.far_tiers
+			.iter()
+			.map(FarTier::status)
+			.collect()
+	}
+
+	/// Writes an object which was evicted from the cache to the first far
+	/// tier, and demotes the objects which are evicted from each far tier to
+	/// make room into the next one. Only the tier being written and the tier
+	/// below it are locked at a time. The next tier is locked before the
+	/// current one is released, so a removal (which locks the tiers in the
+	/// same order) cannot miss an object while it moves between the tiers.
+	pub fn demote(&self, key: &Buffer, value: &[u8]) {
+		let Some(first_tier) = self.far_tiers.first() else {
+			return;
+		};
+
+		let mut ring = first_tier.lock();
+		let mut demoted = ring.insert(key, value, self.far_tiers.len() > 1);
+
+		first_tier.stats().record_demotions(1);
+
+		for (index, tier) in self.far_tiers.iter().enumerate().skip(1) {
+			if demoted.is_empty() {
+				break;
+			}
+
+			let has_next_tier = index + 1 < self.far_tiers.len();
+
+			// the tier above is released once the assignment drops its guard
+			ring = tier.lock();
+
+			tier.stats().record_demotions(demoted.len() as u64);
+
+			demoted = demoted
+				.iter()
+				.flat_map(|(key, value)| ring.insert(key, value, has_next_tier))
+				.collect();
+		}
+	}
+
+	/// Removes the object from every far tier and returns whether any tier
+	/// held it.
+	pub fn remove(&self, key: &[u8]) -> bool {
+		let mut is_removed = false;
+
+		for tier in &self.far_tiers {
+			is_removed |= tier.lock().remove(key);
+		}
+
+		is_removed
+	}
+
+	pub fn clear(&self) {
+		for tier in &self.far_tiers {
+			tier.lock().clear();
+		}
+	}
+}
+
+impl TierStats {
+	pub fn record_access(&self, duration: Duration, is_hit: bool) {
+		if is_hit {
+			self.hits.fetch_add(1, Ordering::Relaxed);
+		}
+
+		self.accesses.fetch_add(1, Ordering::Relaxed);
+		self.access_nanos.fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+	}
+
+	pub fn record_promotion(&self) {
+		self.promotions.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub fn record_demotions(&self, count: u64) {
+		self.demotions.fetch_add(count, Ordering::Relaxed);
+	}
+
+	pub fn hits(&self) -> u64 {
+		self.hits.load(Ordering::Relaxed)
+	}
+
+	pub fn promotions(&self) -> u64 {
+		self.promotions.load(Ordering::Relaxed)
+	}
+
+	pub fn demotions(&self) -> u64 {
+		self.demotions.load(Ordering::Relaxed)
+	}
+
+	pub fn avg_access_nanos(&self) -> u64 {
+		match self.accesses.load(Ordering::Relaxed) {
+			0 => 0,
+			accesses => self.access_nanos.load(Ordering::Relaxed) / accesses,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Two far tiers which each hold two of the test objects.
+	fn tiers() -> Tiers {
+		let configs = ["near:100", "far:100"]
+			.map(|config| config.parse::<TierConfig>().unwrap());
+
+		Tiers::new(&configs).unwrap()
+	}
+
+	fn key(index: u8) -> Buffer {
+		Buffer::from(&[index][..])
+	}
+
+	fn stats(tiers: &Tiers) -> Vec<(u64, u64, u64)> {
+		tiers
+			.status()
+			.iter()
+			.map(|status| (status.hits, status.promotions, status.demotions))
+			.collect()
+	}
+
+	#[test]
+	fn it_parses_a_tier() {
+		let tier = "near:1KiB,numa_node=1".parse::<TierConfig>().unwrap();
+
+		assert_eq!(tier.name(), "near");
+		assert_eq!(tier.size(), 1024);
+		assert!(matches!(tier.backing(), TierBacking::NumaNode(1)));
+		assert_eq!(tier.to_string(), "near:1024,numa_node=1");
+
+		assert!("dram:1KiB".parse::<TierConfig>().unwrap().is_dram());
+		assert!("dram:1KiB,numa_node=1".parse::<TierConfig>().is_err());
+		assert!("near:0".parse::<TierConfig>().is_err());
+	}
+
+	#[test]
+	fn it_demotes_the_objects_evicted_from_each_far_tier() {
+		let tiers = tiers();
+
+		for index in 0..5 {
+			tiers.demote(&key(index), &[index; 40]);
+		}
+
+		// the oldest object was evicted from the last far tier
+		assert_eq!(tiers.get(&key(0), true), None);
+
+		assert_eq!(tiers.get(&key(1), true), Some((1, vec![1; 40])));
+		assert_eq!(tiers.get(&key(2), true), Some((1, vec![2; 40])));
+		assert_eq!(tiers.get(&key(3), true), Some((0, vec![3; 40])));
+		assert_eq!(tiers.get(&key(4), true), Some((0, vec![4; 40])));
+
+		// every object was demoted into the first far tier, and the three
+		// evicted from it were demoted into the second
+		assert_eq!(stats(&tiers), [(2, 0, 5), (2, 0, 3)]);
+	}
+
+	#[test]
+	fn it_removes_a_promoted_object_from_its_tier() {
+		let tiers = tiers();
+
+		for index in 0..3 {
+			tiers.demote(&key(index), &[index; 40]);
+		}
+
+		let (tier_index, _) = tiers.get(&key(0), true).unwrap();
+		assert_eq!(tier_index, 1);
+
+		tiers.promote(tier_index, &key(0));
+
+		assert_eq!(tiers.get(&key(0), true), None);
+		assert_eq!(stats(&tiers), [(0, 0, 3), (1, 1, 1)]);
+
+		assert_eq!(tiers.status()[1].num_objects, 0);
+		assert_eq!(tiers.status()[0].num_objects, 2);
+	}
+
+	#[test]
+	fn it_removes_an_object_from_every_tier() {
+		let tiers = tiers();
+
+		tiers.demote(&key(0), &[0; 40]);
+
+		assert!(tiers.remove(&key(0)));
+		assert!(!tiers.remove(&key(0)));
+		assert_eq!(tiers.get(&key(0), true), None);
+	}
+
+	#[test]
+	fn it_does_not_record_an_unrecorded_access() {
+		let tiers = tiers();
+
+		tiers.demote(&key(0), &[0; 40]);
+
+		assert_eq!(tiers.get(&key(0), false), Some((0, vec![0; 40])));
+		assert_eq!(tiers.get(&key(1), false), None);
+		assert_eq!(stats(&tiers), [(0, 0, 1), (0, 0, 0)]);
+	}
+
+	#[test]
+	fn it_averages_the_access_time() {
+		let stats = TierStats::default();
+
+		assert_eq!(stats.avg_access_nanos(), 0);
+
+		stats.record_access(Duration::from_nanos(10), true);
+		stats.record_access(Duration::from_nanos(30), false);
+
+		assert_eq!(stats.hits(), 1);
+		assert_eq!(stats.avg_access_nanos(), 20);
+	}
+}
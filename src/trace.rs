@@ -0,0 +1,365 @@
+/*
+ * Copyright (c) Kia Shakiba
+ *
+ * This source code is licensed under the GNU AGPLv3 license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::{
+	io::{self, Read, Write, BufReader, BufWriter},
+	fs::{File, OpenOptions},
+	path::{Component, Path, PathBuf},
+	time::{SystemTime, UNIX_EPOCH},
+	hash::{DefaultHasher, Hash, Hasher},
+	sync::{
+		Mutex,
+		atomic::{AtomicBool, AtomicU64, Ordering},
+	},
+};
+
+use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
+
+const MAGIC: &[u8; 8] = b"PAPERTRC";
+const FORMAT_VERSION: u32 = 1;
+
+const SAMPLE_MODULUS: u64 = 1 << 24;
+
+/// The operation of a trace record.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceOp {
+	Get,
+	Set,
+	Del,
+	Has,
+	Peek,
+}
+
+/// A single access in a trace. Each record is written as a fixed-size
+/// 21-byte little-endian record: the timestamp in unix microseconds, the
+/// hashed key, the value size and the operation.
+#[derive(Debug, Clone, Copy)]
+pub struct TraceRecord {
+	pub timestamp: u64,
+	pub key_hash: u64,
+	pub value_size: u32,
+	pub op: TraceOp,
+}
+
+/// Records the accesses handled by the server to a trace file while a
+/// trace is active. Keys are sampled spatially by their hash, so every
+/// access to a sampled key is recorded, which is what policy simulators
+/// need to reproduce the reuse behaviour of the sampled keys.
+///
+/// Traces are only written to new files in the configured trace directory,
+/// and tracing is disabled if no directory is configured.
+#[derive(Default)]
+pub struct Tracer {
+	dir: Option<PathBuf>,
+
+	is_active: AtomicBool,
+	sample_threshold: AtomicU64,
+
+	writer: Mutex<Option<BufWriter<File>>>,
+}
+
+pub struct TraceReader {
+	reader: BufReader<File>,
+}
+
+impl TraceOp {
+	fn to_u8(self) -> u8 {
+		match self {
+			TraceOp::Get => 0,
+			TraceOp::Set => 1,
+			TraceOp::Del => 2,
+			TraceOp::Has => 3,
+			TraceOp::Peek => 4,
+		}
+	}
+
+	fn from_u8(value: u8) -> Option<Self> {
+		match value {
+			0 => Some(TraceOp::Get),
+			1 => Some(TraceOp::Set),
+			2 => Some(TraceOp::Del),
+			3 => Some(TraceOp::Has),
+			4 => Some(TraceOp::Peek),
+
+			_ => None,
+		}
+	}
+}
+
+impl TraceRecord {
+	fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+		writer.write_u64::<LittleEndian>(self.timestamp)?;
+		writer.write_u64::<LittleEndian>(self.key_hash)?;
+		writer.write_u32::<LittleEndian>(self.value_size)?;
+		writer.write_u8(self.op.to_u8())
+	}
+}
+
+impl Tracer {
+	pub fn new(dir: Option<PathBuf>) -> Self {
+		Tracer {
+			dir,
+			..Default::default()
+		}
+	}
+
+	pub fn is_enabled(&self) -> bool {
+		self.dir.is_some()
+	}
+
+	pub fn is_active(&self) -> bool {
+		self.is_active.load(Ordering::Relaxed)
+	}
+
+	/// Starts writing a new trace to the file `name` in the trace directory,
+	/// replacing any active trace, and returns the path of the trace file.
+	/// The name must be a plain file name and the file must not already
+	/// exist. `sample_rate` is the fraction of keys to record.
+	pub fn start(&self, name: &str, sample_rate: f64) -> io::Result<PathBuf> {
+		let Some(dir) = &self.dir else {
+			return Err(io::Error::from(io::ErrorKind::Unsupported));
+		};
+
+		if !is_file_name(name) {
+			return Err(io::Error::new(io::ErrorKind::InvalidInput, "expected a file name"));
+		}
+
+		if !(sample_rate > 0.0 && sample_rate <= 1.0) {
+			return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid sample rate"));
+		}
+
+		let path = dir.join(name);
+
+		let file = OpenOptions::new()
+			.write(true)
+			.create_new(true)
+			.open(&path)?;
+
+		let mut writer = BufWriter::new(file);
+
+		writer.write_all(MAGIC)?;
+		writer.write_u32::<LittleEndian>(FORMAT_VERSION)?;
+
+		let mut current_writer = self.lock_writer();
+
+		if let Some(mut current_writer) = current_writer.take() {
+			current_writer.flush()?;
+		}
+
+		*current_writer = Some(writer);
+
+		let sample_threshold = (sample_rate * SAMPLE_MODULUS as f64) as u64;
+		self.sample_threshold.store(sample_threshold, Ordering::Relaxed);
+		self.is_active.store(true, Ordering::Relaxed);
+
+		Ok(path)
+	}
+
+	pub fn stop(&self) -> io::Result<()> {
+		let mut writer = self.lock_writer();
+
+		self.is_active.store(false, Ordering::Relaxed);
+
+		match writer.take() {
+			Some(mut writer) => writer.flush(),
+			None => Ok(()),
+		}
+	}
+
+	/// Returns the hash of the key if the key is sampled by the active trace.
+	pub fn sample(&self, key: &[u8]) -> Option<u64> {
+		if !self.is_active() {
+			return None;
+		}
+
+		let key_hash = hash_key(key);
+
+		let is_sampled = key_hash % SAMPLE_MODULUS < self.sample_threshold.load(Ordering::Relaxed);
+		is_sampled.then_some(key_hash)
+	}
+
+	pub fn record(&self, key_hash: u64, value_size: u32, op: TraceOp) {
+		let record = TraceRecord {
+			timestamp: now_micros(),
+			key_hash,
+			value_size,
+			op,
+		};
+
+		let mut writer = self.lock_writer();
+
+		let Some(current_writer) = writer.as_mut() else {
+			return;
+		};
+
+		if record.write_to(current_writer).is_err() {
+			// a trace which cannot be written is stopped rather than
+			// leaving a gap in the recorded accesses
+			self.is_active.store(false, Ordering::Relaxed);
+			*writer = None;
+		}
+	}
+
+	fn lock_writer(&self) -> std::sync::MutexGuard<'_, Option<BufWriter<File>>> {
+		self.writer
+			.lock()
+			.unwrap_or_else(|err| err.into_inner())
+	}
+}
+
+impl TraceReader {
+	pub fn open<P>(path: P) -> io::Result<Self>
+	where
+		P: AsRef<Path>,
+	{
+		let mut reader = BufReader::new(File::open(path)?);
+		let mut magic = [0u8; 8];
+
+		reader.read_exact(&mut magic)?;
+
+		if &magic != MAGIC || reader.read_u32::<LittleEndian>()? != FORMAT_VERSION {
+			return Err(io::Error::from(io::ErrorKind::InvalidData));
+		}
+
+		Ok(TraceReader {
+			reader,
+		})
+	}
+
+	fn read_record(&mut self) -> io::Result<Option<TraceRecord>> {
+		let timestamp = match self.reader.read_u64::<LittleEndian>() {
+			Ok(timestamp) => timestamp,
+			Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+			Err(err) => return Err(err),
+		};
+
+		let key_hash = self.reader.read_u64::<LittleEndian>()?;
+		let value_size = self.reader.read_u32::<LittleEndian>()?;
+
+		let op = TraceOp::from_u8(self.reader.read_u8()?)
+			.ok_or(io::Error::from(io::ErrorKind::InvalidData))?;
+
+		Ok(Some(TraceRecord {
+			timestamp,
+			key_hash,
+			value_size,
+			op,
+		}))
+	}
+}
+
+impl Iterator for TraceReader {
+	type Item = io::Result<TraceRecord>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.read_record().transpose()
+	}
+}
+
+/// Returns true if the name is a single normal path component, so that
+/// joining it to the trace directory cannot leave the directory.
+fn is_file_name(name: &str) -> bool {
+	let mut components = Path::new(name).components();
+
+	matches!(
+		(components.next(), components.next()),
+		(Some(Component::Normal(component)), None) if component == name
+	)
+}
+
+pub fn hash_key(key: &[u8]) -> u64 {
+	let mut s = DefaultHasher::new();
+	key.hash(&mut s);
+
+	s.finish()
+}
+
+fn now_micros() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|duration| duration.as_micros() as u64)
+		.unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn trace_dir(name: &str) -> PathBuf {
+		let dir = std::env::temp_dir().join(format!("paper-trace-{name}-{}", std::process::id()));
+
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir_all(&dir).unwrap();
+
+		dir
+	}
+
+	#[test]
+	fn it_is_disabled_without_a_trace_dir() {
+		let tracer = Tracer::default();
+
+		assert!(!tracer.is_enabled());
+
+		let err = tracer.start("trace", 1.0).unwrap_err();
+		assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+	}
+
+	#[test]
+	fn it_rejects_names_outside_the_trace_dir() {
+		let dir = trace_dir("names");
+		let tracer = Tracer::new(Some(dir.clone()));
+
+		for name in ["", ".", "..", "../trace", "/tmp/trace", "sub/trace", "sub/"] {
+			let err = tracer.start(name, 1.0).unwrap_err();
+			assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{name}");
+		}
+
+		assert!(!tracer.is_active());
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[test]
+	fn it_does_not_overwrite_existing_files() {
+		let dir = trace_dir("existing");
+		let tracer = Tracer::new(Some(dir.clone()));
+
+		std::fs::write(dir.join("existing"), b"data").unwrap();
+
+		let err = tracer.start("existing", 1.0).unwrap_err();
+		assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+		assert_eq!(std::fs::read(dir.join("existing")).unwrap(), b"data");
+
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[test]
+	fn it_writes_readable_traces() {
+		let dir = trace_dir("records");
+		let tracer = Tracer::new(Some(dir.clone()));
+
+		let path = tracer.start("trace", 1.0).unwrap();
+		assert_eq!(path, dir.join("trace"));
+
+		let key_hash = tracer.sample(b"key").unwrap();
+		tracer.record(key_hash, 5, TraceOp::Set);
+		tracer.record(key_hash, 0, TraceOp::Get);
+		tracer.stop().unwrap();
+
+		let records = TraceReader::open(&path)
+			.unwrap()
+			.collect::<io::Result<Vec<_>>>()
+			.unwrap();
+
+		assert_eq!(records.len(), 2);
+		assert_eq!(records[0].key_hash, hash_key(b"key"));
+		assert_eq!(records[0].value_size, 5);
+		assert_eq!(records[0].op, TraceOp::Set);
+		assert_eq!(records[1].op, TraceOp::Get);
+
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+}
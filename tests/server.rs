@@ -0,0 +1,173 @@
+/*
+ * Copyright (c) Kia Shakiba
+ *
+ * This source code is licensed under the GNU AGPLv3 license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::net::TcpListener;
+use paper_utils::stream::Buffer;
+
+use paper_server::{
+	ConfigBuilder,
+	NamespaceConfig,
+	ServerBuilder,
+	ServerHandle,
+	command::Command,
+	error::ErrorCode,
+	expiry::Expiry,
+	node_client::{NodeClient, Field, is_ok},
+};
+
+fn spawn(config: ConfigBuilder) -> ServerHandle {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+
+	ServerBuilder::new()
+		.config(config.max_size(1 << 20).build().unwrap())
+		.listener(listener)
+		.build()
+		.unwrap()
+		.spawn()
+		.unwrap()
+}
+
+fn connect(handle: &ServerHandle, auth_token: Option<&str>) -> NodeClient {
+	NodeClient::connect(&handle.local_addr().to_string(), auth_token).unwrap()
+}
+
+fn buffer(value: &str) -> Buffer {
+	Buffer::from(value.as_bytes())
+}
+
+fn set(key: &str, value: &str) -> Command {
+	Command::Set(buffer(key), buffer(value), Expiry::Never, Vec::new())
+}
+
+#[test]
+fn it_serves_a_listener_bound_to_port_zero() {
+	let handle = spawn(ConfigBuilder::new());
+	let mut client = connect(&handle, None);
+
+	assert_ne!(handle.local_addr().port(), 0);
+
+	assert!(is_ok(&client.send(&set("key", "value")).unwrap()));
+
+	let fields = client.send(&Command::Get(buffer("key"))).unwrap();
+	assert!(matches!(&fields[..], [Field::Bool(true), Field::Buf(value)] if *value == buffer("value")));
+
+	let fields = client.send(&Command::Get(buffer("missing"))).unwrap();
+	assert!(matches!(fields[..], [Field::Bool(false), Field::U8(ErrorCode::CACHE), Field::U8(_)]));
+
+	handle.shutdown();
+}
+
+#[test]
+fn it_gets_many_keys() {
+	let handle = spawn(ConfigBuilder::new());
+	let mut client = connect(&handle, None);
+
+	assert!(is_ok(&client.send(&set("a", "1")).unwrap()));
+	assert!(is_ok(&client.send(&set("c", "3")).unwrap()));
+
+	let fields = client
+		.send(&Command::MGet(vec![buffer("a"), buffer("b"), buffer("c")]))
+		.unwrap();
+
+	assert!(matches!(
+		&fields[..],
+		[
+			Field::Bool(true),
+			Field::U32(3),
+			Field::Bool(true), Field::Buf(a),
+			Field::Bool(false),
+			Field::Bool(true), Field::Buf(c),
+		] if *a == buffer("1") && *c == buffer("3")
+	));
+
+	handle.shutdown();
+}
+
+#[test]
+fn it_rejects_a_trace_without_a_trace_dir() {
+	let handle = spawn(ConfigBuilder::new());
+	let mut client = connect(&handle, None);
+
+	let fields = client.send(&Command::TraceStart("trace".into(), 1.0)).unwrap();
+	assert!(matches!(fields[..], [Field::Bool(false), Field::U8(ErrorCode::SERVER)]));
+
+	handle.shutdown();
+}
+
+#[test]
+fn it_requires_the_auth_token() {
+	let handle = spawn(ConfigBuilder::new().auth_token("token"));
+
+	let mut client = connect(&handle, None);
+	let fields = client.send(&set("key", "value")).unwrap();
+	assert!(matches!(fields[..], [Field::Bool(false), Field::U8(ErrorCode::UNAUTHORIZED)]));
+
+	let mut client = connect(&handle, Some("token"));
+	assert!(is_ok(&client.send(&set("key", "value")).unwrap()));
+
+	assert!(NodeClient::connect(&handle.local_addr().to_string(), Some("other")).is_err());
+
+	handle.shutdown();
+}
+
+#[test]
+fn it_reports_the_pinned_size() {
+	let handle = spawn(ConfigBuilder::new().max_pinned_size(1024));
+	let mut client = connect(&handle, None);
+
+	assert!(is_ok(&client.send(&set("key", "value")).unwrap()));
+	assert!(is_ok(&client.send(&Command::Pin(buffer("key"))).unwrap()));
+
+	let fields = client.send(&Command::Status).unwrap();
+	assert!(matches!(fields[..], [.., Field::U64(used_size), Field::U64(1024), Field::U32(0)] if used_size > 0));
+
+	handle.shutdown();
+}
+
+#[test]
+fn it_reports_every_namespace() {
+	let namespaces = [
+		"sessions:1KiB,prefix=session:,policy=lru".parse::<NamespaceConfig>().unwrap(),
+		"pages:2KiB,prefix=page:,policy=fifo".parse::<NamespaceConfig>().unwrap(),
+	];
+
+	let handle = spawn(ConfigBuilder::new().namespaces(&namespaces));
+
+	let mut client = connect(&handle, None);
+
+	let fields = client.send(&Command::Status).unwrap();
+
+	// the namespaces are listed by name, with six fields each
+	let [
+		..,
+		Field::U32(2),
+		Field::Buf(first_name), _, _, _, _, _,
+		Field::Buf(second_name), _, _, _, _, Field::Buf(second_policy),
+	] = &fields[..] else {
+		panic!("expected the status of two namespaces");
+	};
+
+	assert_eq!(*first_name, buffer("pages"));
+	assert_eq!(*second_name, buffer("sessions"));
+	assert_eq!(*second_policy, buffer("lru"));
+
+	handle.shutdown();
+}
+
+#[test]
+fn it_closes_connections_on_shutdown() {
+	let handle = spawn(ConfigBuilder::new());
+	let address = handle.local_addr().to_string();
+	let mut client = connect(&handle, None);
+
+	assert!(is_ok(&client.send(&Command::Ping).unwrap()));
+
+	handle.shutdown();
+
+	assert!(client.send(&Command::Ping).is_err());
+	assert!(NodeClient::connect(&address, None).is_err());
+}